@@ -1,14 +1,16 @@
-pub fn build_content(content: String) -> Vec<String> {
-    let mut code_lines: Vec<String> = Vec::new();
+use crate::program::VmCommand;
 
-    for line in content.lines() {
+pub fn build_content(content: String) -> Vec<VmCommand> {
+    let mut code_lines: Vec<VmCommand> = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
         let line = clean_line(line);
 
-        if line.len() == 0 {
+        if line.is_empty() {
             continue;
         }
 
-        code_lines.push(String::from(line));
+        code_lines.push(VmCommand::parse(&line, index + 1));
     }
 
     code_lines
@@ -17,5 +19,5 @@ pub fn build_content(content: String) -> Vec<String> {
 fn clean_line(line: &str) -> String {
     let line: Vec<&str> = line.split('/').collect();
     let line = line[0];
-    String::from(line.trim())
+    line.trim().to_string()
 }
@@ -0,0 +1,283 @@
+use crate::codegen::{CodeGenerator, Statics};
+use crate::program::mangle;
+
+pub struct CGenerator {
+    result: Vec<String>,
+    filename: String,
+    function_name: String,
+    statics: Statics,
+    return_points: usize,
+}
+
+impl CGenerator {
+    pub fn new() -> CGenerator {
+        CGenerator {
+            result: Vec::new(),
+            filename: String::new(),
+            function_name: String::new(),
+            statics: Statics::default(),
+            return_points: 0,
+        }
+    }
+
+    fn line(&mut self, value: &str) {
+        self.result.push(format!("    {}", value));
+    }
+
+    fn segment_address(&mut self, segment: &str, index: &str) -> String {
+        match segment {
+            "local" => format!("wrap(LCL + {})", index),
+            "argument" => format!("wrap(ARG + {})", index),
+            "this" => format!("wrap(THIS + {})", index),
+            "that" => format!("wrap(THAT + {})", index),
+            "temp" => (5 + index.parse::<usize>().expect("Invalid temp index")).to_string(),
+            "pointer" => (3 + index.parse::<usize>().expect("Invalid pointer index")).to_string(),
+            "static" => {
+                let address = self.statics.address(&self.filename, index);
+                address.to_string()
+            }
+            _ => panic!("Invalid memory location! {}", segment),
+        }
+    }
+
+    fn build_label(&self, label: &str) -> String {
+        format!("L_{}_{}", mangle(&self.function_name), mangle(label))
+    }
+}
+
+impl CodeGenerator for CGenerator {
+    fn extension(&self) -> &'static str {
+        "c"
+    }
+
+    fn prologue(&mut self) {
+        let header = [
+            "/* Generated by hack_vm_translator: Hack VM program as portable C.",
+            " * usage: ./program [max-steps] [address=value ...]",
+            " * prints every non-zero RAM word as `address value` once the program stops. */",
+            "#include <stdint.h>",
+            "#include <stdio.h>",
+            "#include <stdlib.h>",
+            "#include <string.h>",
+            "",
+            "static int16_t RAM[32768];",
+            "",
+            "#define MEM(address) RAM[(uint16_t)(address) & 0x7FFF]",
+            "#define SP RAM[0]",
+            "#define LCL RAM[1]",
+            "#define ARG RAM[2]",
+            "#define THIS RAM[3]",
+            "#define THAT RAM[4]",
+            "#define TICK if (limit && ++steps > limit) return",
+            "",
+            "/* 16-bit two's complement wraparound, like the Hack ALU */",
+            "static int16_t wrap(int32_t value) {",
+            "    value &= 0xFFFF;",
+            "    return (int16_t)(value >= 0x8000 ? value - 0x10000 : value);",
+            "}",
+            "",
+            "static void push(int16_t value) {",
+            "    MEM(SP) = value;",
+            "    SP = wrap(SP + 1);",
+            "}",
+            "",
+            "static int16_t pop(void) {",
+            "    SP = wrap(SP - 1);",
+            "    return MEM(SP);",
+            "}",
+            "",
+            "static void run(long limit) {",
+            "    long steps = 0;",
+            "    int16_t x, y, address;",
+            "    (void)x; (void)y; (void)address; (void)steps;",
+        ];
+
+        self.result
+            .extend(header.iter().map(|line| line.to_string()));
+    }
+
+    fn bootstrap(&mut self) {
+        self.comment("bootstrap");
+        self.line("SP = 256;");
+        self.call("Sys.init", "0");
+    }
+
+    fn file(&mut self, filename: &str) {
+        self.filename = String::from(filename);
+        self.result
+            .push(format!("    /* New file: {} */", filename));
+    }
+
+    fn comment(&mut self, value: &str) {
+        self.line(&format!("/* {} */", value));
+    }
+
+    fn epilogue(&mut self) -> Vec<String> {
+        self.line("return;");
+        self.result.push(String::from("dispatch:"));
+        self.line("switch (RAM[14]) {");
+
+        for point in 1..=self.return_points {
+            self.line(&format!("case {}: goto RET_{};", point, point));
+        }
+
+        self.line("default: return;");
+        self.line("}");
+        self.result.push(String::from("}"));
+
+        let footer = [
+            "",
+            "int main(int argc, char **argv) {",
+            "    long limit = 0;",
+            "    int i;",
+            "    for (i = 1; i < argc; i++) {",
+            "        char *equals = strchr(argv[i], '=');",
+            "        if (equals) MEM(atoi(argv[i])) = wrap(atoi(equals + 1));",
+            "        else limit = atol(argv[i]);",
+            "    }",
+            "    run(limit);",
+            "    for (i = 0; i < 32768; i++) {",
+            "        if (RAM[i]) printf(\"%d %d\\n\", i, RAM[i]);",
+            "    }",
+            "    return 0;",
+            "}",
+        ];
+
+        self.result
+            .extend(footer.iter().map(|line| line.to_string()));
+
+        std::mem::take(&mut self.result)
+    }
+
+    fn push(&mut self, segment: &str, index: &str) {
+        if segment == "constant" {
+            self.line(&format!("push({});", index));
+            return;
+        }
+
+        let address = self.segment_address(segment, index);
+        self.line(&format!("push(MEM({}));", address));
+    }
+
+    fn pop(&mut self, segment: &str, index: &str) {
+        let address = self.segment_address(segment, index);
+        self.line(&format!("address = {};", address));
+        self.line("MEM(address) = pop();");
+    }
+
+    // comparisons use the wrapped difference, exactly like D=M-D on Hack; shifting a
+    // negative value is avoided since C leaves it undefined or implementation-defined
+    fn arithmetic(&mut self, operation: &str) {
+        let expression = match operation {
+            "neg" => "wrap(-x)",
+            "not" => "wrap(~x)",
+            "add" => "wrap(x + y)",
+            "sub" => "wrap(x - y)",
+            "and" => "(int16_t)(x & y)",
+            "or" => "(int16_t)(x | y)",
+            "eq" => "(int16_t)(wrap(x - y) == 0 ? -1 : 0)",
+            "gt" => "(int16_t)(wrap(x - y) > 0 ? -1 : 0)",
+            "lt" => "(int16_t)(wrap(x - y) < 0 ? -1 : 0)",
+            "mul" => "wrap((int32_t)x * y)",
+            "div" => "(int16_t)(y == 0 ? 0 : wrap((int32_t)x / y))",
+            "mod" => "(int16_t)(y == 0 ? x : wrap((int32_t)x % y))",
+            "shl" => "wrap((int32_t)((uint32_t)(uint16_t)x << (y & 15)))",
+            "shr" => "(int16_t)(x < 0 ? ~(~x >> (y & 15)) : x >> (y & 15))",
+            "xor" => "(int16_t)(x ^ y)",
+            _ => panic!("Invalid action required! {}", operation),
+        };
+
+        if operation == "neg" || operation == "not" {
+            self.line("x = pop();");
+        } else {
+            self.line("y = pop();");
+            self.line("x = pop();");
+        }
+
+        self.line(&format!("push({});", expression));
+    }
+
+    fn label(&mut self, label: &str) {
+        self.result.push(format!("{}:", self.build_label(label)));
+        self.line("TICK;");
+    }
+
+    fn goto(&mut self, label: &str) {
+        let target = self.build_label(label);
+        self.line(&format!("goto {};", target));
+    }
+
+    fn if_goto(&mut self, label: &str) {
+        let target = self.build_label(label);
+        self.line(&format!("if (pop() != 0) goto {};", target));
+    }
+
+    fn function(&mut self, name: &str, locals: &str) {
+        self.function_name = String::from(name);
+        self.result.push(format!("F_{}:", mangle(name)));
+        self.line("TICK;");
+
+        for _ in 0..locals.parse::<usize>().expect("Invalid number of locals") {
+            self.line("push(0);");
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: &str) {
+        self.return_points += 1;
+        let point = self.return_points;
+
+        self.line(&format!("push({});", point));
+        self.line("push(LCL);");
+        self.line("push(ARG);");
+        self.line("push(THIS);");
+        self.line("push(THAT);");
+        self.line(&format!("ARG = wrap(SP - {} - 5);", arguments));
+        self.line("LCL = SP;");
+        self.line(&format!("goto F_{};", mangle(name)));
+        self.result.push(format!("RET_{}:", point));
+        self.line(";");
+    }
+
+    fn return_(&mut self) {
+        self.line("RAM[13] = pop();");
+        self.line("RAM[15] = ARG;");
+        self.line("SP = LCL;");
+        self.line("THAT = pop();");
+        self.line("THIS = pop();");
+        self.line("ARG = pop();");
+        self.line("LCL = pop();");
+        self.line("RAM[14] = pop();");
+        self.line("SP = RAM[15];");
+        self.line("push(RAM[13]);");
+        self.line("goto dispatch;");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::generate;
+    use crate::test_programs::{run_native, PROGRAMS};
+
+    #[test]
+    fn compiled_test_programs_leave_the_expected_ram() {
+        for program in PROGRAMS {
+            let output = generate(&mut CGenerator::new(), &program.vm_files(), true);
+
+            let ram = match run_native("cc", program.name, "c", &output) {
+                Some(ram) => ram,
+                None => return eprintln!("no C compiler, skipping the compiled test programs"),
+            };
+
+            for (address, expected) in program.expected {
+                assert_eq!(
+                    ram.get(address).copied().unwrap_or(0),
+                    *expected,
+                    "RAM[{}] after {}",
+                    address,
+                    program.name
+                );
+            }
+        }
+    }
+}
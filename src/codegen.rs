@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use crate::c_backend::CGenerator;
+use crate::options::Options;
+use crate::parser::HackGenerator;
+use crate::program::{VmCommand, VmFile};
+use crate::riscv_backend::RiscVGenerator;
+use crate::wat_backend::WatGenerator;
+use crate::x86_backend::X86Generator;
+
+// one method per VM command kind; segments, indexes and counts are passed as written in the source
+pub trait CodeGenerator {
+    // file extension of the generated output
+    fn extension(&self) -> &'static str;
+
+    fn prologue(&mut self) {}
+
+    // sets SP to 256 and calls Sys.init, for programs translated from a folder
+    fn bootstrap(&mut self);
+
+    fn file(&mut self, filename: &str);
+    fn comment(&mut self, value: &str);
+
+    fn push(&mut self, segment: &str, index: &str);
+    fn pop(&mut self, segment: &str, index: &str);
+    fn arithmetic(&mut self, operation: &str);
+
+    fn label(&mut self, label: &str);
+    fn goto(&mut self, label: &str);
+    fn if_goto(&mut self, label: &str);
+
+    fn function(&mut self, name: &str, locals: &str);
+
+    // Every target keeps the frame of the course: the return address, LCL, ARG, THIS and
+    // THAT pushed in that order, then ARG = SP - arguments - 5 and LCL = SP. Targets
+    // other than Hack push a return point number as the return address.
+    fn call(&mut self, name: &str, arguments: &str);
+
+    // Puts the result at ARG[0], restores the caller's pointers from the frame and
+    // leaves SP right after the result. On Hack the result, the return address and the
+    // caller's SP pass through R13, R14 and R15; the C, WAT and x86 targets use them the
+    // same way, so those words match too.
+    fn return_(&mut self);
+
+    // `call` directly followed by `return`; generators that can reuse the frame override it
+    fn tail_call(&mut self, name: &str, arguments: &str) {
+        self.call(name, arguments);
+        self.return_();
+    }
+
+    fn epilogue(&mut self) -> Vec<String>;
+
+    // how much code `output` takes, leaving out comments, labels and directives
+    fn code_size(&self, output: &[String]) -> usize {
+        output
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| {
+                !line.is_empty()
+                    && !line.ends_with(':')
+                    && !["/*", "*", "#", ";;", "."]
+                        .iter()
+                        .any(|prefix| line.starts_with(prefix))
+            })
+            .count()
+    }
+
+    // what code_size counts
+    fn size_unit(&self) -> &'static str {
+        "instructions"
+    }
+}
+
+// Statics of the targets other than Hack are numbered from RAM[16] in the order they
+// are first used. That is not the Hack layout: the Hack assembler gives RAM[16] on to
+// every variable symbol in first-use order, the translator's scratch variables
+// (Fn$tmp, __vm.*) included, so the same static can sit higher there. Saved return
+// addresses differ as well, ROM addresses in Hack and return point numbers elsewhere.
+// A program's results therefore match its Hack build on the stack, the segments and
+// the heap, but not on static addresses or saved frames.
+const STATIC_BASE: usize = 16;
+
+#[derive(Default)]
+pub struct Statics {
+    addresses: HashMap<String, usize>,
+}
+
+impl Statics {
+    pub fn address(&mut self, filename: &str, index: &str) -> usize {
+        let next = STATIC_BASE + self.addresses.len();
+
+        *self
+            .addresses
+            .entry(format!("{}.{}", filename, index))
+            .or_insert(next)
+    }
+}
+
+pub fn build_generator(options: &Options) -> Box<dyn CodeGenerator> {
+    match options.target.as_str() {
+        "hack" => Box::new(HackGenerator::new(
+            options.prologue_threshold,
+            options.cache_top,
+        )),
+        "c" => Box::new(CGenerator::new()),
+        "wat" => Box::new(WatGenerator::new()),
+        "x86" => Box::new(X86Generator::new()),
+        "riscv" => Box::new(RiscVGenerator::new()),
+        target => panic!("Unknown target {}", target),
+    }
+}
+
+pub fn generate(
+    generator: &mut dyn CodeGenerator,
+    files: &[VmFile],
+    bootstrap: bool,
+) -> Vec<String> {
+    generator.prologue();
+
+    if bootstrap {
+        generator.bootstrap();
+    }
+
+    for file in files {
+        translate_file(generator, &file.name, &file.commands);
+    }
+
+    generator.epilogue()
+}
+
+pub fn translate_file(generator: &mut dyn CodeGenerator, filename: &str, commands: &[VmCommand]) {
+    generator.file(filename);
+
+    for command in commands {
+        generator.comment(&command.to_string());
+        translate_command(generator, command);
+    }
+}
+
+pub fn translate_command(generator: &mut dyn CodeGenerator, command: &VmCommand) {
+    match command.command.as_str() {
+        "push" => generator.push(&command.detail, &command.value),
+        "pop" => generator.pop(&command.detail, &command.value),
+        "label" => generator.label(&command.detail),
+        "goto" => generator.goto(&command.detail),
+        "if-goto" => generator.if_goto(&command.detail),
+        "function" => generator.function(&command.detail, &command.value),
+        "call" => generator.call(&command.detail, &command.value),
+        "return" => generator.return_(),
+        "tail-call" => generator.tail_call(&command.detail, &command.value),
+        operation => generator.arithmetic(operation),
+    }
+}
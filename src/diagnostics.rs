@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::json;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    // filled in from the source by locate_spans
+    pub span: Option<Box<Span>>,
+}
+
+// 1-based columns, the end one past the last character, like cargo
+pub struct Span {
+    pub column_start: usize,
+    pub column_end: usize,
+    pub text: String,
+}
+
+// replacement for the text of the span
+pub struct Suggestion {
+    pub message: String,
+    pub replacement: String,
+}
+
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub location: Option<Location>,
+    pub notes: Vec<String>,
+    pub suggestion: Option<Box<Suggestion>>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: String) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, message)
+    }
+
+    fn new(severity: Severity, code: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            location: None,
+            notes: Vec::new(),
+            suggestion: None,
+        }
+    }
+
+    pub fn at(mut self, file: &str, line: usize) -> Diagnostic {
+        self.location = Some(Location {
+            file: String::from(file),
+            line,
+            span: None,
+        });
+        self
+    }
+
+    pub fn note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+
+    // `replacement` is the whole command the line should hold instead
+    pub fn suggest(mut self, message: String, replacement: String) -> Diagnostic {
+        self.suggestion = Some(Box::new(Suggestion {
+            message,
+            replacement,
+        }));
+        self
+    }
+}
+
+impl Diagnostic {
+    fn severity_name(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    // One object in the spirit of cargo's JSON messages: the fields of the text form,
+    // the span of the line when the source could be read, the suggested fix and the
+    // rendered text. file, line and column are null for program-wide diagnostics.
+    pub fn to_json(&self) -> String {
+        let null = || String::from("null");
+
+        let (file, line, column, span) = match &self.location {
+            Some(location) => (
+                json::string(&location.file),
+                location.line.to_string(),
+                location
+                    .span
+                    .as_ref()
+                    .map_or_else(null, |span| span.column_start.to_string()),
+                location.span.as_ref().map_or_else(null, |span| {
+                    json::object(&[
+                        ("file_name", json::string(&location.file)),
+                        ("line_start", location.line.to_string()),
+                        ("line_end", location.line.to_string()),
+                        ("column_start", span.column_start.to_string()),
+                        ("column_end", span.column_end.to_string()),
+                        ("text", json::string(&span.text)),
+                    ])
+                }),
+            ),
+            None => (null(), null(), null(), null()),
+        };
+
+        let notes: Vec<String> = self.notes.iter().map(|note| json::string(note)).collect();
+
+        let suggestion = self.suggestion.as_ref().map_or_else(null, |suggestion| {
+            json::object(&[
+                ("message", json::string(&suggestion.message)),
+                ("replacement", json::string(&suggestion.replacement)),
+            ])
+        });
+
+        json::object(&[
+            ("$message_type", json::string("diagnostic")),
+            ("severity", json::string(self.severity_name())),
+            ("code", json::string(self.code)),
+            ("message", json::string(&self.message)),
+            ("file", file),
+            ("line", line),
+            ("column", column),
+            ("span", span),
+            ("notes", json::array(&notes)),
+            ("suggestion", suggestion),
+            ("rendered", json::string(&self.to_string())),
+        ])
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {}",
+            self.severity_name(),
+            self.code,
+            self.message
+        )?;
+
+        if let Some(location) = &self.location {
+            write!(f, "\n  --> {}:{}", location.file, location.line)?;
+
+            if let Some(span) = &location.span {
+                write!(f, ":{}", span.column_start)?;
+            }
+        }
+
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n  = help: {}", suggestion.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+// prints every diagnostic to stderr and tells whether any of them is an error
+pub fn report(diagnostics: &[Diagnostic]) -> bool {
+    for diagnostic in diagnostics {
+        eprintln!("{}\n", diagnostic);
+    }
+
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+// one JSON object per line on stdout, for tools reading the diagnostics
+pub fn report_json(diagnostics: &[Diagnostic]) -> bool {
+    for diagnostic in diagnostics {
+        println!("{}", diagnostic.to_json());
+    }
+
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+// Points every located diagnostic at the code on its line, without the indentation
+// and the comment. `source` gives the text of a file, when it can be read.
+pub fn locate_spans(diagnostics: &mut [Diagnostic], source: &dyn Fn(&str) -> Option<String>) {
+    let mut sources: HashMap<String, Option<String>> = HashMap::new();
+
+    for location in diagnostics
+        .iter_mut()
+        .filter_map(|diagnostic| diagnostic.location.as_mut())
+    {
+        let text = sources
+            .entry(location.file.clone())
+            .or_insert_with(|| source(&location.file));
+
+        let line = match text
+            .as_ref()
+            .and_then(|text| text.lines().nth(location.line - 1))
+        {
+            Some(line) => line,
+            None => continue,
+        };
+
+        let code = line.split("//").next().unwrap_or("");
+        let indent = code.len() - code.trim_start().len();
+        let code = code.trim();
+
+        if code.is_empty() {
+            continue;
+        }
+
+        let column_start = line[..indent].chars().count() + 1;
+
+        location.span = Some(Box::new(Span {
+            column_start,
+            column_end: column_start + code.chars().count(),
+            text: code.to_string(),
+        }));
+    }
+}
+
+// the candidate within two edits of `word`, for "did you mean" suggestions
+pub fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance > 0 && *distance <= 2 && *distance < word.len())
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+
+    for (i, left_character) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, right_character) in right.iter().enumerate() {
+            let substitution = previous[j] + (left_character != *right_character) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[right.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{parse, Value};
+
+    fn field<'a>(value: &'a Value, name: &str) -> &'a str {
+        value.get(name).and_then(Value::as_str).unwrap()
+    }
+
+    #[test]
+    fn a_program_wide_diagnostic_has_null_locations() {
+        let diagnostic = Diagnostic::error("undefined-function", String::from("no Sys.init"));
+
+        assert_eq!(
+            diagnostic.to_json(),
+            "{\"$message_type\":\"diagnostic\",\"severity\":\"error\",\"code\":\"undefined-function\",\"message\":\"no Sys.init\",\"file\":null,\"line\":null,\"column\":null,\"span\":null,\"notes\":[],\"suggestion\":null,\"rendered\":\"error[undefined-function]: no Sys.init\"}"
+        );
+    }
+
+    #[test]
+    fn quotes_backslashes_and_newlines_survive_the_round_trip() {
+        let file = "C:\\vm\\\"odd\" name.vm";
+        let message = String::from("`push \"x\"` is\nnot valid");
+        let mut diagnostics = [Diagnostic::warning("syntax", message.clone())
+            .at(file, 2)
+            .note(String::from("a \\ note"))
+            .suggest(
+                String::from("use \"this\""),
+                String::from("push constant 1"),
+            )];
+
+        locate_spans(&mut diagnostics, &|_| {
+            Some(String::from("function Main.f 0\n  push \"x\" // here\n"))
+        });
+
+        let json = diagnostics[0].to_json();
+        assert!(!json.contains('\n'));
+
+        let value = parse(&json).unwrap();
+        assert_eq!(field(&value, "message"), message);
+        assert_eq!(field(&value, "file"), file);
+        assert_eq!(value.at("line").and_then(Value::as_usize), Some(2));
+        assert_eq!(value.at("column").and_then(Value::as_usize), Some(3));
+        assert_eq!(field(value.at("span").unwrap(), "file_name"), file);
+        assert_eq!(field(value.at("span").unwrap(), "text"), "push \"x\"");
+        assert_eq!(
+            value.at("span.column_end").and_then(Value::as_usize),
+            Some(11)
+        );
+        assert_eq!(
+            value.at("notes").and_then(Value::as_array).unwrap()[0].as_str(),
+            Some("a \\ note")
+        );
+        assert_eq!(
+            field(value.at("suggestion").unwrap(), "message"),
+            "use \"this\""
+        );
+        assert_eq!(field(&value, "rendered"), diagnostics[0].to_string());
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::diagnostics::Diagnostic;
+use crate::hack::{is_symbol, Instruction, Line};
+
+const VARIABLE_BASE: u16 = 16;
+
+const PREDEFINED: [(&str, u16); 7] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+// ROM labels and RAM variables by address
+#[derive(Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, Vec<String>>,
+    variables: HashMap<u16, String>,
+}
+
+impl Symbols {
+    // `(LOOP) 12` names a ROM address and `counter 16` a RAM one
+    pub fn from_symbol_file(content: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let entry = match parts.as_slice() {
+                [name, address] => address.parse::<u16>().ok().map(|address| (*name, address)),
+                _ => None,
+            };
+
+            let (name, address) =
+                entry.ok_or_else(|| format!("line {} is not `name address`", index + 1))?;
+
+            match name
+                .strip_prefix('(')
+                .and_then(|name| name.strip_suffix(')'))
+            {
+                Some(label) if is_symbol(label) => symbols.add_label(label, address),
+                _ if is_symbol(name) => symbols.add_variable(name, address),
+                _ => return Err(format!("`{}` on line {} is not a symbol", name, index + 1)),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    // the .asm the binary was assembled from, resolved the way the assembler does:
+    // labels first, then every other symbol as a variable from RAM[16] on
+    pub fn from_source(content: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        let mut references: Vec<String> = Vec::new();
+        let mut address: u16 = 0;
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(label) = line
+                .strip_prefix('(')
+                .and_then(|line| line.strip_suffix(')'))
+            {
+                symbols.add_label(label, address);
+                continue;
+            }
+
+            match Instruction::parse(line) {
+                Ok(Instruction::Symbol(name)) => references.push(name),
+                Ok(_) => (),
+                Err(message) => return Err(format!("line {}: {}", index + 1, message)),
+            }
+
+            address += 1;
+        }
+
+        let labels: BTreeSet<String> = symbols.labels.values().flatten().cloned().collect();
+        let mut next = VARIABLE_BASE;
+
+        for name in &references {
+            let known = labels.contains(name)
+                || symbols.variables.values().any(|variable| variable == name)
+                || is_predefined(name);
+
+            if !known {
+                symbols.add_variable(name, next);
+                next += 1;
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    fn add_label(&mut self, name: &str, address: u16) {
+        self.labels
+            .entry(address)
+            .or_default()
+            .push(String::from(name));
+    }
+
+    fn add_variable(&mut self, name: &str, address: u16) {
+        self.variables
+            .entry(address)
+            .or_insert_with(|| String::from(name));
+    }
+}
+
+// Turns a .hack file back into assembly. An address loaded right before a jump becomes
+// a label, named by the symbols or after its ROM address, and one loaded right before
+// an instruction that touches RAM[A] gets its variable or predefined name. Words that
+// are not instructions are reported and left in the output as comments.
+pub fn disassemble(file: &str, content: &str, symbols: &Symbols) -> (Vec<String>, Vec<Diagnostic>) {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut words: Vec<(String, Option<Instruction>)> = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let text = line.trim();
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let decoded = if text.len() == 16 && text.chars().all(|bit| bit == '0' || bit == '1') {
+            Instruction::decode(u16::from_str_radix(text, 2).unwrap())
+        } else {
+            Err(format!("`{}` is not a 16-bit binary word", text))
+        };
+
+        match decoded {
+            Ok(instruction) => words.push((String::from(text), Some(instruction))),
+            Err(message) => {
+                diagnostics.push(Diagnostic::error("invalid-word", message).at(file, index + 1));
+                words.push((String::from(text), None));
+            }
+        }
+    }
+
+    let next = |address: usize| words.get(address + 1).and_then(|(_, next)| next.as_ref());
+
+    let mut labels = symbols.labels.clone();
+
+    for (address, (_, instruction)) in words.iter().enumerate() {
+        if let Some(Instruction::Address(target)) = instruction {
+            if next(address).map(Instruction::jumps) == Some(true)
+                && *target as usize <= words.len()
+            {
+                labels
+                    .entry(*target)
+                    .or_insert_with(|| vec![format!("ROM.{}", target)]);
+            }
+        }
+    }
+
+    let mut result = vec![Line::Comment(format!("Disassembled from {}", file))];
+
+    for (address, (text, instruction)) in words.iter().enumerate() {
+        for label in labels.get(&(address as u16)).into_iter().flatten() {
+            result.push(Line::Label(label.clone()));
+        }
+
+        let instruction = match instruction {
+            Some(instruction) => instruction,
+            None => {
+                result.push(Line::Comment(format!("invalid: {}", text)));
+                continue;
+            }
+        };
+
+        let name = match (instruction, next(address)) {
+            (Instruction::Address(target), Some(next)) if next.jumps() => {
+                labels.get(target).map(|names| names[0].clone())
+            }
+            (Instruction::Address(target), Some(next)) if next.uses_memory() => symbols
+                .variables
+                .get(target)
+                .cloned()
+                .or_else(|| predefined_name(*target)),
+            _ => None,
+        };
+
+        result.push(Line::Instruction(match name {
+            Some(name) => Instruction::Symbol(name),
+            None => instruction.clone(),
+        }));
+    }
+
+    // a jump can target the address right after the last word
+    let end = words.len() as u16;
+    for label in labels.range(end..).flat_map(|(_, names)| names) {
+        result.push(Line::Label(label.clone()));
+    }
+
+    (
+        result.iter().map(|line| line.to_string()).collect(),
+        diagnostics,
+    )
+}
+
+fn predefined_name(address: u16) -> Option<String> {
+    match PREDEFINED.iter().find(|(_, value)| *value == address) {
+        Some((name, _)) => Some(String::from(*name)),
+        None if address < VARIABLE_BASE => Some(format!("R{}", address)),
+        None => None,
+    }
+}
+
+fn is_predefined(name: &str) -> bool {
+    PREDEFINED.iter().any(|(predefined, _)| *predefined == name)
+        || name
+            .strip_prefix('R')
+            .and_then(|register| register.parse::<u16>().ok())
+            .map(|register| register < VARIABLE_BASE)
+            == Some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the a bit and c1..c6 of every computation, as the Hack specification lists them
+    const COMPUTATIONS: [(&str, &str); 28] = [
+        ("0", "0101010"),
+        ("1", "0111111"),
+        ("-1", "0111010"),
+        ("D", "0001100"),
+        ("A", "0110000"),
+        ("!D", "0001101"),
+        ("!A", "0110001"),
+        ("-D", "0001111"),
+        ("-A", "0110011"),
+        ("D+1", "0011111"),
+        ("A+1", "0110111"),
+        ("D-1", "0001110"),
+        ("A-1", "0110010"),
+        ("D+A", "0000010"),
+        ("D-A", "0010011"),
+        ("A-D", "0000111"),
+        ("D&A", "0000000"),
+        ("D|A", "0010101"),
+        ("M", "1110000"),
+        ("!M", "1110001"),
+        ("-M", "1110011"),
+        ("M+1", "1110111"),
+        ("M-1", "1110010"),
+        ("D+M", "1000010"),
+        ("D-M", "1010011"),
+        ("M-D", "1000111"),
+        ("D&M", "1000000"),
+        ("D|M", "1010101"),
+    ];
+
+    const DESTINATIONS: [&str; 8] = ["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+    const JUMPS: [&str; 8] = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+    // a jump to 3, a store to RAM[16] at 3 and SP++
+    const PROGRAM: &str = "0000000000000011\n1110101010000111\n0000000000010000\n1110001100001000\n0000000000000000\n1111110111001000\n";
+
+    #[test]
+    fn every_computation_destination_and_jump_decodes() {
+        let mut words = String::new();
+        let mut expected = vec![String::from("// Disassembled from Prog.hack")];
+
+        for (comp, bits) in COMPUTATIONS.iter() {
+            for (dest, dest_name) in DESTINATIONS.iter().enumerate() {
+                for (jump, jump_name) in JUMPS.iter().enumerate() {
+                    words.push_str(&format!("111{}{:03b}{:03b}\n", bits, dest, jump));
+
+                    let mut text = String::new();
+                    if !dest_name.is_empty() {
+                        text.push_str(&format!("{}=", dest_name));
+                    }
+                    text.push_str(comp);
+                    if !jump_name.is_empty() {
+                        text.push_str(&format!(";{}", jump_name));
+                    }
+                    expected.push(text);
+                }
+            }
+        }
+
+        let (result, diagnostics) = disassemble("Prog.hack", &words, &Symbols::default());
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn illegal_words_are_reported_and_kept_as_comments() {
+        let content = "1110000001000000\n1000101010000111\n0000000000000101\n";
+        let (result, diagnostics) = disassemble("Prog.hack", content, &Symbols::default());
+
+        assert_eq!(
+            result,
+            [
+                "// Disassembled from Prog.hack",
+                "// invalid: 1110000001000000",
+                "// invalid: 1000101010000111",
+                "@5"
+            ]
+        );
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].message.contains("illegal comp bits 0000001"));
+        assert!(diagnostics[1]
+            .message
+            .contains("does not set bits 13 and 14"));
+        assert_eq!(diagnostics[1].location.as_ref().unwrap().line, 2);
+    }
+
+    #[test]
+    fn jump_targets_and_memory_addresses_become_symbols() {
+        let (result, _) = disassemble("Prog.hack", PROGRAM, &Symbols::default());
+
+        assert_eq!(
+            result,
+            [
+                "// Disassembled from Prog.hack",
+                "@ROM.3",
+                "0;JMP",
+                "@16",
+                "(ROM.3)",
+                "M=D",
+                "@SP",
+                "M=M+1"
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_and_variables_come_from_the_symbols() {
+        let expected = [
+            "// Disassembled from Prog.hack",
+            "@LOOP",
+            "0;JMP",
+            "@counter",
+            "(LOOP)",
+            "M=D",
+            "@SP",
+            "M=M+1",
+        ];
+
+        let from_file = Symbols::from_symbol_file("(LOOP) 3\ncounter 16\n").unwrap();
+        assert_eq!(disassemble("Prog.hack", PROGRAM, &from_file).0, expected);
+
+        let source = "@LOOP\n0;JMP\n@counter\n(LOOP)\nM=D // store\n@SP\nM=M+1\n";
+        let from_source = Symbols::from_source(source).unwrap();
+        assert_eq!(disassemble("Prog.hack", PROGRAM, &from_source).0, expected);
+    }
+}
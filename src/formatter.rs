@@ -0,0 +1,182 @@
+use crate::diagnostics::Diagnostic;
+use crate::program::VmCommand;
+
+const BODY_INDENT: &str = "    ";
+const LABEL_INDENT: &str = "  ";
+
+enum Line {
+    Command(VmCommand, Option<String>),
+    Comment(String),
+}
+
+// Canonical text of a .vm source: one space between words, function bodies indented
+// with labels half way out and a blank line before every function. The file keeps its
+// line endings unless it mixes them, which becomes CRLF. Comments are kept unless
+// stripped; a comment on its own line goes with the command after it.
+pub fn format_source(file: &str, content: &str, keep_comments: bool) -> Result<String, Diagnostic> {
+    let mut lines: Vec<Line> = Vec::new();
+
+    for (index, text) in content.lines().enumerate() {
+        // same comment rule as build_content: everything from the first slash
+        let (code, comment) = match text.find('/') {
+            Some(position) => (&text[..position], Some(text[position..].trim_end())),
+            None => (text, None),
+        };
+        let comment = comment.filter(|_| keep_comments).map(String::from);
+
+        if code.trim().is_empty() {
+            if let Some(comment) = comment {
+                lines.push(Line::Comment(comment));
+            }
+            continue;
+        }
+
+        // the parser ignores anything after the third word, formatting would lose it
+        if code.split_whitespace().count() > 3 {
+            return Err(Diagnostic::error(
+                "malformed-command",
+                format!("`{}` has more than three words", code.trim()),
+            )
+            .at(file, index + 1));
+        }
+
+        lines.push(Line::Command(VmCommand::parse(code, index + 1), comment));
+    }
+
+    Ok(render(lines, line_ending(content)))
+}
+
+// text form of commands that come without comments, like the files of a .vmb bundle
+pub fn format_commands(commands: &[VmCommand]) -> String {
+    render(
+        commands
+            .iter()
+            .map(|command| Line::Command(command.clone(), None))
+            .collect(),
+        "\r\n",
+    )
+}
+
+// LF only when every line ends in a bare LF, CRLF otherwise
+fn line_ending(content: &str) -> &'static str {
+    if content.contains('\n') && !content.contains("\r\n") {
+        "\n"
+    } else {
+        "\r\n"
+    }
+}
+
+// the line of the first difference between a source and its canonical form
+pub fn first_difference(content: &str, formatted: &str) -> Option<usize> {
+    if content == formatted {
+        return None;
+    }
+
+    let mut original = content.split('\n');
+    let mut canonical = formatted.split('\n');
+    let mut line = 1;
+
+    while original.next() == canonical.next() {
+        line += 1;
+    }
+
+    Some(line)
+}
+
+fn render(lines: Vec<Line>, line_ending: &str) -> String {
+    let mut result: Vec<String> = Vec::new();
+    let mut pending_comments: Vec<String> = Vec::new();
+    let mut in_function = false;
+
+    for line in lines {
+        let (command, comment) = match line {
+            Line::Comment(comment) => {
+                pending_comments.push(comment);
+                continue;
+            }
+            Line::Command(command, comment) => (command, comment),
+        };
+
+        let indent = match command.command.as_str() {
+            "function" => {
+                if !result.is_empty() {
+                    result.push(String::new());
+                }
+                in_function = true;
+                ""
+            }
+            _ if !in_function => "",
+            "label" => LABEL_INDENT,
+            _ => BODY_INDENT,
+        };
+
+        for comment in pending_comments.drain(..) {
+            result.push(format!("{}{}", indent, comment));
+        }
+
+        match comment {
+            Some(comment) => result.push(format!("{}{} {}", indent, command, comment)),
+            None => result.push(format!("{}{}", indent, command)),
+        }
+    }
+
+    let indent = if in_function { BODY_INDENT } else { "" };
+
+    for comment in pending_comments {
+        result.push(format!("{}{}", indent, comment));
+    }
+
+    result
+        .iter()
+        .map(|line| format!("{}{}", line, line_ending))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str =
+        "// entry\nfunction Main.main 0\npush   constant 1 // one\nlabel L\nreturn\n";
+
+    fn format(content: &str, keep_comments: bool) -> String {
+        format_source("Main.vm", content, keep_comments)
+            .unwrap_or_else(|_| panic!("{} does not format", content))
+    }
+
+    #[test]
+    fn comments_are_kept_unless_stripped() {
+        assert_eq!(
+            format(SOURCE, true),
+            "// entry\nfunction Main.main 0\n    push constant 1 // one\n  label L\n    return\n"
+        );
+        assert_eq!(
+            format(SOURCE, false),
+            "function Main.main 0\n    push constant 1\n  label L\n    return\n"
+        );
+    }
+
+    #[test]
+    fn formatted_files_keep_their_line_endings() {
+        let lf = format(SOURCE, true);
+        let crlf = lf.replace('\n', "\r\n");
+
+        assert_eq!(format(&lf, true), lf);
+        assert_eq!(format(&crlf, true), crlf);
+        assert_eq!(first_difference(&lf, &format(&lf, true)), None);
+    }
+
+    #[test]
+    fn mixed_line_endings_become_crlf() {
+        let mixed = "function Main.main 0\r\n    return\n";
+
+        assert_eq!(
+            format(mixed, true),
+            "function Main.main 0\r\n    return\r\n"
+        );
+        assert_eq!(
+            first_difference(mixed, "function Main.main 0\r\n    return\r\n"),
+            Some(2)
+        );
+    }
+}
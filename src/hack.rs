@@ -0,0 +1,256 @@
+use std::fmt;
+
+pub const MAX_A_VALUE: u16 = 32767;
+
+// C-instructions start with 111; the CPU ignores bits 13 and 14 but the spec sets them
+const COMPUTE_PREFIX: u16 = 0b111 << 13;
+
+// the a bit and c1..c6 of every computation the Hack ALU documents
+const COMPUTATIONS: [(&str, u16); 28] = [
+    ("0", 0b0101010),
+    ("1", 0b0111111),
+    ("-1", 0b0111010),
+    ("D", 0b0001100),
+    ("A", 0b0110000),
+    ("!D", 0b0001101),
+    ("!A", 0b0110001),
+    ("-D", 0b0001111),
+    ("-A", 0b0110011),
+    ("D+1", 0b0011111),
+    ("A+1", 0b0110111),
+    ("D-1", 0b0001110),
+    ("A-1", 0b0110010),
+    ("D+A", 0b0000010),
+    ("D-A", 0b0010011),
+    ("A-D", 0b0000111),
+    ("D&A", 0b0000000),
+    ("D|A", 0b0010101),
+    ("M", 0b1110000),
+    ("!M", 0b1110001),
+    ("-M", 0b1110011),
+    ("M+1", 0b1110111),
+    ("M-1", 0b1110010),
+    ("D+M", 0b1000010),
+    ("D-M", 0b1010011),
+    ("M-D", 0b1000111),
+    ("D&M", 0b1000000),
+    ("D|M", 0b1010101),
+];
+
+// indexed by the d1 (A), d2 (D) and d3 (M) bits
+const DESTINATIONS: [&str; 8] = ["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+
+const JUMPS: [&str; 8] = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+#[derive(Clone, PartialEq)]
+pub enum Instruction {
+    Address(u16),
+    // left for the assembler to resolve to a label or a variable
+    Symbol(String),
+    Compute { dest: u16, comp: u16, jump: u16 },
+}
+
+// a line of assembly: an instruction, a label for the one after it or a comment
+#[derive(Clone, PartialEq)]
+pub enum Line {
+    Label(String),
+    Instruction(Instruction),
+    Comment(String),
+}
+
+impl Line {
+    pub fn parse(text: &str) -> Result<Line, String> {
+        if let Some(comment) = text.strip_prefix("//") {
+            return Ok(Line::Comment(String::from(comment.trim_start())));
+        }
+
+        match text
+            .strip_prefix('(')
+            .and_then(|text| text.strip_suffix(')'))
+        {
+            Some(label) if is_symbol(label) => Ok(Line::Label(String::from(label))),
+            Some(label) => Err(format!("`{}` is not a valid label", label)),
+            None => Instruction::parse(text).map(Line::Instruction),
+        }
+    }
+}
+
+impl Instruction {
+    // @value for a number or a symbol; numbers too big for an A-instruction are kept
+    // for check_rom_budget to report
+    pub fn address(value: &str) -> Instruction {
+        match value.parse::<u16>() {
+            Ok(number) => Instruction::Address(number),
+            Err(_) => Instruction::Symbol(String::from(value)),
+        }
+    }
+
+    // a C-instruction from its mnemonics, for code that writes fixed instructions
+    pub fn compute(dest: &str, comp: &str, jump: &str) -> Instruction {
+        Instruction::Compute {
+            dest: parse_destination(dest).expect("Invalid destination"),
+            comp: parse_computation(comp).expect("Invalid computation"),
+            jump: JUMPS
+                .iter()
+                .position(|mnemonic| *mnemonic == jump)
+                .expect("Invalid jump") as u16,
+        }
+    }
+
+    // one instruction of assembly text, without comments; labels are not instructions
+    pub fn parse(text: &str) -> Result<Instruction, String> {
+        if let Some(value) = text.strip_prefix('@') {
+            return match value.parse::<u32>() {
+                Ok(number) if number > MAX_A_VALUE as u32 => Err(format!(
+                    "constant {} does not fit an A-instruction (max {})",
+                    number, MAX_A_VALUE
+                )),
+                Ok(number) => Ok(Instruction::Address(number as u16)),
+                Err(_) if is_symbol(value) => Ok(Instruction::Symbol(String::from(value))),
+                Err(_) => Err(format!("`{}` is not a valid symbol", value)),
+            };
+        }
+
+        let (dest, rest) = match text.find('=') {
+            Some(index) => (&text[..index], &text[index + 1..]),
+            None => ("", text),
+        };
+        let (comp, jump) = match rest.find(';') {
+            Some(index) => (&rest[..index], &rest[index + 1..]),
+            None => (rest, ""),
+        };
+
+        Ok(Instruction::Compute {
+            dest: parse_destination(dest)
+                .ok_or_else(|| format!("`{}` is not a destination", dest))?,
+            comp: parse_computation(comp)
+                .ok_or_else(|| format!("`{}` is not a computation", comp))?,
+            jump: JUMPS
+                .iter()
+                .position(|mnemonic| *mnemonic == jump)
+                .ok_or_else(|| format!("`{}` is not a jump", jump))? as u16,
+        })
+    }
+
+    pub fn decode(word: u16) -> Result<Instruction, String> {
+        if word & 0x8000 == 0 {
+            return Ok(Instruction::Address(word));
+        }
+
+        if word & COMPUTE_PREFIX != COMPUTE_PREFIX {
+            return Err(format!(
+                "C-instruction {:016b} does not set bits 13 and 14",
+                word
+            ));
+        }
+
+        let comp = (word >> 6) & 0b1111111;
+
+        if !COMPUTATIONS.iter().any(|(_, bits)| *bits == comp) {
+            return Err(format!(
+                "C-instruction {:016b} has the illegal comp bits {:07b}",
+                word, comp
+            ));
+        }
+
+        Ok(Instruction::Compute {
+            dest: (word >> 3) & 0b111,
+            comp,
+            jump: word & 0b111,
+        })
+    }
+
+    pub fn jumps(&self) -> bool {
+        matches!(self, Instruction::Compute { jump, .. } if *jump != 0)
+    }
+
+    // reads or writes RAM[A]
+    pub fn uses_memory(&self) -> bool {
+        matches!(self, Instruction::Compute { dest, comp, .. } if comp & 0b1000000 != 0 || dest & 0b001 != 0)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Address(value) => write!(f, "@{}", value),
+            Instruction::Symbol(name) => write!(f, "@{}", name),
+            Instruction::Compute { dest, comp, jump } => {
+                if *dest != 0 {
+                    write!(f, "{}=", DESTINATIONS[*dest as usize])?;
+                }
+
+                let (mnemonic, _) = COMPUTATIONS
+                    .iter()
+                    .find(|(_, bits)| bits == comp)
+                    .expect("Invalid comp bits");
+                write!(f, "{}", mnemonic)?;
+
+                if *jump != 0 {
+                    write!(f, ";{}", JUMPS[*jump as usize])?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Label(name) => write!(f, "({})", name),
+            Line::Instruction(instruction) => write!(f, "{}", instruction),
+            Line::Comment(comment) => write!(f, "// {}", comment),
+        }
+    }
+}
+
+// symbols are letters, digits, _ . $ and : but do not start with a digit
+pub fn is_symbol(value: &str) -> bool {
+    !value.is_empty()
+        && !value.starts_with(|character: char| character.is_ascii_digit())
+        && value
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || "_.$:".contains(character))
+}
+
+// A, D and M in any order, each at most once
+fn parse_destination(text: &str) -> Option<u16> {
+    let mut bits = 0;
+
+    for character in text.chars() {
+        let bit = match character {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return None,
+        };
+
+        if bits & bit != 0 {
+            return None;
+        }
+
+        bits |= bit;
+    }
+
+    Some(bits)
+}
+
+// + & and | also take their operands the other way around, like M+D
+fn parse_computation(text: &str) -> Option<u16> {
+    let lookup = |text: &str| {
+        COMPUTATIONS
+            .iter()
+            .find(|(mnemonic, _)| *mnemonic == text)
+            .map(|(_, bits)| *bits)
+    };
+
+    lookup(text).or_else(|| match text.as_bytes() {
+        [x, operator @ (b'+' | b'&' | b'|'), y] => lookup(&format!(
+            "{}{}{}",
+            *y as char, *operator as char, *x as char
+        )),
+        _ => None,
+    })
+}
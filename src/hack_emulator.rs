@@ -0,0 +1,180 @@
+// A Hack CPU for the tests: assembles the generator's output the way the course
+// assembler does and runs it until it settles in an endless loop.
+
+use std::collections::HashMap;
+
+use crate::codegen::{generate, CodeGenerator};
+use crate::hack::Instruction;
+use crate::parser::HackGenerator;
+use crate::program::VmFile;
+
+const PREDEFINED: [(&str, u16); 7] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+// variables are allocated from here in the order they are first used
+const VARIABLE_BASE: u16 = 16;
+
+pub struct Emulator {
+    rom: Vec<Instruction>,
+    ram: Vec<i16>,
+    pc: usize,
+    a: i16,
+    d: i16,
+    // the highest SP seen while running
+    pub max_sp: i16,
+    // instructions run so far, the final jump included
+    pub steps: usize,
+}
+
+impl Emulator {
+    pub fn load(asm: &[String]) -> Emulator {
+        let mut symbols: HashMap<String, u16> = PREDEFINED
+            .iter()
+            .map(|(name, address)| (name.to_string(), *address))
+            .collect();
+
+        for register in 0..16 {
+            symbols.insert(format!("R{}", register), register);
+        }
+
+        let mut instructions: Vec<Instruction> = Vec::new();
+
+        for line in asm.iter().map(|line| line.trim()) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            match line
+                .strip_prefix('(')
+                .and_then(|line| line.strip_suffix(')'))
+            {
+                Some(label) => {
+                    symbols.insert(String::from(label), instructions.len() as u16);
+                }
+                None => instructions.push(
+                    Instruction::parse(line)
+                        .unwrap_or_else(|message| panic!("{}: {}", line, message)),
+                ),
+            }
+        }
+
+        let mut next_variable = VARIABLE_BASE;
+
+        let rom = instructions
+            .into_iter()
+            .map(|instruction| match instruction {
+                Instruction::Symbol(name) => {
+                    Instruction::Address(*symbols.entry(name).or_insert_with(|| {
+                        next_variable += 1;
+                        next_variable - 1
+                    }))
+                }
+                instruction => instruction,
+            })
+            .collect();
+
+        Emulator {
+            rom,
+            ram: vec![0; 32768],
+            pc: 0,
+            a: 0,
+            d: 0,
+            max_sp: 0,
+            steps: 0,
+        }
+    }
+
+    pub fn ram(&self, address: usize) -> i16 {
+        self.ram[address]
+    }
+
+    // Runs until a jump to the instruction right before it, which is how `goto` to
+    // its own label ends a program. Panics when that takes more than `steps`.
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.steps += 1;
+
+            let (dest, comp, jump) = match self.rom[self.pc] {
+                Instruction::Address(value) => {
+                    self.a = value as i16;
+                    self.pc += 1;
+                    continue;
+                }
+                Instruction::Compute { dest, comp, jump } => (dest, comp, jump),
+                Instruction::Symbol(_) => unreachable!("symbols are resolved on load"),
+            };
+
+            let address = (self.a as u16 & 0x7FFF) as usize;
+            let result = alu(
+                self.d,
+                if comp & 0b1000000 != 0 {
+                    self.ram[address]
+                } else {
+                    self.a
+                },
+                comp,
+            );
+
+            if dest & 0b001 != 0 {
+                self.ram[address] = result;
+            }
+            if dest & 0b010 != 0 {
+                self.d = result;
+            }
+            // A is written last, M above used the old one
+            let target = self.a as u16 as usize;
+            if dest & 0b100 != 0 {
+                self.a = result;
+            }
+
+            self.max_sp = self.max_sp.max(self.ram[0]);
+
+            let taken = (jump & 0b100 != 0 && result < 0)
+                || (jump & 0b010 != 0 && result == 0)
+                || (jump & 0b001 != 0 && result > 0);
+
+            if !taken {
+                self.pc += 1;
+            } else if target + 1 == self.pc {
+                return;
+            } else {
+                self.pc = target;
+            }
+        }
+
+        panic!("the program did not stop within {} steps", steps);
+    }
+}
+
+// the c1..c6 bits are zx, nx, zy, ny, f and no
+fn alu(x: i16, y: i16, comp: u16) -> i16 {
+    let bit = |index: u16| comp & (1 << (5 - index)) != 0;
+
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+
+    if bit(5) {
+        !out
+    } else {
+        out
+    }
+}
+
+// translates `files` with the bootstrap code and runs them to the end
+pub fn run_program(files: &[VmFile], generator: &mut HackGenerator) -> Emulator {
+    let asm = generate(generator as &mut dyn CodeGenerator, files, true);
+    let mut emulator = Emulator::load(&asm);
+
+    emulator.run(50_000_000);
+    emulator
+}
@@ -0,0 +1,436 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::program::{VmCommand, VmFile};
+use crate::stack_depth::stack_effect;
+
+pub const DEFAULT_INLINE_BUDGET: usize = 8;
+
+// a function whose body can replace its calls: straight-line code without calls that
+// ends in its only return with exactly the return value on the stack
+struct Candidate {
+    file: usize,
+    locals: usize,
+    body: Vec<VmCommand>,
+    arguments_used: usize,
+    uses_static: bool,
+    // pointers the body overwrites; a real call would restore THIS and THAT
+    pointers: Vec<String>,
+}
+
+pub struct InlinedFunction {
+    pub name: String,
+    pub calls: usize,
+}
+
+// Arguments and locals of the inlined body become extra locals of the caller, past
+// its own, so they live in the caller's frame like they would in the callee's. Calls
+// outside any function have no frame to put them in and are left alone.
+pub fn inline_calls(files: &mut [VmFile], budget: usize) -> Vec<InlinedFunction> {
+    let candidates = find_candidates(files, budget);
+    let mut inlined: BTreeMap<String, usize> = BTreeMap::new();
+
+    for (index, file) in files.iter_mut().enumerate() {
+        let mut commands: Vec<VmCommand> = Vec::new();
+        // position of the caller's `function` command in `commands` and its locals
+        let mut caller: Option<(usize, usize)> = None;
+        let mut extra_locals = 0;
+
+        for command in file.commands.drain(..) {
+            if command.command == "function" {
+                grow_frame(&mut commands, caller, extra_locals);
+
+                caller = command
+                    .value
+                    .parse()
+                    .ok()
+                    .map(|locals| (commands.len(), locals));
+                extra_locals = 0;
+
+                commands.push(command);
+                continue;
+            }
+
+            let (candidate, first_slot) = match (candidates.get(command.detail.as_str()), caller) {
+                (Some(candidate), Some((_, locals))) if command.command == "call" => {
+                    (candidate, locals)
+                }
+                _ => {
+                    commands.push(command);
+                    continue;
+                }
+            };
+
+            let arguments: usize = command.value.parse().unwrap_or(0);
+
+            // statics belong to the file of the callee, which the caller cannot name
+            if candidate.arguments_used > arguments
+                || (candidate.uses_static && candidate.file != index)
+            {
+                commands.push(command);
+                continue;
+            }
+
+            let mut slots = arguments + candidate.locals;
+            if slots == 0 && !candidate.pointers.is_empty() {
+                slots = 1; // somewhere to keep the return value
+            }
+            extra_locals = extra_locals.max(slots);
+
+            commands.extend(expand(candidate, arguments, first_slot, command.line));
+            *inlined.entry(command.detail).or_insert(0) += 1;
+        }
+
+        grow_frame(&mut commands, caller, extra_locals);
+        file.commands = commands;
+    }
+
+    inlined
+        .into_iter()
+        .map(|(name, calls)| InlinedFunction { name, calls })
+        .collect()
+}
+
+// every inlined call in a function reuses the same extra locals
+fn grow_frame(commands: &mut [VmCommand], caller: Option<(usize, usize)>, extra_locals: usize) {
+    if let Some((header, locals)) = caller {
+        if extra_locals > 0 {
+            commands[header].value = (locals + extra_locals).to_string();
+        }
+    }
+}
+
+fn find_candidates(files: &[VmFile], budget: usize) -> HashMap<String, Candidate> {
+    let mut candidates: HashMap<String, Candidate> = HashMap::new();
+
+    for (index, file) in files.iter().enumerate() {
+        for function in file.functions() {
+            let header = &file.commands[function.start];
+            let body = &file.commands[function.start + 1..function.end];
+
+            let locals: usize = match header.value.parse() {
+                Ok(locals) => locals,
+                Err(_) => continue,
+            };
+
+            match body.split_last() {
+                Some((last, rest)) if last.command == "return" && rest.len() <= budget => {
+                    if !is_straight_line(rest) || depth_at_end(rest) != Some(1) {
+                        continue;
+                    }
+
+                    if let Some(candidate) = build_candidate(index, locals, rest) {
+                        candidates.insert(function.name, candidate);
+                    }
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    candidates
+}
+
+fn is_straight_line(body: &[VmCommand]) -> bool {
+    body.iter().all(|command| {
+        !matches!(
+            command.command.as_str(),
+            "call" | "return" | "label" | "goto" | "if-goto" | "function"
+        )
+    })
+}
+
+fn depth_at_end(body: &[VmCommand]) -> Option<i32> {
+    let mut depth = 0;
+
+    for command in body {
+        let (consumed, produced) = stack_effect(command);
+
+        if depth < consumed {
+            return None;
+        }
+
+        depth = depth - consumed + produced;
+    }
+
+    Some(depth)
+}
+
+fn build_candidate(file: usize, locals: usize, body: &[VmCommand]) -> Option<Candidate> {
+    let mut arguments_used = 0;
+    let mut pointers: Vec<String> = Vec::new();
+
+    for command in body {
+        let index: usize = match command.detail.as_str() {
+            "argument" | "local" => command.value.parse().ok()?,
+            _ => 0,
+        };
+
+        match command.detail.as_str() {
+            "argument" => arguments_used = arguments_used.max(index + 1),
+            "local" if index >= locals => return None,
+            "pointer" if command.command == "pop" && !pointers.contains(&command.value) => {
+                pointers.push(command.value.clone())
+            }
+            _ => (),
+        }
+    }
+
+    pointers.sort();
+
+    Some(Candidate {
+        file,
+        locals,
+        body: body.to_vec(),
+        arguments_used,
+        uses_static: body.iter().any(|command| command.detail == "static"),
+        pointers,
+    })
+}
+
+// the callee's argument i is the caller's local first_slot + i, its local j comes after
+fn expand(
+    candidate: &Candidate,
+    arguments: usize,
+    first_slot: usize,
+    line: usize,
+) -> Vec<VmCommand> {
+    let command = |command: &str, detail: &str, slot: usize| VmCommand {
+        command: String::from(command),
+        detail: String::from(detail),
+        value: (first_slot + slot).to_string(),
+        line,
+    };
+
+    let mut result: Vec<VmCommand> = Vec::new();
+
+    // the last argument is on top of the stack
+    for argument in (0..arguments).rev() {
+        result.push(command("pop", "local", argument));
+    }
+
+    for local in 0..candidate.locals {
+        result.push(VmCommand::parse("push constant 0", line));
+        result.push(command("pop", "local", arguments + local));
+    }
+
+    for pointer in &candidate.pointers {
+        result.push(VmCommand::parse(&format!("push pointer {}", pointer), line));
+    }
+
+    for body_command in &candidate.body {
+        let slot = match body_command.detail.as_str() {
+            "argument" => body_command.value.parse::<usize>().ok(),
+            "local" => body_command
+                .value
+                .parse::<usize>()
+                .ok()
+                .map(|local| arguments + local),
+            _ => None,
+        };
+
+        match slot {
+            Some(slot) => result.push(command(&body_command.command, "local", slot)),
+            None => result.push(VmCommand {
+                line,
+                ..body_command.clone()
+            }),
+        }
+    }
+
+    // the return value sits above the saved pointers
+    if !candidate.pointers.is_empty() {
+        result.push(command("pop", "local", 0));
+
+        for pointer in candidate.pointers.iter().rev() {
+            result.push(VmCommand::parse(&format!("pop pointer {}", pointer), line));
+        }
+
+        result.push(command("push", "local", 0));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hack_emulator::Emulator;
+    use crate::options::Options;
+    use crate::reachability::strip_unreachable;
+    use crate::test_programs::{run_on_hack, vm_file, PROGRAMS};
+
+    // getters and setters like the Jack compiler writes them, called from a function
+    // that keeps every temp slot busy across the calls
+    const FILES: [(&str, &str); 3] = [
+        (
+            "Sys",
+            "
+            function Sys.init 1
+            push constant 4000
+            pop pointer 0
+            push constant 10
+            pop temp 0
+            push constant 11
+            pop temp 4
+            push constant 12
+            pop temp 7
+            push constant 3000
+            push constant 17
+            call Point.setX 2
+            pop temp 1
+            push constant 3000
+            call Point.getX 1
+            push constant 4
+            call Util.twice 1
+            add
+            pop local 0
+            push local 0
+            pop temp 2
+            push pointer 0
+            pop temp 3
+            push temp 0
+            push temp 4
+            add
+            push temp 7
+            add
+            pop temp 5
+            label END
+            goto END
+            ",
+        ),
+        (
+            "Point",
+            "
+            function Point.setX 0
+            push argument 0
+            pop pointer 0
+            push argument 1
+            pop this 0
+            push constant 0
+            return
+            function Point.getX 0
+            push argument 0
+            pop pointer 0
+            push this 0
+            return
+            ",
+        ),
+        (
+            "Util",
+            "
+            function Util.twice 1
+            push argument 0
+            pop local 0
+            push local 0
+            push local 0
+            add
+            return
+            ",
+        ),
+    ];
+
+    fn files() -> Vec<VmFile> {
+        FILES
+            .iter()
+            .map(|(name, source)| vm_file(name, source))
+            .collect()
+    }
+
+    // SP is left out, the caller's frame grows by the extra locals, and so are R13-R15,
+    // which the generated code uses as scratch
+    fn assert_same_ram(left: &Emulator, right: &Emulator, addresses: &[usize], name: &str) {
+        for address in (1..13).chain(addresses.iter().copied()) {
+            assert_eq!(
+                left.ram(address),
+                right.ram(address),
+                "RAM[{}] after {}",
+                address,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn inlined_bodies_use_extra_locals_of_the_caller() {
+        let mut files = files();
+        let inlined = inline_calls(&mut files, DEFAULT_INLINE_BUDGET);
+
+        let names: Vec<&str> = inlined
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect();
+        assert_eq!(names, ["Point.getX", "Point.setX", "Util.twice"]);
+
+        // Point.setX takes two slots, Util.twice one for its argument and one for its local
+        assert_eq!(files[0].commands[0].to_string(), "function Sys.init 3");
+        assert!(!files[0]
+            .commands
+            .iter()
+            .any(|command| command.command == "call"));
+    }
+
+    #[test]
+    fn calls_outside_functions_are_not_inlined() {
+        let mut files = files();
+        files[0]
+            .commands
+            .insert(0, VmCommand::parse("call Util.twice 1", 1));
+
+        inline_calls(&mut files, DEFAULT_INLINE_BUDGET);
+
+        assert_eq!(files[0].commands[0].to_string(), "call Util.twice 1");
+    }
+
+    #[test]
+    fn inlining_leaves_the_same_ram() {
+        for cache_top in [false, true] {
+            let plain = run_on_hack(&files(), cache_top);
+
+            let mut inlined_files = files();
+            inline_calls(&mut inlined_files, DEFAULT_INLINE_BUDGET);
+            let inlined = run_on_hack(&inlined_files, cache_top);
+
+            assert_same_ram(&plain, &inlined, &[3000], "getters and setters");
+            assert_eq!(inlined.ram(3000), 17);
+            assert_eq!(inlined.ram(7), 25);
+            assert_eq!(inlined.ram(8), 4000);
+            assert_eq!(inlined.ram(10), 33);
+        }
+    }
+
+    #[test]
+    fn test_programs_leave_the_same_ram_when_inlined() {
+        for program in PROGRAMS {
+            let addresses: Vec<usize> = program
+                .expected
+                .iter()
+                .map(|(address, _)| *address)
+                .collect();
+
+            let plain = run_on_hack(&program.vm_files(), false);
+
+            let mut files = program.vm_files();
+            inline_calls(&mut files, DEFAULT_INLINE_BUDGET);
+            let inlined = run_on_hack(&files, false);
+
+            assert_same_ram(&plain, &inlined, &addresses, program.name);
+        }
+    }
+
+    #[test]
+    fn functions_inlined_everywhere_are_stripped_afterwards() {
+        let mut files = files();
+        let options = Options::from_args(&[String::new(), String::from("Sys")]);
+
+        inline_calls(&mut files, DEFAULT_INLINE_BUDGET);
+        let stripped = strip_unreachable(&mut files, "Sys.init", &options)
+            .ok()
+            .unwrap();
+
+        let names: Vec<&str> = stripped
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect();
+        assert_eq!(names, ["Point.setX", "Point.getX", "Util.twice"]);
+    }
+}
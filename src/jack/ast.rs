@@ -0,0 +1,79 @@
+// Jack class as parsed, before any name is resolved. Declarations listing several
+// names, parentheses and empty else blocks are kept so the parse tree can be printed
+
+pub struct Class {
+    pub name: String,
+    pub variables: Vec<VarDec>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+// `static`, `field` or `var` declaration of one or more names
+pub struct VarDec {
+    pub kind: String,
+    pub type_name: String,
+    pub names: Vec<String>,
+    pub line: usize,
+}
+
+pub struct Parameter {
+    pub type_name: String,
+    pub name: String,
+}
+
+pub struct Subroutine {
+    pub kind: String,
+    pub return_type: String,
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub locals: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+    pub line: usize,
+}
+
+pub struct Statement {
+    pub kind: StatementKind,
+    pub line: usize,
+}
+
+pub enum StatementKind {
+    Let {
+        name: String,
+        index: Option<Expression>,
+        value: Expression,
+    },
+    If {
+        condition: Expression,
+        then: Vec<Statement>,
+        otherwise: Option<Vec<Statement>>,
+    },
+    While {
+        condition: Expression,
+        body: Vec<Statement>,
+    },
+    Do(Call),
+    Return(Option<Expression>),
+}
+
+// Jack has no precedence: operators apply left to right
+pub struct Expression {
+    pub first: Term,
+    pub rest: Vec<(char, Term)>,
+}
+
+pub enum Term {
+    Integer(String),
+    String(String),
+    Keyword(String),
+    Variable(String),
+    Index(String, Box<Expression>),
+    Call(Call),
+    Parenthesized(Box<Expression>),
+    Unary(char, Box<Term>),
+}
+
+// `name(...)`, or `receiver.name(...)` where the receiver is a variable or a class
+pub struct Call {
+    pub receiver: Option<String>,
+    pub name: String,
+    pub arguments: Vec<Expression>,
+}
@@ -0,0 +1,378 @@
+// Jack front end: compiles a .jack class straight into the VM commands the rest of
+// the translator works on
+
+mod ast;
+mod parser;
+mod symbol_table;
+mod tokenizer;
+mod vm_writer;
+mod xml;
+
+use crate::diagnostics::Diagnostic;
+use crate::program::VmCommand;
+
+use self::parser::Parser;
+use self::tokenizer::tokenize;
+use self::vm_writer::VmWriter;
+use self::xml::{parse_xml, tokens_xml};
+
+pub struct JackError {
+    code: &'static str,
+    message: String,
+    line: usize,
+}
+
+impl JackError {
+    pub fn syntax(message: &str, line: usize) -> JackError {
+        JackError {
+            code: "jack-syntax",
+            message: String::from(message),
+            line,
+        }
+    }
+
+    pub fn undefined_variable(name: &str, line: usize) -> JackError {
+        JackError {
+            code: "jack-undefined-variable",
+            message: format!("{} is not a variable in scope", name),
+            line,
+        }
+    }
+
+    pub fn duplicate_variable(name: &str, line: usize) -> JackError {
+        JackError {
+            code: "jack-duplicate-variable",
+            message: format!("{} is declared more than once", name),
+            line,
+        }
+    }
+
+    fn into_diagnostic(self, file_path: &str) -> Diagnostic {
+        Diagnostic::error(self.code, self.message).at(file_path, self.line)
+    }
+}
+
+// statics are named after the file like the course tools, so the class should be
+// declared in a file of the same name
+pub fn compile_class(file_path: &str, content: &str) -> Result<Vec<VmCommand>, Diagnostic> {
+    let compile = || {
+        let tokens = tokenize(content)?;
+        let class = Parser::new(tokens).parse_class()?;
+
+        VmWriter::new(&class.name).write_class(&class)
+    };
+
+    compile().map_err(|error| error.into_diagnostic(file_path))
+}
+
+// `tokens-xml` and `parse-xml` reproduce the analyzer output of the course, T.xml and
+// .xml files, and `vm` the compiler output. The text ends every line in CRLF like the
+// course files, so they compare byte for byte.
+pub fn emit(file_path: &str, content: &str, kind: &str) -> Result<String, Diagnostic> {
+    let emit = || {
+        let tokens = tokenize(content)?;
+
+        if kind == "tokens-xml" {
+            return Ok(tokens_xml(&tokens));
+        }
+
+        let class = Parser::new(tokens).parse_class()?;
+
+        if kind == "parse-xml" {
+            return Ok(parse_xml(&class));
+        }
+
+        let commands = VmWriter::new(&class.name).write_class(&class)?;
+        Ok(commands.iter().map(|command| command.to_string()).collect())
+    };
+
+    emit()
+        .map(|lines: Vec<String>| lines.join("\r\n") + "\r\n")
+        .map_err(|error: JackError| error.into_diagnostic(file_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str) -> Vec<String> {
+        emit("Main.jack", source, "vm")
+            .unwrap_or_else(|diagnostic| panic!("{}", diagnostic.message))
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    fn error(source: &str) -> (&'static str, String, usize) {
+        let diagnostic = compile_class("Main.jack", source)
+            .err()
+            .expect("the class compiles");
+
+        (
+            diagnostic.code,
+            diagnostic.message,
+            diagnostic.location.unwrap().line,
+        )
+    }
+
+    #[test]
+    fn constructors_and_methods_set_this() {
+        let source = "
+            class Point {
+                field int x, y;
+                static int count;
+
+                constructor Point new(int ax, int ay) {
+                    let x = ax;
+                    let y = ay;
+                    let count = count + 1;
+                    return this;
+                }
+
+                method int getX() {
+                    return x;
+                }
+
+                method int plus(Point other) {
+                    do draw();
+                    return x + other.getX();
+                }
+
+                method void draw() {
+                    return;
+                }
+            }
+        ";
+
+        assert_eq!(
+            compile(source),
+            [
+                "function Point.new 0",
+                "push constant 2",
+                "call Memory.alloc 1",
+                "pop pointer 0",
+                "push argument 0",
+                "pop this 0",
+                "push argument 1",
+                "pop this 1",
+                "push static 0",
+                "push constant 1",
+                "add",
+                "pop static 0",
+                "push pointer 0",
+                "return",
+                "function Point.getX 0",
+                "push argument 0",
+                "pop pointer 0",
+                "push this 0",
+                "return",
+                "function Point.plus 0",
+                "push argument 0",
+                "pop pointer 0",
+                "push pointer 0",
+                "call Point.draw 1",
+                "pop temp 0",
+                "push this 0",
+                "push argument 1",
+                "call Point.getX 1",
+                "add",
+                "return",
+                "function Point.draw 0",
+                "push argument 0",
+                "pop pointer 0",
+                "push constant 0",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_go_through_that_and_strings_are_built_by_character() {
+        let source = "
+            class Main {
+                function void main() {
+                    var Array a;
+                    var String s;
+                    let a = Array.new(3);
+                    let a[1] = a[0] * 2;
+                    let s = \"Hi\";
+                    do Output.printString(s);
+                    return;
+                }
+            }
+        ";
+
+        assert_eq!(
+            compile(source),
+            [
+                "function Main.main 2",
+                "push constant 3",
+                "call Array.new 1",
+                "pop local 0",
+                "push constant 1",
+                "push local 0",
+                "add",
+                "push constant 0",
+                "push local 0",
+                "add",
+                "pop pointer 1",
+                "push that 0",
+                "push constant 2",
+                "call Math.multiply 2",
+                "pop temp 0",
+                "pop pointer 1",
+                "push temp 0",
+                "pop that 0",
+                "push constant 2",
+                "call String.new 1",
+                "push constant 72",
+                "call String.appendChar 2",
+                "push constant 105",
+                "call String.appendChar 2",
+                "pop local 1",
+                "push local 1",
+                "call Output.printString 1",
+                "pop temp 0",
+                "push constant 0",
+                "return",
+            ]
+        );
+    }
+
+    // labels are numbered in the order the statements start, again from 0 in every
+    // subroutine
+    #[test]
+    fn if_and_while_labels_are_numbered_per_subroutine() {
+        let source = "
+            class Main {
+                function int f(int n) {
+                    var int i;
+                    while (i < n) {
+                        if (i = 2) { let i = i + 2; } else { let i = i + 1; }
+                        if (~(i > 5)) { let n = n - 1; }
+                    }
+                    while (false) { }
+                    return -i;
+                }
+
+                function boolean g() {
+                    if (true) { return true; }
+                    return null;
+                }
+            }
+        ";
+
+        assert_eq!(
+            compile(source),
+            [
+                "function Main.f 1",
+                "label WHILE_EXP0",
+                "push local 0",
+                "push argument 0",
+                "lt",
+                "not",
+                "if-goto WHILE_END0",
+                "push local 0",
+                "push constant 2",
+                "eq",
+                "if-goto IF_TRUE0",
+                "goto IF_FALSE0",
+                "label IF_TRUE0",
+                "push local 0",
+                "push constant 2",
+                "add",
+                "pop local 0",
+                "goto IF_END0",
+                "label IF_FALSE0",
+                "push local 0",
+                "push constant 1",
+                "add",
+                "pop local 0",
+                "label IF_END0",
+                "push local 0",
+                "push constant 5",
+                "gt",
+                "not",
+                "if-goto IF_TRUE1",
+                "goto IF_FALSE1",
+                "label IF_TRUE1",
+                "push argument 0",
+                "push constant 1",
+                "sub",
+                "pop argument 0",
+                "label IF_FALSE1",
+                "goto WHILE_EXP0",
+                "label WHILE_END0",
+                "label WHILE_EXP1",
+                "push constant 0",
+                "not",
+                "if-goto WHILE_END1",
+                "goto WHILE_EXP1",
+                "label WHILE_END1",
+                "push local 0",
+                "neg",
+                "return",
+                "function Main.g 0",
+                "push constant 0",
+                "not",
+                "if-goto IF_TRUE0",
+                "goto IF_FALSE0",
+                "label IF_TRUE0",
+                "push constant 0",
+                "not",
+                "return",
+                "label IF_FALSE0",
+                "push constant 0",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_variables_are_errors() {
+        let source =
+            "class Main {\n  function void main() {\n    let y = 1;\n    return;\n  }\n}\n";
+
+        assert_eq!(
+            error(source),
+            (
+                "jack-undefined-variable",
+                String::from("y is not a variable in scope"),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn duplicate_declarations_are_errors() {
+        let source =
+            "class Main {\n  function void main() {\n    var int a, b;\n    var char a;\n    return;\n  }\n}\n";
+
+        assert_eq!(
+            error(source),
+            (
+                "jack-duplicate-variable",
+                String::from("a is declared more than once"),
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn integer_constants_must_fit_15_bits() {
+        let source = "class Main {\n  function int main() {\n    return 32768;\n  }\n}\n";
+
+        assert_eq!(
+            error(source),
+            (
+                "jack-syntax",
+                String::from("integer constant 32768 is out of range 0..32767"),
+                3
+            )
+        );
+        assert_eq!(
+            compile("class Main { function int main() { return 32767; } }"),
+            ["function Main.main 0", "push constant 32767", "return"]
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use super::ast::{
+    Call, Class, Expression, Parameter, Statement, StatementKind, Subroutine, Term, VarDec,
+};
+use super::tokenizer::{Token, TokenKind};
+use super::JackError;
+
+const OPERATORS: &str = "+-*/&|<>=";
+
+// recursive descent over the Jack grammar, one method per rule
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Parser {
+        Parser {
+            tokens,
+            position: 0,
+        }
+    }
+
+    pub fn parse_class(&mut self) -> Result<Class, JackError> {
+        self.expect(TokenKind::Keyword, "class")?;
+        let name = self.identifier()?;
+        self.expect(TokenKind::Symbol, "{")?;
+
+        let mut variables: Vec<VarDec> = Vec::new();
+        while self.at_keyword("static") || self.at_keyword("field") {
+            variables.push(self.var_dec()?);
+        }
+
+        let mut subroutines: Vec<Subroutine> = Vec::new();
+        while self.at_keyword("constructor")
+            || self.at_keyword("function")
+            || self.at_keyword("method")
+        {
+            subroutines.push(self.subroutine()?);
+        }
+
+        self.expect(TokenKind::Symbol, "}")?;
+
+        if let Some(token) = self.tokens.get(self.position) {
+            return Err(unexpected(token, "end of file"));
+        }
+
+        Ok(Class {
+            name,
+            variables,
+            subroutines,
+        })
+    }
+
+    // classVarDec and varDec: kind type name (',' name)* ';'
+    fn var_dec(&mut self) -> Result<VarDec, JackError> {
+        let kind = self.next()?;
+        let type_name = self.type_name(false)?;
+        let mut names = vec![self.identifier()?];
+
+        while self.at_symbol(",") {
+            self.next()?;
+            names.push(self.identifier()?);
+        }
+
+        self.expect(TokenKind::Symbol, ";")?;
+
+        Ok(VarDec {
+            kind: kind.text,
+            type_name,
+            names,
+            line: kind.line,
+        })
+    }
+
+    fn subroutine(&mut self) -> Result<Subroutine, JackError> {
+        let kind = self.next()?;
+        let return_type = self.type_name(true)?;
+        let name = self.identifier()?;
+
+        self.expect(TokenKind::Symbol, "(")?;
+        let mut parameters: Vec<Parameter> = Vec::new();
+
+        if !self.at_symbol(")") {
+            loop {
+                let type_name = self.type_name(false)?;
+                let name = self.identifier()?;
+                parameters.push(Parameter { type_name, name });
+
+                if !self.at_symbol(",") {
+                    break;
+                }
+                self.next()?;
+            }
+        }
+
+        self.expect(TokenKind::Symbol, ")")?;
+        self.expect(TokenKind::Symbol, "{")?;
+
+        let mut locals: Vec<VarDec> = Vec::new();
+        while self.at_keyword("var") {
+            locals.push(self.var_dec()?);
+        }
+
+        let statements = self.statements()?;
+        self.expect(TokenKind::Symbol, "}")?;
+
+        Ok(Subroutine {
+            kind: kind.text,
+            return_type,
+            name,
+            parameters,
+            locals,
+            statements,
+            line: kind.line,
+        })
+    }
+
+    fn type_name(&mut self, allow_void: bool) -> Result<String, JackError> {
+        let token = self.next()?;
+
+        match token.kind {
+            TokenKind::Identifier => Ok(token.text),
+            TokenKind::Keyword if matches!(token.text.as_str(), "int" | "char" | "boolean") => {
+                Ok(token.text)
+            }
+            TokenKind::Keyword if allow_void && token.text == "void" => Ok(token.text),
+            _ => Err(unexpected(&token, "a type")),
+        }
+    }
+
+    fn statements(&mut self) -> Result<Vec<Statement>, JackError> {
+        let mut statements: Vec<Statement> = Vec::new();
+
+        while !self.at_symbol("}") {
+            statements.push(self.statement()?);
+        }
+
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, JackError> {
+        let token = self.next()?;
+
+        let kind = match token.text.as_str() {
+            "let" if token.kind == TokenKind::Keyword => {
+                let name = self.identifier()?;
+                let mut index = None;
+
+                if self.at_symbol("[") {
+                    self.next()?;
+                    index = Some(self.expression()?);
+                    self.expect(TokenKind::Symbol, "]")?;
+                }
+
+                self.expect(TokenKind::Symbol, "=")?;
+                let value = self.expression()?;
+                self.expect(TokenKind::Symbol, ";")?;
+
+                StatementKind::Let { name, index, value }
+            }
+            "if" if token.kind == TokenKind::Keyword => {
+                let condition = self.condition()?;
+                let then = self.block()?;
+                let mut otherwise = None;
+
+                if self.at_keyword("else") {
+                    self.next()?;
+                    otherwise = Some(self.block()?);
+                }
+
+                StatementKind::If {
+                    condition,
+                    then,
+                    otherwise,
+                }
+            }
+            "while" if token.kind == TokenKind::Keyword => {
+                let condition = self.condition()?;
+                let body = self.block()?;
+
+                StatementKind::While { condition, body }
+            }
+            "do" if token.kind == TokenKind::Keyword => {
+                let name = self.identifier()?;
+                let call = self.call(name)?;
+                self.expect(TokenKind::Symbol, ";")?;
+
+                StatementKind::Do(call)
+            }
+            "return" if token.kind == TokenKind::Keyword => {
+                let mut value = None;
+
+                if !self.at_symbol(";") {
+                    value = Some(self.expression()?);
+                }
+
+                self.expect(TokenKind::Symbol, ";")?;
+
+                StatementKind::Return(value)
+            }
+            _ => return Err(unexpected(&token, "a statement")),
+        };
+
+        Ok(Statement {
+            kind,
+            line: token.line,
+        })
+    }
+
+    fn condition(&mut self) -> Result<Expression, JackError> {
+        self.expect(TokenKind::Symbol, "(")?;
+        let condition = self.expression()?;
+        self.expect(TokenKind::Symbol, ")")?;
+
+        Ok(condition)
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, JackError> {
+        self.expect(TokenKind::Symbol, "{")?;
+        let statements = self.statements()?;
+        self.expect(TokenKind::Symbol, "}")?;
+
+        Ok(statements)
+    }
+
+    fn expression(&mut self) -> Result<Expression, JackError> {
+        let first = self.term()?;
+        let mut rest: Vec<(char, Term)> = Vec::new();
+
+        while let Some(operator) = self.operator() {
+            self.next()?;
+            rest.push((operator, self.term()?));
+        }
+
+        Ok(Expression { first, rest })
+    }
+
+    fn operator(&self) -> Option<char> {
+        let token = self.tokens.get(self.position)?;
+
+        match token.kind {
+            TokenKind::Symbol if OPERATORS.contains(token.text.as_str()) => {
+                token.text.chars().next()
+            }
+            _ => None,
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, JackError> {
+        let token = self.next()?;
+
+        match token.kind {
+            TokenKind::IntegerConstant => Ok(Term::Integer(token.text)),
+            TokenKind::StringConstant => Ok(Term::String(token.text)),
+            TokenKind::Keyword
+                if matches!(token.text.as_str(), "true" | "false" | "null" | "this") =>
+            {
+                Ok(Term::Keyword(token.text))
+            }
+            TokenKind::Identifier if self.at_symbol("[") => {
+                self.next()?;
+                let index = self.expression()?;
+                self.expect(TokenKind::Symbol, "]")?;
+
+                Ok(Term::Index(token.text, Box::new(index)))
+            }
+            TokenKind::Identifier if self.at_symbol("(") || self.at_symbol(".") => {
+                Ok(Term::Call(self.call(token.text)?))
+            }
+            TokenKind::Identifier => Ok(Term::Variable(token.text)),
+            TokenKind::Symbol if token.text == "(" => {
+                let expression = self.expression()?;
+                self.expect(TokenKind::Symbol, ")")?;
+
+                Ok(Term::Parenthesized(Box::new(expression)))
+            }
+            TokenKind::Symbol if token.text == "-" || token.text == "~" => {
+                let operator = token.text.chars().next().unwrap_or('-');
+                Ok(Term::Unary(operator, Box::new(self.term()?)))
+            }
+            _ => Err(unexpected(&token, "an expression")),
+        }
+    }
+
+    // subroutineCall after its first identifier
+    fn call(&mut self, first: String) -> Result<Call, JackError> {
+        let (receiver, name) = if self.at_symbol(".") {
+            self.next()?;
+            (Some(first), self.identifier()?)
+        } else {
+            (None, first)
+        };
+
+        self.expect(TokenKind::Symbol, "(")?;
+        let mut arguments: Vec<Expression> = Vec::new();
+
+        if !self.at_symbol(")") {
+            loop {
+                arguments.push(self.expression()?);
+
+                if !self.at_symbol(",") {
+                    break;
+                }
+                self.next()?;
+            }
+        }
+
+        self.expect(TokenKind::Symbol, ")")?;
+
+        Ok(Call {
+            receiver,
+            name,
+            arguments,
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, JackError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let line = self.tokens.last().map(|token| token.line).unwrap_or(1);
+                Err(JackError::syntax("unexpected end of file", line))
+            }
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, text: &str) -> Result<Token, JackError> {
+        let token = self.next()?;
+
+        if !token.is(kind, text) {
+            return Err(unexpected(&token, &format!("'{}'", text)));
+        }
+
+        Ok(token)
+    }
+
+    fn identifier(&mut self) -> Result<String, JackError> {
+        let token = self.next()?;
+
+        if token.kind != TokenKind::Identifier {
+            return Err(unexpected(&token, "an identifier"));
+        }
+
+        Ok(token.text)
+    }
+
+    fn at_symbol(&self, text: &str) -> bool {
+        self.at(TokenKind::Symbol, text)
+    }
+
+    fn at_keyword(&self, text: &str) -> bool {
+        self.at(TokenKind::Keyword, text)
+    }
+
+    fn at(&self, kind: TokenKind, text: &str) -> bool {
+        match self.tokens.get(self.position) {
+            Some(token) => token.is(kind, text),
+            None => false,
+        }
+    }
+}
+
+fn unexpected(token: &Token, expected: &str) -> JackError {
+    JackError::syntax(
+        &format!("expected {} but found '{}'", expected, token.text),
+        token.line,
+    )
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+pub struct Symbol {
+    pub type_name: String,
+    pub segment: &'static str,
+    pub index: usize,
+}
+
+// class scope holds statics and fields, subroutine scope arguments and locals; the
+// subroutine scope shadows the class one
+pub struct SymbolTable {
+    class: HashMap<String, Symbol>,
+    subroutine: HashMap<String, Symbol>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            class: HashMap::new(),
+            subroutine: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn start_subroutine(&mut self) {
+        self.subroutine.clear();
+        self.counts.remove("argument");
+        self.counts.remove("local");
+    }
+
+    // `kind` is the Jack declaration keyword; false when the name is already taken
+    // in the same scope
+    pub fn define(&mut self, name: &str, type_name: &str, kind: &str) -> bool {
+        let segment = match kind {
+            "static" => "static",
+            "field" => "this",
+            "argument" => "argument",
+            "var" => "local",
+            _ => panic!("Invalid variable kind! {}", kind),
+        };
+
+        let scope = match segment {
+            "static" | "this" => &mut self.class,
+            _ => &mut self.subroutine,
+        };
+
+        if scope.contains_key(name) {
+            return false;
+        }
+
+        let count = self.counts.entry(segment).or_insert(0);
+
+        scope.insert(
+            String::from(name),
+            Symbol {
+                type_name: String::from(type_name),
+                segment,
+                index: *count,
+            },
+        );
+
+        *count += 1;
+        true
+    }
+
+    pub fn count(&self, segment: &str) -> usize {
+        *self.counts.get(segment).unwrap_or(&0)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.subroutine.get(name).or_else(|| self.class.get(name))
+    }
+}
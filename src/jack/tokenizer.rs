@@ -0,0 +1,153 @@
+use super::JackError;
+
+pub const KEYWORDS: [&str; 21] = [
+    "class",
+    "constructor",
+    "function",
+    "method",
+    "field",
+    "static",
+    "var",
+    "int",
+    "char",
+    "boolean",
+    "void",
+    "true",
+    "false",
+    "null",
+    "this",
+    "let",
+    "do",
+    "if",
+    "else",
+    "while",
+    "return",
+];
+
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Keyword,
+    Symbol,
+    IntegerConstant,
+    StringConstant,
+    Identifier,
+}
+
+#[derive(Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub line: usize,
+}
+
+impl Token {
+    pub fn is(&self, kind: TokenKind, text: &str) -> bool {
+        self.kind == kind && self.text == text
+    }
+}
+
+pub fn tokenize(content: &str) -> Result<Vec<Token>, JackError> {
+    let characters: Vec<char> = content.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut position = 0;
+    let mut line = 1;
+
+    while position < characters.len() {
+        let character = characters[position];
+        let next = characters.get(position + 1).copied();
+
+        if character == '\n' {
+            line += 1;
+            position += 1;
+        } else if character.is_whitespace() {
+            position += 1;
+        } else if character == '/' && next == Some('/') {
+            while position < characters.len() && characters[position] != '\n' {
+                position += 1;
+            }
+        } else if character == '/' && next == Some('*') {
+            let start_line = line;
+            position += 2;
+
+            loop {
+                match characters.get(position) {
+                    Some('*') if characters.get(position + 1) == Some(&'/') => break,
+                    Some('\n') => line += 1,
+                    Some(_) => (),
+                    None => return Err(JackError::syntax("unterminated comment", start_line)),
+                }
+                position += 1;
+            }
+
+            position += 2;
+        } else if SYMBOLS.contains(character) {
+            tokens.push(token(TokenKind::Symbol, character.to_string(), line));
+            position += 1;
+        } else if character == '"' {
+            let start = position + 1;
+            position = start;
+
+            while position < characters.len() && !matches!(characters[position], '"' | '\n') {
+                position += 1;
+            }
+
+            if characters.get(position) != Some(&'"') {
+                return Err(JackError::syntax("unterminated string constant", line));
+            }
+
+            let text: String = characters[start..position].iter().collect();
+            tokens.push(token(TokenKind::StringConstant, text, line));
+            position += 1;
+        } else if character.is_ascii_digit() {
+            let start = position;
+
+            while position < characters.len() && characters[position].is_ascii_digit() {
+                position += 1;
+            }
+
+            let text: String = characters[start..position].iter().collect();
+
+            match text.parse::<u32>() {
+                Ok(value) if value <= 32767 => (),
+                _ => {
+                    return Err(JackError::syntax(
+                        &format!("integer constant {} is out of range 0..32767", text),
+                        line,
+                    ))
+                }
+            }
+
+            tokens.push(token(TokenKind::IntegerConstant, text, line));
+        } else if character.is_ascii_alphabetic() || character == '_' {
+            let start = position;
+
+            while position < characters.len()
+                && (characters[position].is_ascii_alphanumeric() || characters[position] == '_')
+            {
+                position += 1;
+            }
+
+            let text: String = characters[start..position].iter().collect();
+            let kind = if KEYWORDS.contains(&text.as_str()) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Identifier
+            };
+
+            tokens.push(token(kind, text, line));
+        } else {
+            return Err(JackError::syntax(
+                &format!("unexpected character '{}'", character),
+                line,
+            ));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn token(kind: TokenKind, text: String, line: usize) -> Token {
+    Token { kind, text, line }
+}
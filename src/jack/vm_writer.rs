@@ -0,0 +1,307 @@
+use super::ast::{Call, Class, Expression, Statement, StatementKind, Subroutine, Term, VarDec};
+use super::symbol_table::SymbolTable;
+use super::JackError;
+use crate::program::VmCommand;
+
+// Walks a parsed class and writes the same VM code as the course's JackCompiler,
+// including its IF_TRUE/IF_FALSE/IF_END and WHILE_EXP/WHILE_END label names
+pub struct VmWriter {
+    class_name: String,
+    symbols: SymbolTable,
+    commands: Vec<VmCommand>,
+    line: usize,
+    if_count: usize,
+    while_count: usize,
+}
+
+impl VmWriter {
+    pub fn new(class_name: &str) -> VmWriter {
+        VmWriter {
+            class_name: String::from(class_name),
+            symbols: SymbolTable::new(),
+            commands: Vec::new(),
+            line: 0,
+            if_count: 0,
+            while_count: 0,
+        }
+    }
+
+    pub fn write_class(mut self, class: &Class) -> Result<Vec<VmCommand>, JackError> {
+        for variables in &class.variables {
+            self.define(variables, &variables.kind)?;
+        }
+
+        for subroutine in &class.subroutines {
+            self.write_subroutine(subroutine)?;
+        }
+
+        Ok(self.commands)
+    }
+
+    fn define(&mut self, variables: &VarDec, kind: &str) -> Result<(), JackError> {
+        for name in &variables.names {
+            if !self.symbols.define(name, &variables.type_name, kind) {
+                return Err(JackError::duplicate_variable(name, variables.line));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_subroutine(&mut self, subroutine: &Subroutine) -> Result<(), JackError> {
+        self.symbols.start_subroutine();
+        self.if_count = 0;
+        self.while_count = 0;
+        self.line = subroutine.line;
+
+        // methods get the object as argument 0
+        if subroutine.kind == "method" {
+            self.symbols
+                .define("this", &self.class_name.clone(), "argument");
+        }
+
+        for parameter in &subroutine.parameters {
+            if !self
+                .symbols
+                .define(&parameter.name, &parameter.type_name, "argument")
+            {
+                return Err(JackError::duplicate_variable(
+                    &parameter.name,
+                    subroutine.line,
+                ));
+            }
+        }
+
+        for variables in &subroutine.locals {
+            self.define(variables, "var")?;
+        }
+
+        let name = format!("{}.{}", self.class_name, subroutine.name);
+        let locals = self.symbols.count("local").to_string();
+        self.emit("function", &name, &locals);
+
+        match subroutine.kind.as_str() {
+            "constructor" => {
+                let fields = self.symbols.count("this").to_string();
+                self.emit("push", "constant", &fields);
+                self.emit("call", "Memory.alloc", "1");
+                self.emit("pop", "pointer", "0");
+            }
+            "method" => {
+                self.emit("push", "argument", "0");
+                self.emit("pop", "pointer", "0");
+            }
+            _ => (),
+        }
+
+        self.write_statements(&subroutine.statements)
+    }
+
+    fn write_statements(&mut self, statements: &[Statement]) -> Result<(), JackError> {
+        for statement in statements {
+            self.line = statement.line;
+            self.write_statement(&statement.kind)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_statement(&mut self, statement: &StatementKind) -> Result<(), JackError> {
+        match statement {
+            StatementKind::Let {
+                name,
+                index: None,
+                value,
+            } => {
+                self.write_expression(value)?;
+                let (segment, index) = self.variable(name)?;
+                self.emit("pop", segment, &index);
+            }
+            StatementKind::Let {
+                name,
+                index: Some(index),
+                value,
+            } => {
+                self.write_element_address(name, index)?;
+                self.write_expression(value)?;
+                self.emit("pop", "temp", "0");
+                self.emit("pop", "pointer", "1");
+                self.emit("push", "temp", "0");
+                self.emit("pop", "that", "0");
+            }
+            StatementKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let count = self.if_count;
+                self.if_count += 1;
+
+                let true_label = format!("IF_TRUE{}", count);
+                let false_label = format!("IF_FALSE{}", count);
+                let end_label = format!("IF_END{}", count);
+
+                self.write_expression(condition)?;
+                self.emit("if-goto", &true_label, "");
+                self.emit("goto", &false_label, "");
+                self.emit("label", &true_label, "");
+                self.write_statements(then)?;
+
+                match otherwise {
+                    Some(otherwise) => {
+                        self.emit("goto", &end_label, "");
+                        self.emit("label", &false_label, "");
+                        self.write_statements(otherwise)?;
+                        self.emit("label", &end_label, "");
+                    }
+                    None => self.emit("label", &false_label, ""),
+                }
+            }
+            StatementKind::While { condition, body } => {
+                let count = self.while_count;
+                self.while_count += 1;
+
+                let loop_label = format!("WHILE_EXP{}", count);
+                let end_label = format!("WHILE_END{}", count);
+
+                self.emit("label", &loop_label, "");
+                self.write_expression(condition)?;
+                self.emit("not", "", "");
+                self.emit("if-goto", &end_label, "");
+                self.write_statements(body)?;
+                self.emit("goto", &loop_label, "");
+                self.emit("label", &end_label, "");
+            }
+            StatementKind::Do(call) => {
+                self.write_call(call)?;
+                self.emit("pop", "temp", "0");
+            }
+            StatementKind::Return(value) => {
+                match value {
+                    Some(value) => self.write_expression(value)?,
+                    None => self.emit("push", "constant", "0"),
+                }
+                self.emit("return", "", "");
+            }
+        }
+
+        Ok(())
+    }
+
+    // leaves name + index on the stack, index first like the course compiler
+    fn write_element_address(&mut self, name: &str, index: &Expression) -> Result<(), JackError> {
+        self.write_expression(index)?;
+        let (segment, position) = self.variable(name)?;
+        self.emit("push", segment, &position);
+        self.emit("add", "", "");
+
+        Ok(())
+    }
+
+    fn write_expression(&mut self, expression: &Expression) -> Result<(), JackError> {
+        self.write_term(&expression.first)?;
+
+        for (operator, term) in &expression.rest {
+            self.write_term(term)?;
+
+            match operator {
+                '+' => self.emit("add", "", ""),
+                '-' => self.emit("sub", "", ""),
+                '*' => self.emit("call", "Math.multiply", "2"),
+                '/' => self.emit("call", "Math.divide", "2"),
+                '&' => self.emit("and", "", ""),
+                '|' => self.emit("or", "", ""),
+                '<' => self.emit("lt", "", ""),
+                '>' => self.emit("gt", "", ""),
+                _ => self.emit("eq", "", ""),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_term(&mut self, term: &Term) -> Result<(), JackError> {
+        match term {
+            Term::Integer(value) => self.emit("push", "constant", value),
+            Term::String(value) => {
+                self.emit("push", "constant", &value.chars().count().to_string());
+                self.emit("call", "String.new", "1");
+
+                for character in value.chars() {
+                    self.emit("push", "constant", &(character as u32).to_string());
+                    self.emit("call", "String.appendChar", "2");
+                }
+            }
+            Term::Keyword(keyword) => match keyword.as_str() {
+                "true" => {
+                    self.emit("push", "constant", "0");
+                    self.emit("not", "", "");
+                }
+                "this" => self.emit("push", "pointer", "0"),
+                _ => self.emit("push", "constant", "0"),
+            },
+            Term::Variable(name) => {
+                let (segment, index) = self.variable(name)?;
+                self.emit("push", segment, &index);
+            }
+            Term::Index(name, index) => {
+                self.write_element_address(name, index)?;
+                self.emit("pop", "pointer", "1");
+                self.emit("push", "that", "0");
+            }
+            Term::Call(call) => self.write_call(call)?,
+            Term::Parenthesized(expression) => self.write_expression(expression)?,
+            Term::Unary(operator, term) => {
+                self.write_term(term)?;
+                self.emit(if *operator == '-' { "neg" } else { "not" }, "", "");
+            }
+        }
+
+        Ok(())
+    }
+
+    // `f()` is a method of this object, `v.f()` a method of the object in variable v
+    // and `C.f()` a function or constructor of class C
+    fn write_call(&mut self, call: &Call) -> Result<(), JackError> {
+        let (class_name, receiver) = match &call.receiver {
+            None => (self.class_name.clone(), Some((String::from("pointer"), 0))),
+            Some(name) => match self.symbols.lookup(name) {
+                Some(symbol) => (
+                    symbol.type_name.clone(),
+                    Some((String::from(symbol.segment), symbol.index)),
+                ),
+                None => (name.clone(), None),
+            },
+        };
+
+        if let Some((segment, index)) = &receiver {
+            self.emit("push", segment, &index.to_string());
+        }
+
+        for argument in &call.arguments {
+            self.write_expression(argument)?;
+        }
+
+        let arguments = call.arguments.len() + receiver.iter().count();
+        let name = format!("{}.{}", class_name, call.name);
+        self.emit("call", &name, &arguments.to_string());
+
+        Ok(())
+    }
+
+    fn variable(&self, name: &str) -> Result<(&'static str, String), JackError> {
+        match self.symbols.lookup(name) {
+            Some(symbol) => Ok((symbol.segment, symbol.index.to_string())),
+            None => Err(JackError::undefined_variable(name, self.line)),
+        }
+    }
+
+    fn emit(&mut self, command: &str, detail: &str, value: &str) {
+        self.commands.push(VmCommand {
+            command: String::from(command),
+            detail: String::from(detail),
+            value: String::from(value),
+            line: self.line,
+        });
+    }
+}
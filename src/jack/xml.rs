@@ -0,0 +1,427 @@
+use super::ast::{Call, Class, Expression, Statement, StatementKind, Subroutine, Term, VarDec};
+use super::tokenizer::{Token, TokenKind};
+
+// Same layout as the project 10 comparison files: one element per line, children
+// indented by two spaces, and empty elements written as an open and a close tag
+
+pub fn tokens_xml(tokens: &[Token]) -> Vec<String> {
+    let mut lines = vec![String::from("<tokens>")];
+
+    for token in tokens {
+        lines.push(element(tag(token.kind), &token.text));
+    }
+
+    lines.push(String::from("</tokens>"));
+    lines
+}
+
+pub fn parse_xml(class: &Class) -> Vec<String> {
+    let mut writer = XmlWriter {
+        lines: Vec::new(),
+        depth: 0,
+    };
+
+    writer.class(class);
+    writer.lines
+}
+
+fn tag(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Keyword => "keyword",
+        TokenKind::Symbol => "symbol",
+        TokenKind::IntegerConstant => "integerConstant",
+        TokenKind::StringConstant => "stringConstant",
+        TokenKind::Identifier => "identifier",
+    }
+}
+
+fn element(tag: &str, text: &str) -> String {
+    let text = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+
+    format!("<{}> {} </{}>", tag, text, tag)
+}
+
+struct XmlWriter {
+    lines: Vec<String>,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn line(&mut self, value: String) {
+        self.lines
+            .push(format!("{}{}", "  ".repeat(self.depth), value));
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.line(format!("<{}>", tag));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.line(format!("</{}>", tag));
+    }
+
+    fn keyword(&mut self, text: &str) {
+        self.line(element("keyword", text));
+    }
+
+    fn symbol(&mut self, text: &str) {
+        self.line(element("symbol", text));
+    }
+
+    fn identifier(&mut self, text: &str) {
+        self.line(element("identifier", text));
+    }
+
+    // int, char, boolean and void are keywords, class names identifiers
+    fn type_name(&mut self, text: &str) {
+        match text {
+            "int" | "char" | "boolean" | "void" => self.keyword(text),
+            _ => self.identifier(text),
+        }
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.open("class");
+        self.keyword("class");
+        self.identifier(&class.name);
+        self.symbol("{");
+
+        for variables in &class.variables {
+            self.var_dec("classVarDec", variables);
+        }
+
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+
+        self.symbol("}");
+        self.close("class");
+    }
+
+    fn var_dec(&mut self, tag: &str, variables: &VarDec) {
+        self.open(tag);
+        self.keyword(&variables.kind);
+        self.type_name(&variables.type_name);
+
+        for (index, name) in variables.names.iter().enumerate() {
+            if index > 0 {
+                self.symbol(",");
+            }
+            self.identifier(name);
+        }
+
+        self.symbol(";");
+        self.close(tag);
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.open("subroutineDec");
+        self.keyword(&subroutine.kind);
+        self.type_name(&subroutine.return_type);
+        self.identifier(&subroutine.name);
+        self.symbol("(");
+
+        self.open("parameterList");
+        for (index, parameter) in subroutine.parameters.iter().enumerate() {
+            if index > 0 {
+                self.symbol(",");
+            }
+            self.type_name(&parameter.type_name);
+            self.identifier(&parameter.name);
+        }
+        self.close("parameterList");
+
+        self.symbol(")");
+        self.open("subroutineBody");
+        self.symbol("{");
+
+        for variables in &subroutine.locals {
+            self.var_dec("varDec", variables);
+        }
+
+        self.statements(&subroutine.statements);
+        self.symbol("}");
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        self.open("statements");
+
+        for statement in statements {
+            self.statement(&statement.kind);
+        }
+
+        self.close("statements");
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.symbol("{");
+        self.statements(statements);
+        self.symbol("}");
+    }
+
+    fn condition(&mut self, condition: &Expression) {
+        self.symbol("(");
+        self.expression(condition);
+        self.symbol(")");
+    }
+
+    fn statement(&mut self, statement: &StatementKind) {
+        match statement {
+            StatementKind::Let { name, index, value } => {
+                self.open("letStatement");
+                self.keyword("let");
+                self.identifier(name);
+
+                if let Some(index) = index {
+                    self.symbol("[");
+                    self.expression(index);
+                    self.symbol("]");
+                }
+
+                self.symbol("=");
+                self.expression(value);
+                self.symbol(";");
+                self.close("letStatement");
+            }
+            StatementKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.open("ifStatement");
+                self.keyword("if");
+                self.condition(condition);
+                self.block(then);
+
+                if let Some(otherwise) = otherwise {
+                    self.keyword("else");
+                    self.block(otherwise);
+                }
+
+                self.close("ifStatement");
+            }
+            StatementKind::While { condition, body } => {
+                self.open("whileStatement");
+                self.keyword("while");
+                self.condition(condition);
+                self.block(body);
+                self.close("whileStatement");
+            }
+            StatementKind::Do(call) => {
+                self.open("doStatement");
+                self.keyword("do");
+                self.call(call);
+                self.symbol(";");
+                self.close("doStatement");
+            }
+            StatementKind::Return(value) => {
+                self.open("returnStatement");
+                self.keyword("return");
+
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+
+                self.symbol(";");
+                self.close("returnStatement");
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.term(&expression.first);
+
+        for (operator, term) in &expression.rest {
+            self.symbol(&operator.to_string());
+            self.term(term);
+        }
+
+        self.close("expression");
+    }
+
+    fn term(&mut self, term: &Term) {
+        self.open("term");
+
+        match term {
+            Term::Integer(value) => self.line(element("integerConstant", value)),
+            Term::String(value) => self.line(element("stringConstant", value)),
+            Term::Keyword(keyword) => self.keyword(keyword),
+            Term::Variable(name) => self.identifier(name),
+            Term::Index(name, index) => {
+                self.identifier(name);
+                self.symbol("[");
+                self.expression(index);
+                self.symbol("]");
+            }
+            Term::Call(call) => self.call(call),
+            Term::Parenthesized(expression) => {
+                self.symbol("(");
+                self.expression(expression);
+                self.symbol(")");
+            }
+            Term::Unary(operator, term) => {
+                self.symbol(&operator.to_string());
+                self.term(term);
+            }
+        }
+
+        self.close("term");
+    }
+
+    fn call(&mut self, call: &Call) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(receiver);
+            self.symbol(".");
+        }
+
+        self.identifier(&call.name);
+        self.symbol("(");
+        self.open("expressionList");
+
+        for (index, argument) in call.arguments.iter().enumerate() {
+            if index > 0 {
+                self.symbol(",");
+            }
+            self.expression(argument);
+        }
+
+        self.close("expressionList");
+        self.symbol(")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jack::emit;
+
+    const SOURCE: &str = "// a comment\nclass Main {\n  function void main() {\n    let x = 1 < \"a&b\";\n    do Sys.halt();\n    return;\n  }\n}\n";
+
+    fn emitted(kind: &str) -> String {
+        emit("Main.jack", SOURCE, kind)
+            .unwrap_or_else(|diagnostic| panic!("{}", diagnostic.message))
+    }
+
+    // the course files end every line, the last one too, in CRLF
+    fn crlf(lines: &[&str]) -> String {
+        lines.iter().map(|line| format!("{}\r\n", line)).collect()
+    }
+
+    #[test]
+    fn tokens_match_the_course_t_xml() {
+        assert_eq!(
+            emitted("tokens-xml"),
+            crlf(&[
+                "<tokens>",
+                "<keyword> class </keyword>",
+                "<identifier> Main </identifier>",
+                "<symbol> { </symbol>",
+                "<keyword> function </keyword>",
+                "<keyword> void </keyword>",
+                "<identifier> main </identifier>",
+                "<symbol> ( </symbol>",
+                "<symbol> ) </symbol>",
+                "<symbol> { </symbol>",
+                "<keyword> let </keyword>",
+                "<identifier> x </identifier>",
+                "<symbol> = </symbol>",
+                "<integerConstant> 1 </integerConstant>",
+                "<symbol> &lt; </symbol>",
+                "<stringConstant> a&amp;b </stringConstant>",
+                "<symbol> ; </symbol>",
+                "<keyword> do </keyword>",
+                "<identifier> Sys </identifier>",
+                "<symbol> . </symbol>",
+                "<identifier> halt </identifier>",
+                "<symbol> ( </symbol>",
+                "<symbol> ) </symbol>",
+                "<symbol> ; </symbol>",
+                "<keyword> return </keyword>",
+                "<symbol> ; </symbol>",
+                "<symbol> } </symbol>",
+                "<symbol> } </symbol>",
+                "</tokens>",
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_tree_matches_the_course_xml() {
+        assert_eq!(
+            emitted("parse-xml"),
+            crlf(&[
+                "<class>",
+                "  <keyword> class </keyword>",
+                "  <identifier> Main </identifier>",
+                "  <symbol> { </symbol>",
+                "  <subroutineDec>",
+                "    <keyword> function </keyword>",
+                "    <keyword> void </keyword>",
+                "    <identifier> main </identifier>",
+                "    <symbol> ( </symbol>",
+                "    <parameterList>",
+                "    </parameterList>",
+                "    <symbol> ) </symbol>",
+                "    <subroutineBody>",
+                "      <symbol> { </symbol>",
+                "      <statements>",
+                "        <letStatement>",
+                "          <keyword> let </keyword>",
+                "          <identifier> x </identifier>",
+                "          <symbol> = </symbol>",
+                "          <expression>",
+                "            <term>",
+                "              <integerConstant> 1 </integerConstant>",
+                "            </term>",
+                "            <symbol> &lt; </symbol>",
+                "            <term>",
+                "              <stringConstant> a&amp;b </stringConstant>",
+                "            </term>",
+                "          </expression>",
+                "          <symbol> ; </symbol>",
+                "        </letStatement>",
+                "        <doStatement>",
+                "          <keyword> do </keyword>",
+                "          <identifier> Sys </identifier>",
+                "          <symbol> . </symbol>",
+                "          <identifier> halt </identifier>",
+                "          <symbol> ( </symbol>",
+                "          <expressionList>",
+                "          </expressionList>",
+                "          <symbol> ) </symbol>",
+                "          <symbol> ; </symbol>",
+                "        </doStatement>",
+                "        <returnStatement>",
+                "          <keyword> return </keyword>",
+                "          <symbol> ; </symbol>",
+                "        </returnStatement>",
+                "      </statements>",
+                "      <symbol> } </symbol>",
+                "    </subroutineBody>",
+                "  </subroutineDec>",
+                "  <symbol> } </symbol>",
+                "</class>",
+            ])
+        );
+    }
+
+    #[test]
+    fn symbols_and_strings_are_escaped() {
+        let source =
+            "class Main { function void main() { let x = (a > b) | (c = \"<&>\"); return; } }";
+        let tokens = emit("Main.jack", source, "tokens-xml")
+            .unwrap_or_else(|diagnostic| panic!("{}", diagnostic.message));
+
+        assert!(tokens.contains("<symbol> &gt; </symbol>\r\n"));
+        assert!(tokens.contains("<symbol> | </symbol>\r\n"));
+        assert!(tokens.contains("<stringConstant> &lt;&amp;&gt; </stringConstant>\r\n"));
+    }
+}
@@ -0,0 +1,325 @@
+// just enough JSON to write diagnostics for other tools
+
+pub fn string(value: &str) -> String {
+    let mut result = String::from("\"");
+
+    for character in value.chars() {
+        match character {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            character if (character as u32) < 0x20 => {
+                result.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => result.push(character),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+// fields are written in the order given
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}:{}", string(name), value))
+        .collect();
+
+    format!("{{{}}}", fields.join(","))
+}
+
+pub fn array(values: &[String]) -> String {
+    format!("[{}]", values.join(","))
+}
+
+// parsed JSON, with object fields kept in their order
+#[derive(Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // follows a path of object fields, like `params.textDocument.uri`
+    pub fn at(&self, path: &str) -> Option<&Value> {
+        path.split('.')
+            .try_fold(self, |value, name| value.get(name))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Value::Number(value) if *value >= 0.0 => Some(*value as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self {
+            Value::Null => String::from("null"),
+            Value::Bool(value) => value.to_string(),
+            Value::Number(value) => value.to_string(),
+            Value::String(value) => string(value),
+            Value::Array(values) => {
+                array(&values.iter().map(Value::to_json).collect::<Vec<String>>())
+            }
+            Value::Object(fields) => format!(
+                "{{{}}}",
+                fields
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}", string(name), value.to_json()))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        characters: text.chars().collect(),
+        position: 0,
+    };
+
+    let value = parser.value()?;
+    parser.whitespace();
+
+    if parser.position < parser.characters.len() {
+        return Err(format!("unexpected text at {}", parser.position));
+    }
+
+    Ok(value)
+}
+
+struct Parser {
+    characters: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn whitespace(&mut self) {
+        while self.peek().map(char::is_whitespace) == Some(true) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.characters.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let character = self.peek().ok_or("unexpected end of input")?;
+        self.position += 1;
+        Ok(character)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next()? {
+            character if character == expected => Ok(()),
+            character => Err(format!(
+                "expected `{}` but found `{}` at {}",
+                expected,
+                character,
+                self.position - 1
+            )),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, String> {
+        for expected in keyword.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+
+        match self.peek().ok_or("unexpected end of input")? {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => Ok(Value::String(self.string()?)),
+            't' => self.keyword("true", Value::Bool(true)),
+            'f' => self.keyword("false", Value::Bool(false)),
+            'n' => self.keyword("null", Value::Null),
+            _ => self.number(),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        self.expect('{')?;
+        self.whitespace();
+
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Value::Object(fields));
+        }
+
+        loop {
+            self.whitespace();
+            let name = self.string()?;
+            self.whitespace();
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+            self.whitespace();
+
+            match self.next()? {
+                ',' => continue,
+                '}' => return Ok(Value::Object(fields)),
+                character => return Err(format!("unexpected `{}` in object", character)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        let mut values: Vec<Value> = Vec::new();
+        self.expect('[')?;
+        self.whitespace();
+
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+
+            match self.next()? {
+                ',' => continue,
+                ']' => return Ok(Value::Array(values)),
+                character => return Err(format!("unexpected `{}` in array", character)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let mut result = String::new();
+        self.expect('"')?;
+
+        loop {
+            match self.next()? {
+                '"' => return Ok(result),
+                '\\' => match self.next()? {
+                    'n' => result.push('\n'),
+                    'r' => result.push('\r'),
+                    't' => result.push('\t'),
+                    'b' => result.push('\u{8}'),
+                    'f' => result.push('\u{c}'),
+                    'u' => {
+                        let code = self.hex()?;
+
+                        // characters outside the BMP come as a surrogate pair
+                        let code = if (0xD800..0xDC00).contains(&code) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex()?;
+
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(String::from("invalid surrogate pair"));
+                            }
+
+                            0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                        } else {
+                            code
+                        };
+
+                        result.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    character => result.push(character),
+                },
+                character => result.push(character),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4)
+            .map(|_| self.next())
+            .collect::<Result<String, String>>()?;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape \\u{}", digits))
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+
+        while self
+            .peek()
+            .map(|character| character.is_ascii_digit() || "+-.eE".contains(character))
+            == Some(true)
+        {
+            self.position += 1;
+        }
+
+        let text: String = self.characters[start..self.position].iter().collect();
+        text.parse()
+            .map(Value::Number)
+            .map_err(|_| format!("invalid value at {}", start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(
+            string("say \"hi\"\\ C:\\dir\nnext\r\tend\u{1}"),
+            "\"say \\\"hi\\\"\\\\ C:\\\\dir\\nnext\\r\\tend\\u0001\""
+        );
+    }
+
+    #[test]
+    fn objects_keep_their_field_order_and_parse_back() {
+        let text = object(&[
+            ("b", string("x\"y")),
+            ("a", array(&[String::from("1"), String::from("null")])),
+        ]);
+
+        assert_eq!(text, "{\"b\":\"x\\\"y\",\"a\":[1,null]}");
+
+        let value = parse(&text).unwrap();
+        assert_eq!(value.to_json(), text);
+        assert_eq!(value.get("b").and_then(Value::as_str), Some("x\"y"));
+    }
+
+    #[test]
+    fn escapes_and_surrogate_pairs_are_decoded() {
+        let value = parse("\"a\\\\b\\n\\u00e9\\ud83d\\ude00\"").unwrap();
+
+        assert_eq!(value.as_str(), Some("a\\b\né\u{1F600}"));
+    }
+}
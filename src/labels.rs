@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::{closest, Diagnostic};
+use crate::program::{VmCommand, VmFile};
+
+// labels are scoped per function by build_label, so each function is resolved on its own
+pub fn check_labels(files: &[VmFile]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for file in files {
+        let top_level = &file.commands[..file.top_level_end()];
+
+        for command in top_level
+            .iter()
+            .filter(|command| command.command == "label")
+        {
+            diagnostics.push(
+                Diagnostic::warning(
+                    "label-outside-function",
+                    format!("label {} is not inside any function", command.detail),
+                )
+                .at(&file.path, command.line)
+                .note(format!(
+                    "it is emitted as ${} and shared with every other file",
+                    command.detail
+                )),
+            );
+        }
+
+        diagnostics.extend(check_scope(&file.path, top_level));
+
+        for function in file.functions() {
+            diagnostics.extend(check_scope(
+                &file.path,
+                &file.commands[function.start..function.end],
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+fn check_scope(path: &str, commands: &[VmCommand]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut labels: HashMap<&str, &VmCommand> = HashMap::new();
+
+    for command in commands.iter().filter(|command| command.command == "label") {
+        match labels.get(command.detail.as_str()) {
+            Some(previous) => diagnostics.push(
+                Diagnostic::error(
+                    "duplicate-label",
+                    format!("label {} is defined more than once", command.detail),
+                )
+                .at(path, command.line)
+                .note(format!("first defined at {}:{}", path, previous.line)),
+            ),
+            None => {
+                labels.insert(&command.detail, command);
+            }
+        }
+    }
+
+    let jumps: Vec<&VmCommand> = commands
+        .iter()
+        .filter(|command| command.command == "goto" || command.command == "if-goto")
+        .collect();
+
+    for jump in &jumps {
+        if !labels.contains_key(jump.detail.as_str()) {
+            let mut diagnostic = Diagnostic::error(
+                "undefined-label",
+                format!(
+                    "{} target {} is not defined in this function",
+                    jump.command, jump.detail
+                ),
+            )
+            .at(path, jump.line);
+
+            if let Some(suggestion) = closest(&jump.detail, labels.keys().copied()) {
+                diagnostic = diagnostic.suggest(
+                    format!("did you mean {}?", suggestion),
+                    format!("{} {}", jump.command, suggestion),
+                );
+            }
+
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    let mut unused: Vec<&&VmCommand> = labels
+        .values()
+        .filter(|label| !jumps.iter().any(|jump| jump.detail == label.detail))
+        .collect();
+    unused.sort_by_key(|label| label.line);
+
+    for label in unused {
+        diagnostics.push(
+            Diagnostic::warning(
+                "unused-label",
+                format!("label {} is never jumped to", label.detail),
+            )
+            .at(path, label.line),
+        );
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::vm_file;
+
+    // the code and line of every diagnostic
+    fn findings(source: &str) -> Vec<(&'static str, usize)> {
+        check_labels(&[vm_file("Main", source)])
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.location.as_ref().unwrap().line))
+            .collect()
+    }
+
+    #[test]
+    fn jumps_to_labels_of_their_function_are_fine() {
+        let source = "function Main.f 0\nlabel LOOP\npush constant 0\nif-goto END\ngoto LOOP\nlabel END\npush constant 0\nreturn\n";
+
+        assert!(findings(source).is_empty());
+    }
+
+    #[test]
+    fn an_undefined_goto_target_suggests_the_closest_label() {
+        let source = "function Main.f 0\nlabel LOOP\ngoto LOPO\ngoto LOOP\n";
+        let diagnostics = check_labels(&[vm_file("Main", source)]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "undefined-label");
+        assert_eq!(diagnostics[0].location.as_ref().unwrap().line, 3);
+
+        let suggestion = diagnostics[0].suggestion.as_ref().unwrap();
+        assert_eq!(suggestion.replacement, "goto LOOP");
+    }
+
+    #[test]
+    fn a_label_defined_twice_in_a_function_is_an_error() {
+        let source = "function Main.f 0\nlabel LOOP\nlabel LOOP\ngoto LOOP\n";
+
+        assert_eq!(findings(source), [("duplicate-label", 3)]);
+    }
+
+    #[test]
+    fn the_same_label_in_two_functions_is_fine() {
+        let source =
+            "function Main.f 0\nlabel LOOP\ngoto LOOP\nfunction Main.g 0\nlabel LOOP\ngoto LOOP\n";
+
+        assert!(findings(source).is_empty());
+    }
+
+    #[test]
+    fn a_label_of_another_function_is_not_a_target() {
+        let source = "function Main.f 0\nlabel DONE\npush constant 0\nreturn\nfunction Main.g 0\ngoto DONE\n";
+
+        assert_eq!(
+            findings(source),
+            [("unused-label", 2), ("undefined-label", 6)]
+        );
+    }
+
+    #[test]
+    fn labels_outside_functions_and_never_jumped_to_are_warnings() {
+        let source = "label START\nfunction Main.f 0\nlabel UNUSED\npush constant 0\nreturn\n";
+
+        assert_eq!(
+            findings(source),
+            [
+                ("label-outside-function", 1),
+                ("unused-label", 1),
+                ("unused-label", 3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::{closest, Diagnostic};
+use crate::os::bundled_source;
+use crate::program::VmFile;
+
+struct Site<'a> {
+    file: &'a str,
+    line: usize,
+    arguments: &'a str,
+}
+
+// checks the calls of the whole program against the functions it defines
+pub fn link(files: &[VmFile], bootstrap_entry: Option<&str>) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut definitions: HashMap<&str, Site> = HashMap::new();
+    let mut calls: Vec<(&str, Site)> = Vec::new();
+
+    for file in files {
+        for command in &file.commands {
+            let site = Site {
+                file: &file.path,
+                line: command.line,
+                arguments: &command.value,
+            };
+
+            match command.command.as_str() {
+                "function" => {
+                    if let Some(previous) = definitions.get(command.detail.as_str()) {
+                        // bundled files point at the lines of their sources
+                        let note = if previous.file == site.file && previous.line == site.line {
+                            format!(
+                                "{} is loaded more than once, directly or through a .vmb bundle",
+                                site.file
+                            )
+                        } else {
+                            format!("first defined at {}:{}", previous.file, previous.line)
+                        };
+
+                        diagnostics.push(
+                            Diagnostic::error(
+                                "duplicate-function",
+                                format!("function {} is defined more than once", command.detail),
+                            )
+                            .at(site.file, site.line)
+                            .note(note),
+                        );
+                    } else {
+                        definitions.insert(&command.detail, site);
+                    }
+                }
+                "call" => calls.push((&command.detail, site)),
+                _ => (),
+            }
+        }
+    }
+
+    if let Some(entry) = bootstrap_entry {
+        if !definitions.contains_key(entry) {
+            diagnostics.push(Diagnostic::error(
+                "undefined-function",
+                format!("bootstrap code calls {} but no file defines it", entry),
+            ));
+        }
+    }
+
+    let mut first_calls: HashMap<&str, &Site> = HashMap::new();
+    let mut incomplete: Vec<&str> = Vec::new();
+
+    for (name, site) in &calls {
+        if !definitions.contains_key(name) {
+            if let Some(diagnostic) = replaced_os_class(name, site, &definitions) {
+                // once for each missing function, not for every call the OS makes to it
+                if !incomplete.contains(name) {
+                    incomplete.push(name);
+                    diagnostics.push(diagnostic);
+                }
+                continue;
+            }
+
+            let mut diagnostic = Diagnostic::error(
+                "undefined-function",
+                format!("call to undefined function {}", name),
+            )
+            .at(site.file, site.line);
+
+            if let Some(suggestion) = closest(name, definitions.keys().copied()) {
+                diagnostic = diagnostic.suggest(
+                    format!("did you mean {}?", suggestion),
+                    format!("call {} {}", suggestion, site.arguments)
+                        .trim_end()
+                        .to_string(),
+                );
+            }
+
+            diagnostics.push(diagnostic);
+            continue;
+        }
+
+        match first_calls.get(name) {
+            Some(first) if first.arguments != site.arguments => diagnostics.push(
+                Diagnostic::warning(
+                    "arity-mismatch",
+                    format!(
+                        "{} is called with {} arguments here but with {} elsewhere",
+                        name, site.arguments, first.arguments
+                    ),
+                )
+                .at(site.file, site.line)
+                .note(format!(
+                    "called with {} arguments at {}:{}",
+                    first.arguments, first.file, first.line
+                )),
+            ),
+            Some(_) => (),
+            None => {
+                first_calls.insert(name, site);
+            }
+        }
+    }
+
+    diagnostics
+}
+
+// A class of the program replaces the bundled OS class of that name as a whole, so the
+// bundled classes may call functions it leaves out. That is reported against the
+// program's class, the only place it can be fixed.
+fn replaced_os_class(
+    name: &str,
+    site: &Site,
+    definitions: &HashMap<&str, Site>,
+) -> Option<Diagnostic> {
+    bundled_source(site.file)?;
+
+    let class = name.split('.').next().unwrap_or(name);
+    let replacement = definitions
+        .iter()
+        .filter(|(function, definition)| {
+            function.split('.').next() == Some(class) && bundled_source(definition.file).is_none()
+        })
+        .map(|(_, definition)| (definition.file, definition.line))
+        .min()?;
+
+    Some(
+        Diagnostic::error(
+            "incomplete-os-class",
+            format!(
+                "{} replaces the OS class {} but does not define {}",
+                replacement.0, class, name
+            ),
+        )
+        .at(replacement.0, replacement.1)
+        .note(format!("the OS calls it at {}:{}", site.file, site.line)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Severity;
+    use crate::test_programs::vm_file;
+
+    const MAIN: &str = "function Main.main 0\npush constant 1\ncall Main.double 1\nreturn\nfunction Main.double 0\npush argument 0\npush argument 0\nadd\nreturn\n";
+
+    // the code, file and line of every diagnostic
+    fn located(diagnostics: &[Diagnostic]) -> Vec<(&'static str, String, usize)> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let location = diagnostic.location.as_ref().unwrap();
+                (diagnostic.code, location.file.clone(), location.line)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_complete_program_links_cleanly() {
+        assert!(link(&[vm_file("Main", MAIN)], None).is_empty());
+    }
+
+    #[test]
+    fn a_call_to_an_undefined_function_suggests_the_closest_one() {
+        let files = [vm_file(
+            "Main",
+            "function Main.main 0\npush constant 1\ncall Main.doubel 1\nreturn\nfunction Main.double 0\npush argument 0\nreturn\n",
+        )];
+
+        let diagnostics = link(&files, None);
+
+        assert_eq!(
+            located(&diagnostics),
+            [("undefined-function", String::from("Main.vm"), 3)]
+        );
+
+        let suggestion = diagnostics[0].suggestion.as_ref().unwrap();
+        assert_eq!(suggestion.message, "did you mean Main.double?");
+        assert_eq!(suggestion.replacement, "call Main.double 1");
+    }
+
+    #[test]
+    fn a_function_defined_twice_points_at_the_first_definition() {
+        let files = [
+            vm_file("Main", MAIN),
+            vm_file("Other", "function Main.double 0\npush constant 0\nreturn\n"),
+        ];
+
+        let diagnostics = link(&files, None);
+
+        assert_eq!(
+            located(&diagnostics),
+            [("duplicate-function", String::from("Other.vm"), 1)]
+        );
+        assert_eq!(diagnostics[0].notes, ["first defined at Main.vm:5"]);
+    }
+
+    #[test]
+    fn a_missing_bootstrap_entry_is_a_program_wide_error() {
+        let diagnostics = link(&[vm_file("Main", MAIN)], Some("Sys.init"));
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "undefined-function");
+        assert!(diagnostics[0].location.is_none());
+        assert!(diagnostics[0].message.contains("Sys.init"));
+    }
+
+    #[test]
+    fn calls_with_different_argument_counts_warn_at_the_later_one() {
+        let files = [
+            vm_file("Main", MAIN),
+            vm_file(
+                "Other",
+                "function Other.run 0\npush constant 1\npush constant 2\ncall Main.double 2\nreturn\n",
+            ),
+        ];
+
+        let diagnostics = link(&files, None);
+
+        assert_eq!(
+            located(&diagnostics),
+            [("arity-mismatch", String::from("Other.vm"), 4)]
+        );
+        assert!(diagnostics[0].severity == Severity::Warning);
+        assert_eq!(
+            diagnostics[0].notes,
+            ["called with 1 arguments at Main.vm:3"]
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::program::{VmCommand, VmFile};
+
+pub const RULES: [&str; 6] = [
+    "unreachable-code",
+    "missing-return",
+    "pointer-round-trip",
+    "unread-temp",
+    "uninitialized-local",
+    "unused-static",
+];
+
+// Findings of the enabled rules as warnings, or errors for the denied ones. None of
+// them stop a program from running; they point at code that is probably not what its
+// author meant.
+pub fn lint(files: &[VmFile], enabled: &[String], denied: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for file in files {
+        let mut found: Vec<Diagnostic> = Vec::new();
+
+        found.extend(unreachable_code(file));
+        found.extend(missing_return(file));
+        found.extend(pointer_round_trip(file));
+        found.extend(uninitialized_locals(file));
+        found.extend(unused_statics(file));
+        found.extend(unread_temps(files, file));
+
+        found.sort_by_key(|diagnostic| diagnostic.location.as_ref().map(|location| location.line));
+        diagnostics.extend(found);
+    }
+
+    diagnostics.retain(|diagnostic| enabled.iter().any(|rule| rule == diagnostic.code));
+
+    for diagnostic in diagnostics.iter_mut() {
+        if denied.iter().any(|rule| rule == diagnostic.code) {
+            diagnostic.severity = Severity::Error;
+        }
+    }
+
+    diagnostics
+}
+
+// the top level and every function, without the function commands themselves
+fn scopes(file: &VmFile) -> Vec<&[VmCommand]> {
+    let mut scopes = vec![&file.commands[..file.top_level_end()]];
+
+    for function in file.functions() {
+        scopes.push(&file.commands[function.start + 1..function.end]);
+    }
+
+    scopes
+}
+
+// nothing jumps to the commands between a goto or return and the next label
+fn unreachable_code(file: &VmFile) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for scope in scopes(file) {
+        let mut after: Option<&VmCommand> = None;
+
+        for command in scope {
+            if command.command == "label" {
+                after = None;
+                continue;
+            }
+
+            if let Some(jump) = after.take() {
+                diagnostics.push(
+                    Diagnostic::warning("unreachable-code", format!("`{}` can never run", command))
+                        .at(&file.path, command.line)
+                        .note(format!(
+                            "it follows `{}` on line {} with no label in between",
+                            jump, jump.line
+                        )),
+                );
+                continue;
+            }
+
+            if command.command == "goto" || command.command == "return" {
+                after = Some(command);
+            }
+        }
+    }
+
+    diagnostics
+}
+
+// a function that ends with anything but return or goto runs on into the next one
+fn missing_return(file: &VmFile) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for function in file.functions() {
+        let last = &file.commands[function.end - 1];
+
+        if last.command != "return" && last.command != "goto" {
+            diagnostics.push(
+                Diagnostic::warning(
+                    "missing-return",
+                    format!("function {} does not end with return", function.name),
+                )
+                .at(&file.path, file.commands[function.start].line)
+                .note(String::from(
+                    "execution continues into whatever code follows it",
+                )),
+            );
+        }
+    }
+
+    diagnostics
+}
+
+fn pointer_round_trip(file: &VmFile) -> Vec<Diagnostic> {
+    file.commands
+        .windows(2)
+        .filter(|pair| {
+            pair[0].command == "pop"
+                && pair[1].command == "push"
+                && pair[0].detail == "pointer"
+                && pair[1].detail == "pointer"
+                && pair[0].value == pair[1].value
+        })
+        .map(|pair| {
+            Diagnostic::warning(
+                "pointer-round-trip",
+                format!(
+                    "`{}` reads back the value `{}` just stored",
+                    pair[1], pair[0]
+                ),
+            )
+            .at(&file.path, pair[1].line)
+        })
+        .collect()
+}
+
+// Locals start at 0, so reading one first is legal but usually a missing `let`. A
+// local counts as written at a command only when it is written on every path there.
+fn uninitialized_locals(file: &VmFile) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for function in file.functions() {
+        let body = &file.commands[function.start + 1..function.end];
+
+        // the locals written on every jump seen so far to each label, until nothing changes
+        let mut at_labels: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        let reads: Vec<&VmCommand> = loop {
+            let (jumps, reads) = written_locals(body, &at_labels);
+
+            if jumps == at_labels {
+                break reads;
+            }
+
+            at_labels = jumps;
+        };
+
+        let mut reported: BTreeSet<&str> = BTreeSet::new();
+
+        for read in reads {
+            if reported.insert(&read.value) {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        "uninitialized-local",
+                        format!(
+                            "local {} of {} is read before anything is written to it",
+                            read.value, function.name
+                        ),
+                    )
+                    .at(&file.path, read.line)
+                    .note(String::from("locals start at 0 on every call")),
+                );
+            }
+        }
+    }
+
+    diagnostics
+}
+
+// one pass over a function body: the written locals each label is jumped to with and
+// the reads of locals not written yet
+fn written_locals<'a>(
+    body: &'a [VmCommand],
+    at_labels: &HashMap<&'a str, BTreeSet<&'a str>>,
+) -> (HashMap<&'a str, BTreeSet<&'a str>>, Vec<&'a VmCommand>) {
+    let mut jumps: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    let mut reads: Vec<&VmCommand> = Vec::new();
+    // None once nothing can fall through to the next command
+    let mut written: Option<BTreeSet<&str>> = Some(BTreeSet::new());
+
+    let meet =
+        |left: Option<BTreeSet<&'a str>>, right: Option<&BTreeSet<&'a str>>| match (left, right) {
+            (Some(left), Some(right)) => Some(left.intersection(right).cloned().collect()),
+            (Some(left), None) => Some(left),
+            (None, right) => right.cloned(),
+        };
+
+    for command in body {
+        match command.command.as_str() {
+            "label" => written = meet(written, at_labels.get(command.detail.as_str())),
+            "goto" | "if-goto" => {
+                if let Some(jump) = meet(written.clone(), jumps.get(command.detail.as_str())) {
+                    jumps.insert(&command.detail, jump);
+                }
+
+                if command.command == "goto" {
+                    written = None;
+                }
+            }
+            "return" => written = None,
+            "push" if command.detail == "local" => match &written {
+                Some(locals) if !locals.contains(command.value.as_str()) => reads.push(command),
+                _ => (),
+            },
+            "pop" if command.detail == "local" => {
+                if let Some(locals) = written.as_mut() {
+                    locals.insert(&command.value);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    (jumps, reads)
+}
+
+// statics are private to their file, so one that is never read there is never read
+fn unused_statics(file: &VmFile) -> Vec<Diagnostic> {
+    let read: BTreeSet<&str> = file
+        .commands
+        .iter()
+        .filter(|command| command.command == "push" && command.detail == "static")
+        .map(|command| command.value.as_str())
+        .collect();
+
+    let mut reported: BTreeSet<&str> = BTreeSet::new();
+
+    file.commands
+        .iter()
+        .filter(|command| command.command == "pop" && command.detail == "static")
+        .filter(|command| !read.contains(command.value.as_str()))
+        .filter(|command| reported.insert(&command.value))
+        .map(|command| {
+            Diagnostic::warning(
+                "unused-static",
+                format!(
+                    "static {} of {} is written but never read",
+                    command.value, file.name
+                ),
+            )
+            .at(&file.path, command.line)
+        })
+        .collect()
+}
+
+// temp is shared by the whole program. Popping the result of a call into temp is how
+// Jack discards it, so those writes are not reported.
+fn unread_temps(files: &[VmFile], file: &VmFile) -> Vec<Diagnostic> {
+    let read: BTreeSet<&str> = files
+        .iter()
+        .flat_map(|file| file.commands.iter())
+        .filter(|command| command.command == "push" && command.detail == "temp")
+        .map(|command| command.value.as_str())
+        .collect();
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut previous: Option<&VmCommand> = None;
+
+    for command in &file.commands {
+        let discard = previous.map(|previous| previous.command == "call") == Some(true);
+        previous = Some(command);
+
+        if command.command == "pop"
+            && command.detail == "temp"
+            && !discard
+            && !read.contains(command.value.as_str())
+        {
+            diagnostics.push(
+                Diagnostic::warning(
+                    "unread-temp",
+                    format!("temp {} is written but never read", command.value),
+                )
+                .at(&file.path, command.line),
+            );
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::report;
+    use crate::options::Options;
+    use crate::test_programs::vm_file;
+
+    fn all_rules() -> Vec<String> {
+        RULES.iter().map(|rule| rule.to_string()).collect()
+    }
+
+    // the code and line of every finding
+    fn findings(source: &str) -> Vec<(&'static str, usize)> {
+        lint(&[vm_file("Main", source)], &all_rules(), &[])
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.location.as_ref().unwrap().line))
+            .collect()
+    }
+
+    #[test]
+    fn code_after_a_goto_or_return_is_unreachable() {
+        let source = "function Main.f 0\npush constant 0\nreturn\npush constant 1\nlabel L\npush constant 2\nreturn\n";
+
+        assert_eq!(findings(source), [("unreachable-code", 4)]);
+    }
+
+    #[test]
+    fn a_function_without_a_final_return_is_reported() {
+        let source =
+            "function Main.f 0\npush constant 0\nreturn\nfunction Main.g 0\npush constant 0\n";
+
+        assert_eq!(findings(source), [("missing-return", 4)]);
+    }
+
+    #[test]
+    fn a_pointer_read_back_right_after_it_is_stored_is_reported() {
+        let source = "function Main.f 0\npush argument 0\npop pointer 0\npush pointer 0\npush pointer 1\nreturn\n";
+
+        assert_eq!(findings(source), [("pointer-round-trip", 4)]);
+    }
+
+    #[test]
+    fn temps_are_unread_unless_some_file_reads_them_or_they_discard_a_call() {
+        let source = "function Main.f 0\npush constant 1\npop temp 0\npush constant 2\npop temp 1\ncall Main.g 0\npop temp 2\npush temp 1\nreturn\nfunction Main.g 0\npush constant 0\nreturn\n";
+
+        assert_eq!(findings(source), [("unread-temp", 3)]);
+    }
+
+    #[test]
+    fn a_local_read_before_any_write_is_reported_once() {
+        let source = "function Main.f 2\npush local 0\npush local 0\npop local 1\npush local 1\nadd\nreturn\n";
+
+        assert_eq!(findings(source), [("uninitialized-local", 2)]);
+    }
+
+    #[test]
+    fn a_local_written_only_later_in_a_loop_is_read_uninitialized_on_the_first_pass() {
+        let source = "function Main.f 1\nlabel LOOP\npush local 0\npush constant 1\nadd\npop local 0\ngoto LOOP\n";
+
+        assert_eq!(findings(source), [("uninitialized-local", 3)]);
+    }
+
+    #[test]
+    fn a_local_written_on_every_path_into_a_loop_is_not_reported() {
+        let source = "function Main.f 1\npush constant 0\npop local 0\nlabel LOOP\npush local 0\npush constant 1\nadd\npop local 0\ngoto LOOP\n";
+
+        assert!(findings(source).is_empty());
+    }
+
+    #[test]
+    fn a_static_written_but_never_read_is_reported() {
+        let source = "function Main.f 0\npush constant 1\npop static 0\npush constant 2\npop static 1\npush static 1\nreturn\n";
+
+        assert_eq!(findings(source), [("unused-static", 3)]);
+    }
+
+    #[test]
+    fn only_enabled_rules_are_reported() {
+        let source = "function Main.f 1\npush local 0\npop static 0\n";
+        let enabled = [String::from("unused-static")];
+
+        let diagnostics = lint(&[vm_file("Main", source)], &enabled, &[]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "unused-static");
+    }
+
+    #[test]
+    fn deny_turns_a_rule_into_an_error_that_fails_the_run() {
+        let args: Vec<String> = ["vm-translator", "Main.vm", "--deny", "unused-static"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = Options::from_args(&args);
+        let files = [vm_file(
+            "Main",
+            "function Main.f 0\npush constant 1\npop static 0\npush constant 0\nreturn\n",
+        )];
+
+        let diagnostics = lint(&files, options.lint.as_ref().unwrap(), &options.deny);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].severity == Severity::Error);
+        assert!(report(&diagnostics));
+
+        // the same finding only warns without --deny
+        let warnings = lint(&files, options.lint.as_ref().unwrap(), &[]);
+        assert!(!report(&warnings));
+    }
+}
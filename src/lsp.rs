@@ -0,0 +1,662 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::builder::build_content;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::json::{self, Value};
+use crate::labels::check_labels;
+use crate::linker::link;
+use crate::os::{link_os, CLASSES};
+use crate::parser::parse_content;
+use crate::program::{VmCommand, VmFile};
+use crate::runtime::EXTENDED_OPERATIONS;
+use crate::size::count_instructions;
+use crate::stack_depth::check_stack_depth;
+use crate::syntax::{check_syntax, ARITHMETIC, COMMANDS, SEGMENTS};
+
+const METHOD_NOT_FOUND: i32 = -32601;
+const PARSE_ERROR: i32 = -32700;
+
+// LSP symbol and completion item kinds
+const FUNCTION_KIND: usize = 12;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_KEYWORD: usize = 14;
+const COMPLETION_REFERENCE: usize = 18;
+
+// Language server for .vm files over stdio. Every open document is checked together
+// with the other .vm files of its folder, preferring the text open in the editor, and
+// with the OS classes it calls linked in, the same way the translator sees a program.
+pub fn serve() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server {
+        documents: BTreeMap::new(),
+    };
+
+    while let Some(body) = read_message(&mut input) {
+        let message = match json::parse(&body) {
+            Ok(message) => message,
+            Err(error) => {
+                send(&error_response(&Value::Null, PARSE_ERROR, &error));
+                continue;
+            }
+        };
+
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        match message.get("id") {
+            _ if method == "exit" => break,
+            Some(id) => send(&match server.request(method, &params) {
+                Ok(result) => json::object(&[
+                    ("jsonrpc", json::string("2.0")),
+                    ("id", id.to_json()),
+                    ("result", result),
+                ]),
+                Err((code, error)) => error_response(id, code, &error),
+            }),
+            None => server.notification(method, &params),
+        }
+    }
+}
+
+struct Server {
+    // open documents by URI
+    documents: BTreeMap<String, String>,
+}
+
+impl Server {
+    fn request(&self, method: &str, params: &Value) -> Result<String, (i32, String)> {
+        let uri = params
+            .at("textDocument.uri")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        let position = (
+            params
+                .at("position.line")
+                .and_then(Value::as_usize)
+                .unwrap_or(0),
+            params
+                .at("position.character")
+                .and_then(Value::as_usize)
+                .unwrap_or(0),
+        );
+
+        match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => Ok(String::from("null")),
+            "textDocument/definition" => Ok(self.definition(uri, position)),
+            "textDocument/hover" => Ok(self.hover(uri, position)),
+            "textDocument/documentSymbol" => Ok(self.symbols(uri)),
+            "textDocument/completion" => Ok(self.completion(uri, position)),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) {
+        let uri = params
+            .at("textDocument.uri")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string();
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.at("textDocument.text").and_then(Value::as_str);
+                self.documents.insert(uri, text.unwrap_or("").to_string());
+            }
+            // the server asks for full document sync, so the last change is the whole text
+            "textDocument/didChange" => {
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str);
+
+                if let Some(text) = text {
+                    self.documents.insert(uri, text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                send(&publish_diagnostics(&uri, ""));
+            }
+            "textDocument/didSave" => (),
+            _ => return,
+        }
+
+        // a change in one file can fix or break calls in the others
+        for uri in self.documents.keys() {
+            send(&publish_diagnostics(uri, &self.diagnostics(uri)));
+        }
+    }
+
+    // clients may encode URIs differently, so open documents are matched by path
+    fn text(&self, path: &str) -> String {
+        match self
+            .documents
+            .iter()
+            .find(|(uri, _)| uri_to_path(uri) == path)
+        {
+            Some((_, text)) => text.clone(),
+            None => fs::read_to_string(path).unwrap_or_default(),
+        }
+    }
+
+    fn file(&self, uri: &str) -> VmFile {
+        let path = uri_to_path(uri);
+
+        VmFile {
+            name: file_stem(&path),
+            path: path.clone(),
+            commands: build_content(self.text(&path)),
+        }
+    }
+
+    // the document, the other .vm files of its folder and the OS classes they call
+    fn program(&self, uri: &str) -> Vec<VmFile> {
+        let path = uri_to_path(uri);
+        let directory = Path::new(&path).parent().unwrap_or_else(|| Path::new("."));
+
+        let mut paths: Vec<String> = fs::read_dir(directory)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path().to_string_lossy().to_string())
+                    .filter(|file_path| file_path.ends_with(".vm"))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for open in self.documents.keys().map(|open| uri_to_path(open)) {
+            if Path::new(&open).parent() == Some(directory) && !paths.contains(&open) {
+                paths.push(open);
+            }
+        }
+
+        paths.sort();
+
+        let mut files: Vec<VmFile> = paths
+            .iter()
+            .map(|file_path| self.file(&path_to_uri(file_path)))
+            .collect();
+
+        let entry = bootstrap_entry(&files);
+        link_os(&mut files, entry, &[]);
+        files
+    }
+
+    fn diagnostics(&self, uri: &str) -> String {
+        let path = uri_to_path(uri);
+        let files = self.program(uri);
+        let entry = bootstrap_entry(&files);
+
+        let mut diagnostics = check_syntax(&files);
+        diagnostics.extend(link(&files, entry));
+        diagnostics.extend(check_labels(&files));
+        diagnostics.extend(check_stack_depth(&files).0);
+
+        let text = self.text(&path);
+
+        let items: Vec<String> = diagnostics
+            .iter()
+            .filter_map(|diagnostic| {
+                let location = diagnostic.location.as_ref()?;
+
+                if location.file != path {
+                    return None;
+                }
+
+                Some(diagnostic_json(
+                    diagnostic,
+                    line_range(&text, location.line),
+                ))
+            })
+            .collect();
+
+        json::array(&items)
+    }
+
+    fn command_at(&self, uri: &str, line: usize) -> Option<VmCommand> {
+        self.file(uri)
+            .commands
+            .into_iter()
+            .find(|command| command.line == line + 1)
+    }
+
+    // calls and functions lead to the function, jumps and labels to the label
+    fn definition(&self, uri: &str, (line, _): (usize, usize)) -> String {
+        let command = match self.command_at(uri, line) {
+            Some(command) => command,
+            None => return String::from("null"),
+        };
+
+        let found = match command.command.as_str() {
+            "call" | "function" => find_function(&self.program(uri), &command.detail)
+                .map(|(file, start, _)| (file.path.clone(), file.commands[start].line)),
+            "goto" | "if-goto" | "label" => {
+                let file = self.file(uri);
+                let (start, end) = scope_of(&file, line + 1);
+
+                file.commands[start..end]
+                    .iter()
+                    .find(|label| label.command == "label" && label.detail == command.detail)
+                    .map(|label| (file.path.clone(), label.line))
+            }
+            _ => None,
+        };
+
+        match found {
+            // OS classes come from the binary and have no file to open
+            Some((path, line)) if Path::new(&path).exists() || path == uri_to_path(uri) => {
+                let text = self.text(&path);
+                json::object(&[
+                    ("uri", json::string(&path_to_uri(&path))),
+                    ("range", line_range(&text, line)),
+                ])
+            }
+            _ => String::from("null"),
+        }
+    }
+
+    fn hover(&self, uri: &str, (line, _): (usize, usize)) -> String {
+        let command = match self.command_at(uri, line) {
+            Some(command) if command.command == "call" || command.command == "function" => command,
+            _ => return String::from("null"),
+        };
+
+        let program = self.program(uri);
+
+        let (file, start, end) = match find_function(&program, &command.detail) {
+            Some(found) => found,
+            None => return String::from("null"),
+        };
+
+        let header = &file.commands[start];
+        let body = VmFile {
+            name: file.name.clone(),
+            path: file.path.clone(),
+            commands: file.commands[start..end].to_vec(),
+        };
+
+        let mut text = format!("```vm\n{}\n```\n{} locals", header, header.value);
+
+        // the code generator only takes commands that pass the syntax check
+        if check_syntax(std::slice::from_ref(&body)).is_empty() {
+            let words = count_instructions(&parse_content(&body.commands, &body.name));
+            text.push_str(&format!(", {} Hack instructions", words));
+        }
+
+        json::object(&[(
+            "contents",
+            json::object(&[
+                ("kind", json::string("markdown")),
+                ("value", json::string(&text)),
+            ]),
+        )])
+    }
+
+    fn symbols(&self, uri: &str) -> String {
+        let file = self.file(uri);
+        let text = self.text(&file.path);
+
+        let symbols: Vec<String> = file
+            .functions()
+            .iter()
+            .map(|function| {
+                let first = file.commands[function.start].line;
+                let last = file.commands[function.end - 1].line;
+
+                json::object(&[
+                    ("name", json::string(&function.name)),
+                    ("kind", FUNCTION_KIND.to_string()),
+                    (
+                        "location",
+                        json::object(&[
+                            ("uri", json::string(uri)),
+                            ("range", lines_range(&text, first, last)),
+                        ]),
+                    ),
+                ])
+            })
+            .collect();
+
+        json::array(&symbols)
+    }
+
+    // commands first, then segments after push and pop, functions after call and
+    // the labels of the enclosing function after goto and if-goto
+    fn completion(&self, uri: &str, (line, character): (usize, usize)) -> String {
+        let text = self.text(&uri_to_path(uri));
+        let current = text.lines().nth(line).unwrap_or("");
+        let before: String = current.chars().take(character).collect();
+
+        let words: Vec<&str> = before.split_whitespace().collect();
+        let position = if before.ends_with(char::is_whitespace) || words.is_empty() {
+            words.len()
+        } else {
+            words.len() - 1
+        };
+
+        let items: Vec<(String, usize)> = match (position, words.first().cloned()) {
+            (0, _) => COMMANDS
+                .iter()
+                .chain(ARITHMETIC.iter())
+                .chain(EXTENDED_OPERATIONS.iter())
+                .map(|command| (command.to_string(), COMPLETION_KEYWORD))
+                .collect(),
+            (1, Some("push")) | (1, Some("pop")) => SEGMENTS
+                .iter()
+                .filter(|segment| words[0] == "push" || **segment != "constant")
+                .map(|segment| (segment.to_string(), COMPLETION_KEYWORD))
+                .collect(),
+            (1, Some("call")) => self
+                .known_functions(uri)
+                .into_iter()
+                .map(|name| (name, COMPLETION_FUNCTION))
+                .collect(),
+            (1, Some("goto")) | (1, Some("if-goto")) => {
+                let file = self.file(uri);
+                let (start, end) = scope_of(&file, line + 1);
+
+                file.commands[start..end]
+                    .iter()
+                    .filter(|command| command.command == "label")
+                    .map(|command| (command.detail.clone(), COMPLETION_REFERENCE))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let items: Vec<String> = items
+            .iter()
+            .map(|(label, kind)| {
+                json::object(&[("label", json::string(label)), ("kind", kind.to_string())])
+            })
+            .collect();
+
+        json::array(&items)
+    }
+
+    // the program's functions, then those of every OS class it does not replace
+    fn known_functions(&self, uri: &str) -> Vec<String> {
+        let mut files = self.program(uri);
+
+        for (class, content) in CLASSES.iter() {
+            if !files.iter().any(|file| file.name == *class) {
+                files.push(VmFile {
+                    name: class.to_string(),
+                    path: String::new(),
+                    commands: build_content(content.to_string()),
+                });
+            }
+        }
+
+        files
+            .iter()
+            .flat_map(|file| file.functions())
+            .map(|function| function.name)
+            .collect()
+    }
+}
+
+fn capabilities() -> String {
+    json::object(&[
+        (
+            "capabilities",
+            json::object(&[
+                ("textDocumentSync", String::from("1")),
+                ("definitionProvider", String::from("true")),
+                ("hoverProvider", String::from("true")),
+                ("documentSymbolProvider", String::from("true")),
+                (
+                    "completionProvider",
+                    json::object(&[("triggerCharacters", json::array(&[json::string(" ")]))]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            json::object(&[("name", json::string("hack_vm_translator"))]),
+        ),
+    ])
+}
+
+fn bootstrap_entry(files: &[VmFile]) -> Option<&'static str> {
+    let defines_sys_init = files.iter().any(|file| {
+        file.commands
+            .iter()
+            .any(|command| command.command == "function" && command.detail == "Sys.init")
+    });
+
+    if defines_sys_init {
+        Some("Sys.init")
+    } else {
+        None
+    }
+}
+
+// the file defining a function with the command range of its body
+fn find_function<'a>(files: &'a [VmFile], name: &str) -> Option<(&'a VmFile, usize, usize)> {
+    files.iter().find_map(|file| {
+        file.functions()
+            .into_iter()
+            .find(|function| function.name == name)
+            .map(|function| (file, function.start, function.end))
+    })
+}
+
+// the commands of the function around a source line, or of the top level before any
+fn scope_of(file: &VmFile, line: usize) -> (usize, usize) {
+    file.functions()
+        .into_iter()
+        .rfind(|function| file.commands[function.start].line <= line)
+        .map(|function| (function.start, function.end))
+        .unwrap_or((0, file.top_level_end()))
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic, range: String) -> String {
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    if let Some(suggestion) = &diagnostic.suggestion {
+        message.push_str(&format!("\nhelp: {}", suggestion.message));
+    }
+
+    json::object(&[
+        ("range", range),
+        ("severity", severity.to_string()),
+        ("code", json::string(diagnostic.code)),
+        ("source", json::string("hack_vm_translator")),
+        ("message", json::string(&message)),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: &str) -> String {
+    let diagnostics = if diagnostics.is_empty() {
+        "[]"
+    } else {
+        diagnostics
+    };
+
+    json::object(&[
+        ("jsonrpc", json::string("2.0")),
+        ("method", json::string("textDocument/publishDiagnostics")),
+        (
+            "params",
+            json::object(&[
+                ("uri", json::string(uri)),
+                ("diagnostics", diagnostics.to_string()),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: &Value, code: i32, message: &str) -> String {
+    json::object(&[
+        ("jsonrpc", json::string("2.0")),
+        ("id", id.to_json()),
+        (
+            "error",
+            json::object(&[
+                ("code", code.to_string()),
+                ("message", json::string(message)),
+            ]),
+        ),
+    ])
+}
+
+// a whole source line; LSP lines count from 0
+fn line_range(text: &str, line: usize) -> String {
+    lines_range(text, line, line)
+}
+
+fn lines_range(text: &str, first: usize, last: usize) -> String {
+    let length = text
+        .lines()
+        .nth(last.saturating_sub(1))
+        .map(|line| line.trim_end().encode_utf16().count())
+        .unwrap_or(0);
+
+    let position = |line: usize, character: usize| {
+        json::object(&[
+            ("line", line.saturating_sub(1).to_string()),
+            ("character", character.to_string()),
+        ])
+    };
+
+    json::object(&[
+        ("start", position(first, 0)),
+        ("end", position(last, length)),
+    ])
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<String> {
+    let mut length: Option<usize> = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+
+        let header = header.trim();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    Some(String::from_utf8_lossy(&body).to_string())
+}
+
+fn send(message: &str) {
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        message.len(),
+        message
+    )
+    .and_then(|_| output.flush())
+    .expect("Something failed on write to the client");
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let encoded = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut characters = encoded.bytes();
+
+    while let Some(byte) = characters.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let digits: Vec<u8> = characters.by_ref().take(2).collect();
+        match std::str::from_utf8(&digits)
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        {
+            Some(decoded) => bytes.push(decoded),
+            None => {
+                bytes.push(b'%');
+                bytes.extend(digits);
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    uri
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///nonexistent/Main.vm";
+
+    fn server(text: &str) -> Server {
+        let mut documents = BTreeMap::new();
+        documents.insert(String::from(URI), String::from(text));
+
+        Server { documents }
+    }
+
+    #[test]
+    fn hover_counts_the_instructions_of_a_valid_function() {
+        let hover = server("function Main.main 1\npush constant 1\nreturn\n").hover(URI, (0, 0));
+
+        assert!(hover.contains("1 locals, "));
+        assert!(hover.contains(" Hack instructions"));
+    }
+
+    #[test]
+    fn hover_leaves_out_the_size_of_a_function_with_syntax_errors() {
+        let server = server("function Main.main 0\npush foo 1\npop constant 0\nreturn\n");
+        let hover = server.hover(URI, (0, 0));
+
+        assert!(hover.contains("0 locals"));
+        assert!(!hover.contains("Hack instructions"));
+        assert!(server.diagnostics(URI).contains("foo"));
+    }
+}
@@ -1,49 +1,620 @@
 use std::fs;
-use std::{env, path::Path};
+use std::io::{self, Write};
+use std::{env, panic, path::Path, process};
 
 mod builder;
+mod c_backend;
+mod codegen;
+mod diagnostics;
+mod disassembler;
+mod formatter;
+mod hack;
+#[cfg(test)]
+mod hack_emulator;
+mod inliner;
+mod jack;
+mod json;
+mod labels;
+mod linker;
+mod lint;
+mod lsp;
+mod math_ops;
+mod optimizer;
+mod options;
+mod os;
 mod parser;
+mod program;
+mod reachability;
+mod riscv_backend;
+mod runtime;
+mod size;
+mod stack_depth;
+mod syntax;
+mod tail_calls;
+#[cfg(test)]
+mod test_programs;
+mod vmb;
+mod wat_backend;
+mod x86_backend;
 
 use crate::builder::build_content;
-use crate::parser::{initial_data, parse_content};
+use crate::codegen::{build_generator, generate};
+use crate::diagnostics::{locate_spans, report, report_json, Diagnostic};
+use crate::disassembler::{disassemble, Symbols};
+use crate::formatter::{first_difference, format_commands, format_source};
+use crate::inliner::{inline_calls, InlinedFunction};
+use crate::jack::{compile_class, emit};
+use crate::labels::check_labels;
+use crate::linker::link;
+use crate::lint::lint;
+use crate::math_ops::lower_math_calls;
+use crate::optimizer::optimize;
+use crate::options::{json_messages, Options};
+use crate::os::{bundled_source, link_os};
+use crate::program::VmFile;
+use crate::reachability::{strip_unreachable, StrippedFunction};
+use crate::size::{check_rom_budget, SizeReport};
+use crate::stack_depth::{check_stack_depth, StackUsage};
+use crate::syntax::check_syntax;
+use crate::tail_calls::mark_tail_calls;
+use crate::vmb::{read_vmb, write_vmb};
+
+const SOURCE_EXTENSIONS: [&str; 3] = [".vmb", ".vm", ".jack"];
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let path = args.get(1).expect("Please supply a folder or file name");
+
+    // whatever stops the translator is a JSON object as well
+    if json_messages(&args) {
+        panic::set_hook(Box::new(|info| {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("the translator stopped unexpectedly"));
+
+            // a closed stdout must not panic again inside the hook
+            let _ = writeln!(
+                io::stdout(),
+                "{}",
+                Diagnostic::error("fatal", message).to_json()
+            );
+        }));
+    }
+
+    let options = Options::from_args(&args);
+    let json = options.message_format == "json";
+
+    if options.lsp {
+        return lsp::serve();
+    }
+
+    let path = options.path.as_str();
     let name = Path::new(path).file_name().unwrap().to_str().unwrap();
 
-    let mut result: Vec<String> = Vec::new();
-    let output: String;
+    let emit_vmb = options.emit.as_deref() == Some("vmb");
 
-    if path.ends_with(".vm") {
-        result.extend(parse_file(path));
-        output = path.replace(".vm", ".asm");
-    } else {
-        result.extend(initial_data());
+    if let Some(emit) = &options.emit {
+        if !emit_vmb {
+            return emit_jack_output(path, emit, json);
+        }
+    }
 
-        let file_list = fs::read_dir(path).unwrap();
+    if options.format || options.check {
+        return format_vm_files(path, &options);
+    }
+
+    if path.ends_with(".hack") {
+        return disassemble_file(path, options.symbols.as_deref(), json);
+    }
+
+    let mut files: Vec<VmFile> = Vec::new();
+    let mut load_diagnostics: Vec<Diagnostic> = Vec::new();
+
+    let bootstrap = !is_source_file(path);
 
-        for file in file_list {
+    let output = if !bootstrap {
+        files.extend(load_files(path, &mut load_diagnostics));
+        strip_extension(path)
+    } else {
+        let mut file_paths: Vec<String> = Vec::new();
+
+        for file in fs::read_dir(path).unwrap() {
             let file_path_buff = file.unwrap().path();
-            let file_path = file_path_buff.to_str().unwrap();
-            let file_name = Path::new(file_path).file_name().unwrap().to_str().unwrap();
+            file_paths.push(String::from(file_path_buff.to_str().unwrap()));
+        }
+
+        for file_path in &file_paths {
+            // a .vm file next to its .jack source is a stale compiler output, and a
+            // bundle being rebuilt must not include its previous version
+            let compiled = file_path.ends_with(".vm")
+                && file_paths.contains(&format!("{}.jack", strip_extension(file_path)));
+            let bundle = emit_vmb && file_path.ends_with(".vmb");
+
+            if !is_source_file(file_path) || compiled || bundle {
+                continue;
+            }
+
+            let loaded = load_files(file_path, &mut load_diagnostics);
 
-            if file_name.ends_with(".vm") {
-                result.extend(parse_file(&file_path));
+            // a bundle built from this folder would load its sources a second time
+            if file_path.ends_with(".vmb") && bundles_sources_of(&loaded, &file_paths) {
+                continue;
             }
+
+            files.extend(loaded);
         }
-        output = format!("{}/{}.asm", path, name);
+        format!("{}/{}", path, name)
+    };
+
+    if report_diagnostics(&mut load_diagnostics, json) {
+        process::exit(1);
+    }
+
+    if emit_vmb {
+        return write_bundle(&files, &output, json);
     }
 
-    fs::write(output, result.join("\r\n")).expect("Something failed on write file to disk");
+    // only the program's own code is linted, not the OS or the lowered math calls
+    let mut lints = match &options.lint {
+        Some(rules) => lint(&files, rules, &options.deny),
+        None => Vec::new(),
+    };
+
+    if options.math_ops {
+        lower_math_calls(&mut files);
+    }
+
+    let bootstrap_entry = if bootstrap { Some("Sys.init") } else { None };
+
+    if options.link_os {
+        link_os(&mut files, bootstrap_entry, &options.no_os_classes);
+    }
+
+    let mut diagnostics = check_syntax(&files);
+    diagnostics.extend(link(&files, bootstrap_entry));
+    diagnostics.extend(check_labels(&files));
+
+    let (stack_diagnostics, stack_usage) = check_stack_depth(&files);
+    diagnostics.extend(stack_diagnostics);
+
+    if options.stack_report {
+        print_report(&stack_usage_report(&stack_usage), json);
+    }
+
+    let lint_failed = if options.lint_format == "json" && !json {
+        report_diagnostics(&mut lints, true)
+    } else {
+        diagnostics.extend(lints);
+        false
+    };
+
+    if report_diagnostics(&mut diagnostics, json) || lint_failed {
+        process::exit(1);
+    }
+
+    // functions whose every call was inlined are left for --strip-dead to remove
+    if let Some(budget) = options.inline {
+        print_report(&inlined_report(&inline_calls(&mut files, budget)), json);
+    }
+
+    if let Some(entry) = &options.strip_dead {
+        match strip_unreachable(&mut files, entry, &options) {
+            Ok(stripped) => print_report(
+                &stripped_report(&stripped, build_generator(&options).size_unit()),
+                json,
+            ),
+            Err(diagnostic) => fail(diagnostic, json),
+        }
+    }
+
+    if options.optimize {
+        optimize(&mut files);
+    }
+
+    if options.tail_calls {
+        mark_tail_calls(&mut files);
+    }
+
+    let mut generator = build_generator(&options);
+    let result = generate(generator.as_mut(), &files, bootstrap);
+
+    if options.target == "hack" {
+        check_hack_output(&result, &options, json);
+    }
+
+    fs::write(
+        format!("{}.{}", output, generator.extension()),
+        result.join("\r\n"),
+    )
+    .expect("Something failed on write file to disk");
 }
 
-fn parse_file(file_path: &str) -> Vec<String> {
-    let content = fs::read_to_string(file_path).expect("Something went wrong reading the file");
+fn check_hack_output(result: &[String], options: &Options, json: bool) {
+    if options.size_report {
+        print_report(
+            &SizeReport::from_asm(result, options.prologue_threshold).to_string(),
+            json,
+        );
+    }
+
+    if let Err(diagnostic) = check_rom_budget(result) {
+        fail(diagnostic, json);
+    }
+}
+
+// Text on stderr or one JSON object per line on stdout, both pointing at the code on
+// the line when its source can be read. Tells whether any of them is an error.
+fn report_diagnostics(diagnostics: &mut [Diagnostic], json: bool) -> bool {
+    locate_spans(diagnostics, &|file| match bundled_source(file) {
+        Some(content) => Some(String::from(content)),
+        // the lines of a bundle are those of the files it was built from
+        None if file.ends_with(".vmb") => None,
+        None => fs::read_to_string(file).ok(),
+    });
+
+    if json {
+        report_json(diagnostics)
+    } else {
+        report(diagnostics)
+    }
+}
+
+fn fail(diagnostic: Diagnostic, json: bool) -> ! {
+    report_diagnostics(&mut [diagnostic], json);
+    process::exit(1);
+}
+
+// writes Xxx.vm, Xxx.xml or XxxT.xml next to every Jack source
+fn emit_jack_output(path: &str, kind: &str, json: bool) {
+    let file_paths: Vec<String> = if path.ends_with(".jack") {
+        vec![String::from(path)]
+    } else {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|file| String::from(file.unwrap().path().to_str().unwrap()))
+            .filter(|file_path| file_path.ends_with(".jack"))
+            .collect()
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for file_path in file_paths {
+        let content =
+            fs::read_to_string(&file_path).expect("Something went wrong reading the file");
+
+        match emit(&file_path, &content, kind) {
+            Ok(text) => {
+                let suffix = match kind {
+                    "vm" => ".vm",
+                    "tokens-xml" => "T.xml",
+                    _ => ".xml",
+                };
+
+                fs::write(format!("{}{}", strip_extension(&file_path), suffix), text)
+                    .expect("Something failed on write file to disk");
+            }
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+
+    if report_diagnostics(&mut diagnostics, json) {
+        process::exit(1);
+    }
+}
+
+// --format rewrites .vm sources in canonical form, --check only reports the ones that
+// are not. A .vmb given on its own is written out as one .vm file per bundled file.
+fn format_vm_files(path: &str, options: &Options) {
+    if path.ends_with(".vmb") {
+        // a bundle has no text of its own to check
+        if !options.check {
+            disassemble_bundle(path, options.message_format == "json");
+        }
+        return;
+    }
+
+    let file_paths: Vec<String> = if path.ends_with(".vm") {
+        vec![String::from(path)]
+    } else {
+        let file_paths: Vec<String> = fs::read_dir(path)
+            .unwrap()
+            .map(|file| String::from(file.unwrap().path().to_str().unwrap()))
+            .collect();
+
+        // compiler outputs are rewritten by the compiler, not formatted
+        file_paths
+            .iter()
+            .filter(|file_path| {
+                file_path.ends_with(".vm")
+                    && !file_paths.contains(&format!("{}.jack", strip_extension(file_path)))
+            })
+            .cloned()
+            .collect()
+    };
 
-    let lines = build_content(content);
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for file_path in file_paths {
+        let content =
+            fs::read_to_string(&file_path).expect("Something went wrong reading the file");
+
+        let formatted = match format_source(&file_path, &content, !options.strip_comments) {
+            Ok(formatted) => formatted,
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                continue;
+            }
+        };
+
+        match first_difference(&content, &formatted) {
+            Some(line) if options.check => diagnostics.push(
+                Diagnostic::error("unformatted", format!("{} is not formatted", file_path))
+                    .at(&file_path, line),
+            ),
+            Some(_) => {
+                fs::write(&file_path, formatted).expect("Something failed on write file to disk")
+            }
+            None => (),
+        }
+    }
+
+    if report_diagnostics(&mut diagnostics, options.message_format == "json") {
+        process::exit(1);
+    }
+}
+
+fn disassemble_bundle(path: &str, json: bool) {
+    let bytes = fs::read(path).expect("Something went wrong reading the file");
+
+    let files = read_vmb(&bytes).unwrap_or_else(|message| {
+        fail(
+            Diagnostic::error(
+                "invalid-vmb",
+                format!("{} cannot be read: {}", path, message),
+            ),
+            json,
+        )
+    });
+
+    if let Err(diagnostic) = write_bundled_sources(path, &files) {
+        fail(diagnostic, json);
+    }
+}
+
+// Xxx.vmb is written out to the folder Xxx next to it, since the folder of the bundle
+// usually holds the sources it was built from. Existing files are never overwritten.
+fn write_bundled_sources(path: &str, files: &[VmFile]) -> Result<(), Diagnostic> {
+    let directory = Path::new(path).with_extension("");
+    let targets: Vec<_> = files
+        .iter()
+        .map(|file| directory.join(format!("{}.vm", file.name)))
+        .collect();
+
+    if let Some(existing) = targets.iter().find(|target| target.exists()) {
+        return Err(Diagnostic::error(
+            "file-exists",
+            format!(
+                "{} already exists, not overwriting it with the text of {}",
+                existing.display(),
+                path
+            ),
+        ));
+    }
+
+    fs::create_dir_all(&directory).expect("Something failed on write file to disk");
+
+    for (file, target) in files.iter().zip(targets) {
+        fs::write(target, format_commands(&file.commands))
+            .expect("Something failed on write file to disk");
+    }
+
+    Ok(())
+}
+
+// writes Xxx.dis.asm next to Xxx.hack; the symbols come from a symbol file or from
+// the .asm the binary was assembled from
+fn disassemble_file(path: &str, symbols_path: Option<&str>, json: bool) {
+    let content = fs::read_to_string(path).expect("Something went wrong reading the file");
+
+    let symbols = match symbols_path {
+        Some(symbols_path) => {
+            let symbols_content =
+                fs::read_to_string(symbols_path).expect("Something went wrong reading the file");
+
+            let symbols = if symbols_path.ends_with(".asm") {
+                Symbols::from_source(&symbols_content)
+            } else {
+                Symbols::from_symbol_file(&symbols_content)
+            };
+
+            symbols.unwrap_or_else(|message| {
+                fail(
+                    Diagnostic::error(
+                        "invalid-symbols",
+                        format!("{} cannot be read: {}", symbols_path, message),
+                    ),
+                    json,
+                )
+            })
+        }
+        None => Symbols::default(),
+    };
+
+    let (result, mut diagnostics) = disassemble(path, &content, &symbols);
+
+    fs::write(
+        format!("{}.dis.asm", path.trim_end_matches(".hack")),
+        result.join("\r\n"),
+    )
+    .expect("Something failed on write file to disk");
+
+    if report_diagnostics(&mut diagnostics, json) {
+        process::exit(1);
+    }
+}
+
+// one .vmb file with a section for every loaded file, before any OS class is linked
+fn write_bundle(files: &[VmFile], output: &str, json: bool) {
+    match write_vmb(files) {
+        Ok(bytes) => fs::write(format!("{}.vmb", output), bytes)
+            .expect("Something failed on write file to disk"),
+        Err(message) => fail(Diagnostic::error("unencodable-command", message), json),
+    }
+}
+
+fn is_source_file(file_path: &str) -> bool {
+    SOURCE_EXTENSIONS
+        .iter()
+        .any(|extension| file_path.ends_with(extension))
+}
+
+fn strip_extension(file_path: &str) -> String {
+    SOURCE_EXTENSIONS
+        .iter()
+        .find_map(|extension| file_path.strip_suffix(extension))
+        .unwrap_or(file_path)
+        .to_string()
+}
+
+// whether any file of a bundle has its source among `file_paths`
+fn bundles_sources_of(bundled: &[VmFile], file_paths: &[String]) -> bool {
+    let file_name = |path: &str| Path::new(path).file_name().map(|name| name.to_owned());
+
+    bundled.iter().any(|file| {
+        file_paths
+            .iter()
+            .any(|file_path| file_name(file_path) == file_name(&file.path))
+    })
+}
+
+// a .vmb bundle holds any number of files, .vm and .jack sources one each
+fn load_files(file_path: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<VmFile> {
+    if file_path.ends_with(".vmb") {
+        let bytes = fs::read(file_path).expect("Something went wrong reading the file");
+
+        return read_vmb(&bytes).unwrap_or_else(|message| {
+            diagnostics.push(Diagnostic::error(
+                "invalid-vmb",
+                format!("{} cannot be read: {}", file_path, message),
+            ));
+            Vec::new()
+        });
+    }
+
+    let content = fs::read_to_string(file_path).expect("Something went wrong reading the file");
 
     let filename = Path::new(file_path).file_name().unwrap().to_str().unwrap();
 
-    parse_content(lines, filename.replace(".vm", ""))
+    let commands = if file_path.ends_with(".jack") {
+        compile_class(file_path, &content).unwrap_or_else(|diagnostic| {
+            diagnostics.push(diagnostic);
+            Vec::new()
+        })
+    } else {
+        build_content(content)
+    };
+
+    vec![VmFile {
+        name: strip_extension(filename),
+        path: String::from(file_path),
+        commands,
+    }]
+}
+
+// under --message-format json stdout carries only the diagnostics, so reports go to stderr
+fn print_report(report: &str, json: bool) {
+    if json {
+        eprint!("{}", report);
+    } else {
+        print!("{}", report);
+    }
+}
+
+fn stripped_report(stripped: &[StrippedFunction], unit: &str) -> String {
+    let words: usize = stripped.iter().map(|function| function.words).sum();
+
+    let mut report = format!(
+        "Stripped {} unreachable functions ({} {} saved)\n",
+        stripped.len(),
+        words,
+        unit
+    );
+
+    for function in stripped {
+        report.push_str(&format!(
+            "  {} ({}.vm): {} {}\n",
+            function.name, function.filename, function.words, unit
+        ));
+    }
+
+    report
+}
+
+fn inlined_report(inlined: &[InlinedFunction]) -> String {
+    let calls: usize = inlined.iter().map(|function| function.calls).sum();
+
+    let mut report = format!("Inlined {} calls to {} functions\n", calls, inlined.len());
+
+    for function in inlined {
+        report.push_str(&format!("  {}: {} calls\n", function.name, function.calls));
+    }
+
+    report
+}
+
+fn stack_usage_report(usage: &[StackUsage]) -> String {
+    let mut usage: Vec<&StackUsage> = usage.iter().collect();
+    usage.sort_by(|a, b| {
+        b.max_depth
+            .cmp(&a.max_depth)
+            .then_with(|| a.function.cmp(&b.function))
+    });
+
+    let mut report = String::from("Maximum working stack depth per function:\n");
+
+    for function in usage {
+        report.push_str(&format!(
+            "  {:>6}  {}\n",
+            function.max_depth, function.function
+        ));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "// entry point\r\nfunction Sys.init 0\r\n    push constant 1 // one\r\n    pop temp 0\r\n  label END\r\n    goto END\r\n";
+
+    #[test]
+    fn bundle_round_trip_leaves_the_sources_unchanged() {
+        let directory = env::temp_dir().join(format!("vmb_round_trip_{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let source = directory.join("Sys.vm");
+        fs::write(&source, SOURCE).unwrap();
+
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let files = load_files(source.to_str().unwrap(), &mut diagnostics);
+        let bundle = directory.join("prog.vmb");
+        fs::write(&bundle, write_vmb(&files).unwrap()).unwrap();
+
+        let bundle = bundle.to_str().unwrap();
+        assert!(write_bundled_sources(bundle, &files).is_ok());
+        // a second run finds its own output and leaves it alone as well
+        assert!(write_bundled_sources(bundle, &files).is_err());
+
+        assert_eq!(fs::read_to_string(&source).unwrap(), SOURCE);
+        assert_eq!(
+            fs::read_to_string(directory.join("prog").join("Sys.vm")).unwrap(),
+            format_commands(&files[0].commands)
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
 }
@@ -0,0 +1,24 @@
+use crate::program::VmFile;
+
+// the OS routines compute the same wrapped product and truncated quotient; only
+// Math.divide by zero differs, calling Sys.error where div gives 0
+const LOWERED_CALLS: [(&str, &str); 2] = [("Math.multiply", "mul"), ("Math.divide", "div")];
+
+pub fn lower_math_calls(files: &mut [VmFile]) {
+    for file in files.iter_mut() {
+        for command in file.commands.iter_mut() {
+            if command.command != "call" || command.value != "2" {
+                continue;
+            }
+
+            if let Some((_, operation)) = LOWERED_CALLS
+                .iter()
+                .find(|(name, _)| *name == command.detail)
+            {
+                command.command = operation.to_string();
+                command.detail.clear();
+                command.value.clear();
+            }
+        }
+    }
+}
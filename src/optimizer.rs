@@ -0,0 +1,300 @@
+use crate::program::{VmCommand, VmFile};
+
+// Peephole pass over each file: every command is appended to the output and then
+// simplified against the commands already there. Labels, calls and jumps are never
+// part of a pattern, so nothing is folded across a point other code can jump to.
+pub fn optimize(files: &mut [VmFile]) {
+    for file in files.iter_mut() {
+        let mut output: Vec<VmCommand> = Vec::new();
+
+        for command in file.commands.drain(..) {
+            append(&mut output, command);
+        }
+
+        file.commands = output;
+    }
+}
+
+fn append(output: &mut Vec<VmCommand>, command: VmCommand) {
+    let line = command.line;
+
+    match command.command.as_str() {
+        "neg" | "not" => {
+            if let Some((x, length)) = constant_at_end(output) {
+                let value = evaluate_unary(&command.command, x);
+                return replace_tail(output, length, constant(value, line));
+            }
+
+            // neg neg and not not cancel out
+            if output.last().map(|last| last.command == command.command) == Some(true) {
+                output.pop();
+                return;
+            }
+        }
+        "if-goto" => {
+            if let Some((x, length)) = constant_at_end(output) {
+                output.truncate(output.len() - length);
+
+                if x != 0 {
+                    output.push(VmCommand {
+                        command: String::from("goto"),
+                        detail: command.detail,
+                        value: String::new(),
+                        line,
+                    });
+                }
+
+                return;
+            }
+        }
+        operation if is_binary(operation) => {
+            if let Some((y, y_length)) = constant_at_end(output) {
+                let x_end = output.len() - y_length;
+
+                if let Some((x, x_length)) = constant_at_end(&output[..x_end]) {
+                    let value = evaluate_binary(operation, x, y);
+                    return replace_tail(output, x_length + y_length, constant(value, line));
+                }
+
+                // x + 0, x - 0, x * 1 and friends leave x untouched
+                if is_identity(operation, y) {
+                    output.truncate(x_end);
+                    return;
+                }
+            }
+        }
+        _ => (),
+    }
+
+    output.push(command);
+}
+
+// the folded value is picked up again by whichever command consumes it next
+fn replace_tail(output: &mut Vec<VmCommand>, length: usize, commands: Vec<VmCommand>) {
+    output.truncate(output.len() - length);
+    output.extend(commands);
+}
+
+// the value left on the stack by the last commands of `commands`, if it is a constant,
+// with the number of commands that produce it
+fn constant_at_end(commands: &[VmCommand]) -> Option<(i16, usize)> {
+    let last = commands.last()?;
+
+    match last.command.as_str() {
+        "push" if last.detail == "constant" => match last.value.parse::<u16>() {
+            Ok(value) if value <= 32767 => Some((value as i16, 1)),
+            _ => None,
+        },
+        "neg" | "not" => {
+            let (x, length) = constant_at_end(&commands[..commands.len() - 1])?;
+            Some((evaluate_unary(&last.command, x), length + 1))
+        }
+        _ => None,
+    }
+}
+
+// push constant only takes 0..32767, so negative values are written as the
+// complement of a positive one, the way Jack compiles `true`
+fn constant(value: i16, line: usize) -> Vec<VmCommand> {
+    let push = |value: i16| VmCommand {
+        command: String::from("push"),
+        detail: String::from("constant"),
+        value: value.to_string(),
+        line,
+    };
+
+    if value >= 0 {
+        return vec![push(value)];
+    }
+
+    vec![
+        push(!value),
+        VmCommand {
+            command: String::from("not"),
+            detail: String::new(),
+            value: String::new(),
+            line,
+        },
+    ]
+}
+
+fn is_binary(operation: &str) -> bool {
+    matches!(
+        operation,
+        "add"
+            | "sub"
+            | "eq"
+            | "gt"
+            | "lt"
+            | "and"
+            | "or"
+            | "mul"
+            | "div"
+            | "mod"
+            | "shl"
+            | "shr"
+            | "xor"
+    )
+}
+
+fn is_identity(operation: &str, y: i16) -> bool {
+    match operation {
+        "add" | "sub" | "or" | "xor" | "shl" | "shr" => y == 0,
+        "mul" | "div" => y == 1,
+        "and" => y == -1,
+        _ => false,
+    }
+}
+
+fn evaluate_unary(operation: &str, x: i16) -> i16 {
+    match operation {
+        "neg" => x.wrapping_neg(),
+        _ => !x,
+    }
+}
+
+// same 16-bit semantics as the generated code, including comparisons on the wrapped
+// difference and the division by zero results of the runtime routines
+fn evaluate_binary(operation: &str, x: i16, y: i16) -> i16 {
+    let truth = |value: bool| if value { -1 } else { 0 };
+
+    match operation {
+        "add" => x.wrapping_add(y),
+        "sub" => x.wrapping_sub(y),
+        "and" => x & y,
+        "or" => x | y,
+        "xor" => x ^ y,
+        "eq" => truth(x.wrapping_sub(y) == 0),
+        "gt" => truth(x.wrapping_sub(y) > 0),
+        "lt" => truth(x.wrapping_sub(y) < 0),
+        "mul" => x.wrapping_mul(y),
+        "div" if y == 0 => 0,
+        "div" => x.wrapping_div(y),
+        "mod" if y == 0 => x,
+        "mod" => x.wrapping_rem(y),
+        "shl" => ((x as u16) << (y & 15)) as i16,
+        "shr" => x >> (y & 15),
+        _ => panic!("Invalid action required! {}", operation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::{run_on_hack, vm_file, PROGRAMS};
+
+    fn optimized(source: &str) -> Vec<String> {
+        let mut files = vec![vm_file("Main", source)];
+
+        optimize(&mut files);
+
+        files[0]
+            .commands
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn constants_fold_with_16_bit_wraparound() {
+        assert_eq!(
+            optimized("push constant 32767\npush constant 1\nadd\n"),
+            ["push constant 32767", "not"]
+        );
+        assert_eq!(
+            optimized("push constant 300\npush constant 300\nmul\n"),
+            ["push constant 24464"]
+        );
+        assert_eq!(
+            optimized("push constant 3\npush constant 5\nsub\npush constant 2\nmul\n"),
+            ["push constant 3", "not"]
+        );
+    }
+
+    #[test]
+    fn negative_constants_are_the_complement_of_a_positive_one() {
+        assert_eq!(
+            optimized("push constant 5\nneg\n"),
+            ["push constant 4", "not"]
+        );
+        assert_eq!(
+            optimized("push constant 0\nnot\n"),
+            ["push constant 0", "not"]
+        );
+        assert_eq!(
+            optimized("push constant 1\nneg\nneg\n"),
+            ["push constant 1"]
+        );
+        assert_eq!(
+            optimized("push constant 7\nneg\npush constant 2\nlt\n"),
+            ["push constant 0", "not"]
+        );
+    }
+
+    #[test]
+    fn neg_neg_and_not_not_cancel() {
+        assert_eq!(optimized("push local 0\nneg\nneg\n"), ["push local 0"]);
+        assert_eq!(optimized("push local 0\nnot\nnot\n"), ["push local 0"]);
+        assert_eq!(
+            optimized("push local 0\nneg\nnot\n"),
+            ["push local 0", "neg", "not"]
+        );
+    }
+
+    #[test]
+    fn if_goto_on_a_constant_becomes_goto_or_nothing() {
+        assert_eq!(
+            optimized("push constant 1\nneg\nif-goto LOOP\n"),
+            ["goto LOOP"]
+        );
+        assert_eq!(
+            optimized("push constant 0\nif-goto LOOP\npush local 0\n"),
+            ["push local 0"]
+        );
+        assert_eq!(
+            optimized("push local 0\nif-goto LOOP\n"),
+            ["push local 0", "if-goto LOOP"]
+        );
+    }
+
+    #[test]
+    fn nothing_is_folded_across_a_label() {
+        let source = "push constant 1\nlabel L\npush constant 2\nadd\nneg\nlabel M\nneg\n";
+
+        assert_eq!(
+            optimized(source),
+            [
+                "push constant 1",
+                "label L",
+                "push constant 2",
+                "add",
+                "neg",
+                "label M",
+                "neg"
+            ]
+        );
+    }
+
+    #[test]
+    fn optimized_test_programs_leave_the_same_ram() {
+        for program in PROGRAMS {
+            let plain = run_on_hack(&program.vm_files(), false);
+
+            let mut files = program.vm_files();
+            optimize(&mut files);
+            let optimized = run_on_hack(&files, false);
+
+            let addresses = (0..13).chain(program.expected.iter().map(|(address, _)| *address));
+
+            for address in addresses {
+                assert_eq!(
+                    optimized.ram(address),
+                    plain.ram(address),
+                    "RAM[{}] after {} with -O",
+                    address,
+                    program.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::Path;
+
+use crate::inliner::DEFAULT_INLINE_BUDGET;
+use crate::lint::RULES;
+use crate::os::CLASSES;
+use crate::parser::DEFAULT_PROLOGUE_THRESHOLD;
+
+pub struct Options {
+    pub path: String,
+    pub strip_dead: Option<String>,
+    // functions --strip-dead keeps along with whatever they call
+    pub keep: Vec<String>,
+    pub size_report: bool,
+    pub stack_report: bool,
+    pub target: String,
+    pub math_ops: bool,
+    pub prologue_threshold: usize,
+    pub optimize: bool,
+    pub cache_top: bool,
+    pub inline: Option<usize>,
+    pub tail_calls: bool,
+    pub emit: Option<String>,
+    pub link_os: bool,
+    pub no_os_classes: Vec<String>,
+    pub format: bool,
+    pub check: bool,
+    pub strip_comments: bool,
+    pub symbols: Option<String>,
+    pub lint: Option<Vec<String>>,
+    pub deny: Vec<String>,
+    pub lint_format: String,
+    pub lsp: bool,
+    pub message_format: String,
+}
+
+impl Options {
+    pub fn from_args(args: &[String]) -> Options {
+        let mut path: Option<String> = None;
+        let mut strip_dead: Option<String> = None;
+        let mut keep: Vec<String> = Vec::new();
+        let mut size_report = false;
+        let mut stack_report = false;
+        let mut target = String::from("hack");
+        let mut math_ops = false;
+        let mut prologue_threshold = DEFAULT_PROLOGUE_THRESHOLD;
+        let mut optimize = false;
+        let mut cache_top = false;
+        let mut inline: Option<usize> = None;
+        let mut tail_calls = false;
+        let mut emit: Option<String> = None;
+        let mut link_os = true;
+        let mut no_os_classes: Vec<String> = Vec::new();
+        let mut format = false;
+        let mut check = false;
+        let mut strip_comments = false;
+        let mut symbols: Option<String> = None;
+        let mut lint: Option<Vec<String>> = None;
+        let mut deny: Vec<String> = Vec::new();
+        let mut lint_format = String::from("text");
+        let mut lsp = false;
+        let mut message_format = String::from("human");
+
+        let mut args = args.iter().skip(1).peekable();
+
+        while let Some(arg) = args.next() {
+            let (flag, value) = split_flag(arg);
+
+            // options that always take a value also accept it as the next argument
+            let mut required = |name: &str| match value {
+                Some(value) => value.to_string(),
+                None => args
+                    .next()
+                    .unwrap_or_else(|| panic!("{} needs a value", name))
+                    .clone(),
+            };
+
+            match flag {
+                // the entry is optional, so a separate argument only counts when it is
+                // a function name rather than the path
+                "--strip-dead" => {
+                    strip_dead = Some(match value {
+                        Some(entry) => entry.to_string(),
+                        None => match args.peek() {
+                            Some(next) if is_function_name(next) => args.next().unwrap().clone(),
+                            _ => String::from("Sys.init"),
+                        },
+                    })
+                }
+                "--keep" => keep.extend(required("--keep").split(',').map(|name| name.to_string())),
+                "--size-report" => size_report = true,
+                "--stack-report" => stack_report = true,
+                "--target" => target = required("--target"),
+                "--symbols" => symbols = Some(required("--symbols")),
+                "--math-ops" => math_ops = true,
+                "-O" | "--optimize" => optimize = true,
+                "--cache-top" => cache_top = true,
+                "--tail-calls" => tail_calls = true,
+                "--format" => format = true,
+                "--check" => check = true,
+                "--strip-comments" => strip_comments = true,
+                "--lsp" => lsp = true,
+                "--no-os" => match value {
+                    Some(classes) => {
+                        for class in classes.split(',') {
+                            if !CLASSES.iter().any(|(name, _)| *name == class) {
+                                panic!("Unknown OS class {}", class);
+                            }
+                            no_os_classes.push(class.to_string());
+                        }
+                    }
+                    None => link_os = false,
+                },
+                "--inline" => {
+                    inline = Some(match value {
+                        Some(budget) => budget.parse().expect("--inline needs a number"),
+                        None => DEFAULT_INLINE_BUDGET,
+                    })
+                }
+                "--lint" => {
+                    lint = Some(match value {
+                        Some(rules) => lint_rules(rules),
+                        None => RULES.iter().map(|rule| rule.to_string()).collect(),
+                    })
+                }
+                "--deny" => deny.extend(lint_rules(&required("--deny"))),
+                "--lint-format" => match required("--lint-format").as_str() {
+                    format @ "text" | format @ "json" => lint_format = format.to_string(),
+                    format => panic!("Unknown --lint-format {}", format),
+                },
+                "--message-format" => match required("--message-format").as_str() {
+                    format @ "human" | format @ "json" => message_format = format.to_string(),
+                    format => panic!("Unknown --message-format {}", format),
+                },
+                "--prologue-threshold" => {
+                    prologue_threshold = required("--prologue-threshold")
+                        .parse()
+                        .expect("--prologue-threshold needs a number")
+                }
+                "--emit" => match required("--emit").as_str() {
+                    kind @ "vm" | kind @ "vmb" | kind @ "tokens-xml" | kind @ "parse-xml" => {
+                        emit = Some(kind.to_string())
+                    }
+                    kind => panic!("Unknown --emit kind {}", kind),
+                },
+                _ if flag.starts_with("--") => panic!("Unknown option {}", flag),
+                _ => path = Some(arg.clone()),
+            }
+        }
+
+        // the other targets have no ROM to measure
+        if size_report && target != "hack" {
+            panic!(
+                "--size-report only applies to --target hack, not {}",
+                target
+            );
+        }
+
+        // denied rules run even when --lint does not name them
+        if !deny.is_empty() {
+            let rules = lint.get_or_insert_with(Vec::new);
+
+            for rule in &deny {
+                if !rules.contains(rule) {
+                    rules.push(rule.clone());
+                }
+            }
+        }
+
+        Options {
+            // the language server gets its files from the editor
+            path: match path {
+                Some(path) => path,
+                None if lsp => String::new(),
+                None => panic!("Please supply a folder or file name"),
+            },
+            strip_dead,
+            keep,
+            size_report,
+            stack_report,
+            target,
+            math_ops,
+            prologue_threshold,
+            optimize,
+            cache_top,
+            inline,
+            tail_calls,
+            emit,
+            link_os,
+            no_os_classes,
+            format,
+            check,
+            strip_comments,
+            symbols,
+            lint,
+            deny,
+            lint_format,
+            lsp,
+            message_format,
+        }
+    }
+}
+
+// Looked up before the arguments are parsed, so that parsing errors are reported as
+// JSON too.
+pub fn json_messages(args: &[String]) -> bool {
+    args.iter()
+        .zip(args.iter().skip(1).map(Some).chain([None]))
+        .any(|(arg, next)| match split_flag(arg) {
+            ("--message-format", Some(value)) => value == "json",
+            ("--message-format", None) => next.map(String::as_str) == Some("json"),
+            _ => false,
+        })
+}
+
+// `Class.name`, not an option or a file or folder to translate
+fn is_function_name(arg: &str) -> bool {
+    let path = Path::new(arg);
+    let source = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("vm") | Some("vmb") | Some("jack")
+    );
+
+    !arg.starts_with('-') && arg.contains('.') && !source && !path.exists()
+}
+
+fn lint_rules(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|rule| {
+            if !RULES.contains(&rule) {
+                panic!("Unknown lint rule {}", rule);
+            }
+            rule.to_string()
+        })
+        .collect()
+}
+
+// accepts both `--flag` and `--flag=value`
+fn split_flag(arg: &str) -> (&str, Option<&str>) {
+    match arg.find('=') {
+        Some(index) if arg.starts_with("--") => (&arg[..index], Some(&arg[index + 1..])),
+        _ => (arg, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        let args: Vec<String> = ["vm-translator"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+
+        Options::from_args(&args)
+    }
+
+    #[test]
+    fn strip_dead_takes_its_entry_joined_or_as_the_next_argument() {
+        let entry = |args: &[&str]| parse(args).strip_dead;
+
+        assert_eq!(
+            entry(&["Prog", "--strip-dead"]),
+            Some(String::from("Sys.init"))
+        );
+        assert_eq!(
+            entry(&["Prog", "--strip-dead=Main.main"]),
+            Some(String::from("Main.main"))
+        );
+        assert_eq!(
+            entry(&["--strip-dead", "Main.main", "Prog"]),
+            Some(String::from("Main.main"))
+        );
+        assert_eq!(
+            entry(&["--strip-dead", "Main.vm"]),
+            Some(String::from("Sys.init"))
+        );
+        assert_eq!(
+            entry(&["--strip-dead", "--keep", "Main.debug,Main.trace", "Prog"]),
+            Some(String::from("Sys.init"))
+        );
+        assert_eq!(
+            parse(&["--strip-dead", "--keep", "Main.debug,Main.trace", "Prog"]).keep,
+            ["Main.debug", "Main.trace"]
+        );
+    }
+
+    #[test]
+    fn size_report_is_accepted_for_hack() {
+        assert!(parse(&["Main.vm", "--size-report"]).size_report);
+    }
+
+    #[test]
+    #[should_panic(expected = "--size-report only applies to --target hack, not c")]
+    fn size_report_is_rejected_for_other_targets() {
+        parse(&["Main.vm", "--size-report", "--target", "c"]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+
+use crate::builder::build_content;
+use crate::program::{VmCommand, VmFile};
+use crate::reachability::{build_call_graph, called_functions};
+
+// compiled from the Jack sources next to them with `--emit vm os`
+pub const CLASSES: [(&str, &str); 8] = [
+    ("Array", include_str!("../os/Array.vm")),
+    ("Keyboard", include_str!("../os/Keyboard.vm")),
+    ("Math", include_str!("../os/Math.vm")),
+    ("Memory", include_str!("../os/Memory.vm")),
+    ("Output", include_str!("../os/Output.vm")),
+    ("Screen", include_str!("../os/Screen.vm")),
+    ("String", include_str!("../os/String.vm")),
+    ("Sys", include_str!("../os/Sys.vm")),
+];
+
+// Adds the OS classes the program calls but does not define, then the ones those call
+// in turn. A class the program defines replaces the bundled one as a whole, and the
+// linker reports the functions of it that bundled classes call but the program lacks.
+// Classes listed in `disabled` are left out. The init calls of the bundled Sys.init do not make a class
+// needed; they are removed for the classes that are not there. Without bootstrap code
+// only the bundled functions the program reaches are kept, so that Sys.init and its
+// call to Main.main do not come along with the other Sys functions.
+pub fn link_os(files: &mut Vec<VmFile>, bootstrap_entry: Option<&str>, disabled: &[String]) {
+    let mut linked: Vec<&str> = Vec::new();
+
+    loop {
+        let needed = CLASSES.iter().find(|(class, _)| {
+            !disabled.iter().any(|name| name == class)
+                && !defines_class(files, class)
+                && (calls_class(files, class, &linked)
+                    || bootstrap_entry.map(class_of) == Some(class))
+        });
+
+        let (class, content) = match needed {
+            Some(needed) => needed,
+            None => break,
+        };
+
+        files.push(VmFile {
+            name: class.to_string(),
+            path: bundled_path(class),
+            commands: build_content(content.to_string()),
+        });
+        linked.push(class);
+    }
+
+    if bootstrap_entry.is_none() {
+        keep_reached_functions(files);
+    }
+
+    if !linked.contains(&"Sys") {
+        return;
+    }
+
+    let missing: Vec<String> = CLASSES
+        .iter()
+        .filter(|(class, _)| !defines_class(files, class))
+        .map(|(class, _)| format!("{}.init", class))
+        .collect();
+
+    if let Some(sys) = files
+        .iter_mut()
+        .find(|file| file.path == bundled_path("Sys"))
+    {
+        let mut commands = Vec::new();
+        let mut skip_result = false;
+
+        for command in sys.commands.drain(..) {
+            if skip_result && command.command == "pop" {
+                skip_result = false;
+                continue;
+            }
+
+            skip_result = command.command == "call" && missing.contains(&command.detail);
+
+            if !skip_result {
+                commands.push(command);
+            }
+        }
+
+        sys.commands = commands;
+    }
+}
+
+fn keep_reached_functions(files: &mut Vec<VmFile>) {
+    let call_graph = build_call_graph(files);
+
+    let mut pending: Vec<String> = files
+        .iter()
+        .filter(|file| bundled_source(&file.path).is_none())
+        .flat_map(|file| called_functions(&file.commands))
+        .collect();
+
+    let mut reached: HashSet<String> = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if let Some(callees) = call_graph.get(&name) {
+            if reached.insert(name) {
+                pending.extend(callees.iter().cloned());
+            }
+        }
+    }
+
+    for file in files.iter_mut() {
+        if bundled_source(&file.path).is_none() {
+            continue;
+        }
+
+        let mut commands: Vec<VmCommand> = Vec::new();
+
+        for function in file.functions() {
+            if reached.contains(&function.name) {
+                commands.extend(file.commands[function.start..function.end].iter().cloned());
+            }
+        }
+
+        file.commands = commands;
+    }
+
+    files.retain(|file| bundled_source(&file.path).is_none() || !file.commands.is_empty());
+}
+
+fn bundled_path(class: &str) -> String {
+    format!("<os>/{}.vm", class)
+}
+
+// the text behind a path diagnostics give for a bundled class
+pub fn bundled_source(path: &str) -> Option<&'static str> {
+    CLASSES
+        .iter()
+        .find(|(class, _)| bundled_path(class) == path)
+        .map(|(_, content)| *content)
+}
+
+fn class_of(function: &str) -> &str {
+    function.split('.').next().unwrap_or(function)
+}
+
+fn defines_class(files: &[VmFile], class: &str) -> bool {
+    files.iter().any(|file| {
+        file.commands
+            .iter()
+            .any(|command| command.command == "function" && class_of(&command.detail) == class)
+    })
+}
+
+fn calls_class(files: &[VmFile], class: &str, linked: &[&str]) -> bool {
+    files.iter().any(|file| {
+        let bundled_sys = linked.contains(&"Sys") && file.path == bundled_path("Sys");
+
+        file.commands.iter().any(|command| {
+            command.command == "call"
+                && class_of(&command.detail) == class
+                && !(bundled_sys && command.detail.ends_with(".init"))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linker::link;
+    use crate::test_programs::vm_file;
+
+    fn program(source: &str) -> Vec<VmFile> {
+        vec![vm_file("Foo", source)]
+    }
+
+    fn functions(files: &[VmFile]) -> Vec<String> {
+        files
+            .iter()
+            .flat_map(|file| file.functions())
+            .map(|function| function.name)
+            .collect()
+    }
+
+    #[test]
+    fn a_single_file_gets_only_the_functions_it_reaches() {
+        let mut files = program(
+            "function Foo.main 0\npush constant 7\npush constant 0\ncall Math.divide 2\nreturn\n",
+        );
+
+        link_os(&mut files, None, &[]);
+
+        let functions = functions(&files);
+        assert!(functions.contains(&String::from("Math.divide")));
+        assert!(functions.contains(&String::from("Sys.error")));
+        assert!(!functions.contains(&String::from("Sys.init")));
+        assert!(!functions.contains(&String::from("Math.sqrt")));
+        assert!(link(&files, None).is_empty());
+    }
+
+    #[test]
+    fn a_bootstrapped_program_gets_the_whole_os_it_needs() {
+        let mut files = program("function Main.main 0\npush constant 0\nreturn\n");
+
+        link_os(&mut files, Some("Sys.init"), &[]);
+
+        let functions = functions(&files);
+        assert!(functions.contains(&String::from("Sys.init")));
+        assert!(functions.contains(&String::from("Sys.wait")));
+        assert!(link(&files, Some("Sys.init")).is_empty());
+    }
+
+    // like the course's project 8 programs, which come with their own Sys.vm
+    #[test]
+    fn a_replaced_class_missing_a_function_the_os_calls_is_reported_against_it() {
+        let mut files = vec![vm_file(
+            "Sys",
+            "function Sys.init 0\npush constant 6\npush constant 7\ncall Math.multiply 2\npop temp 0\nlabel END\ngoto END\n",
+        )];
+
+        link_os(&mut files, Some("Sys.init"), &[]);
+
+        let diagnostics = link(&files, Some("Sys.init"));
+        assert_eq!(diagnostics.len(), 1);
+
+        let diagnostic = &diagnostics[0];
+        let location = diagnostic.location.as_ref().unwrap();
+
+        assert_eq!(diagnostic.code, "incomplete-os-class");
+        assert_eq!((location.file.as_str(), location.line), ("Sys.vm", 1));
+        assert!(diagnostic.message.contains("does not define Sys.error"));
+    }
+}
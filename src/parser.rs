@@ -1,35 +1,240 @@
 use rand::prelude::*;
-use std::collections::HashMap;
 
-pub fn initial_data() -> Vec<String> {
-    let mut commands: Vec<String> = Vec::new();
+use crate::codegen::{translate_file, CodeGenerator};
+use crate::hack::{Instruction, Line};
+use crate::program::VmCommand;
+use crate::runtime::{hack_runtime, routine_label, EXTENDED_OPERATIONS, ROUTINES};
+use crate::size::count_instructions;
 
-    commands.push(String::from("initialize sp 256"));
-    commands.push(String::from("call Sys.init 0"));
+// translates a single file, leaving out the runtime routines shared by the whole program
+pub fn parse_content(content: &[VmCommand], filename: &str) -> Vec<String> {
+    let mut generator = HackGenerator::new(DEFAULT_PROLOGUE_THRESHOLD, false);
 
-    parse_content(commands, String::from("initialize ASM file"))
+    translate_file(&mut generator, filename, content);
+
+    generator.result
 }
 
-pub fn parse_content(content: Vec<String>, filename: String) -> Vec<String> {
-    let actions = build_actions();
-    let mut result: Vec<String> = Vec::new();
-    let mut instruction = VMInstruction::new(&filename);
+// functions with up to this many locals zero them with straight-line code
+pub const DEFAULT_PROLOGUE_THRESHOLD: usize = 4;
+
+// With cache_top set the value on top of the stack may be kept in D instead of
+// RAM[SP-1] while top_in_d is true; SP then only counts the values in RAM.
+pub struct HackGenerator {
+    result: Vec<String>,
+    instruction: VMInstruction,
+    runtime: Vec<&'static str>,
+    prologue_threshold: usize,
+    cache_top: bool,
+    top_in_d: bool,
+}
 
-    result.push(format!("// New file: {}", &filename));
+impl HackGenerator {
+    pub fn new(prologue_threshold: usize, cache_top: bool) -> HackGenerator {
+        HackGenerator {
+            result: Vec::new(),
+            instruction: VMInstruction::new(""),
+            runtime: Vec::new(),
+            prologue_threshold,
+            cache_top,
+            top_in_d: false,
+        }
+    }
 
-    for line in content {
-        result.push(format!("// {}", line));
+    fn emit(&mut self, command: &str, detail: &str, value: &str, action: Callback) {
+        self.instruction.update(command, detail, value);
+        self.write(action(&self.instruction));
+    }
 
-        &instruction.update(&line);
+    // the one place the assembly text is written, runtime routines and comments included
+    fn write(&mut self, lines: Vec<Line>) {
+        self.result
+            .extend(lines.iter().map(|line| line.to_string()));
+    }
 
-        let action = actions
-            .get(&instruction.command)
-            .expect("Invalid action required!");
+    // writes a cached top of stack back to RAM, needed wherever control flow can
+    // come from elsewhere or other code reads the stack
+    fn flush(&mut self) {
+        if self.top_in_d {
+            let mut builder = AssemblerCommandBuilder::new();
+            builder.push_to_stack();
 
-        result.extend(action(&instruction));
+            self.write(builder.lines());
+            self.top_in_d = false;
+        }
     }
 
-    result
+    fn load(&mut self) {
+        if !self.top_in_d {
+            let mut builder = AssemblerCommandBuilder::new();
+            builder.pop_from_stack_to_d();
+
+            self.write(builder.lines());
+            self.top_in_d = true;
+        }
+    }
+}
+
+impl CodeGenerator for HackGenerator {
+    fn extension(&self) -> &'static str {
+        "asm"
+    }
+
+    fn bootstrap(&mut self) {
+        self.file("initialize ASM file");
+
+        self.comment("initialize sp 256");
+        self.emit("initialize", "sp", "256", initialize_action);
+
+        self.comment("call Sys.init 0");
+        self.call("Sys.init", "0");
+    }
+
+    fn file(&mut self, filename: &str) {
+        self.flush();
+        self.instruction = VMInstruction::new(filename);
+        self.write(vec![Line::Comment(format!("New file: {}", filename))]);
+    }
+
+    fn comment(&mut self, value: &str) {
+        self.write(vec![Line::Comment(String::from(value))]);
+    }
+
+    fn push(&mut self, segment: &str, index: &str) {
+        if self.cache_top {
+            self.flush();
+            self.emit("push", segment, index, cached_push_action);
+            self.top_in_d = true;
+        } else {
+            self.emit("push", segment, index, push_action);
+        }
+    }
+
+    fn pop(&mut self, segment: &str, index: &str) {
+        if self.cache_top {
+            self.load();
+            self.emit("pop", segment, index, cached_pop_action);
+            self.top_in_d = false;
+        } else {
+            self.emit("pop", segment, index, pop_action);
+        }
+    }
+
+    fn arithmetic(&mut self, operation: &str) {
+        if self.cache_top {
+            match operation {
+                "add" | "sub" | "eq" | "lt" | "gt" | "and" | "or" | "neg" | "not" => {
+                    self.load();
+                    self.emit(operation, "", "", cached_arithmetic_action);
+                    return;
+                }
+                // the runtime routines take both operands from RAM
+                _ => self.flush(),
+            }
+        }
+
+        let action: Callback = match operation {
+            "add" => add_action,
+            "sub" => sub_action,
+            "neg" => neg_action,
+            "eq" => eq_action,
+            "lt" => lt_action,
+            "gt" => gt_action,
+            "and" => and_action,
+            "or" => or_action,
+            "not" => not_action,
+            _ => match EXTENDED_OPERATIONS.iter().find(|name| **name == operation) {
+                Some(name) => {
+                    if !self.runtime.contains(name) {
+                        self.runtime.push(name);
+                    }
+                    runtime_call_action
+                }
+                None => panic!("Invalid action required! {}", operation),
+            },
+        };
+
+        self.emit(operation, "", "", action);
+    }
+
+    fn label(&mut self, label: &str) {
+        self.flush();
+        self.emit("label", label, "", label_action);
+    }
+
+    fn goto(&mut self, label: &str) {
+        self.flush();
+        self.emit("goto", label, "", goto_action);
+    }
+
+    fn if_goto(&mut self, label: &str) {
+        if self.cache_top {
+            self.load();
+            self.emit("if-goto", label, "", cached_ifgoto_action);
+            self.top_in_d = false;
+        } else {
+            self.emit("if-goto", label, "", ifgoto_action);
+        }
+    }
+
+    fn function(&mut self, name: &str, locals: &str) {
+        let action: Callback = match parse_locals(locals) {
+            0 => function_action,
+            count if count <= self.prologue_threshold => unrolled_function_action,
+            _ => looped_function_action,
+        };
+
+        self.flush();
+        self.emit("function", name, locals, action);
+    }
+
+    fn call(&mut self, name: &str, arguments: &str) {
+        self.flush();
+        self.emit("call", name, arguments, call_action);
+    }
+
+    fn tail_call(&mut self, name: &str, arguments: &str) {
+        self.flush();
+
+        if !self.runtime.contains(&"tail") {
+            self.runtime.push("tail");
+        }
+
+        self.emit("tail-call", name, arguments, tail_call_action);
+    }
+
+    fn return_(&mut self) {
+        if self.cache_top {
+            self.load();
+            self.emit("return", "", "", return_from_d_action);
+            self.top_in_d = false;
+        } else {
+            self.emit("return", "", "", return_action);
+        }
+    }
+
+    // routines go in a fixed order so the output does not depend on which op came first
+    fn epilogue(&mut self) -> Vec<String> {
+        self.flush();
+
+        let operations: Vec<&str> = ROUTINES
+            .iter()
+            .filter(|name| self.runtime.contains(name))
+            .cloned()
+            .collect();
+
+        self.write(hack_runtime(&operations));
+
+        std::mem::take(&mut self.result)
+    }
+
+    fn code_size(&self, output: &[String]) -> usize {
+        count_instructions(output)
+    }
+
+    fn size_unit(&self) -> &'static str {
+        "ROM words"
+    }
 }
 
 struct VMInstruction {
@@ -51,19 +256,14 @@ impl VMInstruction {
         }
     }
 
-    pub fn update(&mut self, line: &String) {
-        let (command, detail, value) = VMInstruction::split_command(&line);
-
-        let function_name = if command == "function" {
-            detail.clone()
-        } else {
-            self.function_name.clone()
-        };
+    pub fn update(&mut self, command: &str, detail: &str, value: &str) {
+        if command == "function" {
+            self.function_name = String::from(detail);
+        }
 
-        self.command = command;
-        self.detail = detail;
-        self.value = value;
-        self.function_name = function_name;
+        self.command = String::from(command);
+        self.detail = String::from(detail);
+        self.value = String::from(value);
     }
 
     pub fn build_label(&self) -> String {
@@ -84,70 +284,13 @@ impl VMInstruction {
     }
 
     pub fn build_function_name(&self) -> String {
-        format!("{}", self.detail)
-    }
-
-    fn split_command(line: &String) -> (String, String, String) {
-        let command: Vec<&str> = line.split(' ').collect();
-
-        if command.len() == 1 {
-            return (
-                String::from(command[0].trim()),
-                String::new(),
-                String::new(),
-            );
-        }
-
-        if command.len() == 2 {
-            return (
-                String::from(command[0].trim()),
-                String::from(command[1].trim()),
-                String::new(),
-            );
-        }
-
-        (
-            String::from(command[0].trim()),
-            String::from(command[1].trim()),
-            String::from(command[2].trim()),
-        )
+        self.detail.to_string()
     }
 }
 
-type Callback = fn(&VMInstruction) -> Vec<String>;
-
-fn build_actions() -> HashMap<String, Callback> {
-    let mut actions: HashMap<String, Callback> = HashMap::new();
-
-    actions.insert(String::from("push"), push_action);
-    actions.insert(String::from("pop"), pop_action);
-
-    actions.insert(String::from("add"), add_action);
-    actions.insert(String::from("sub"), sub_action);
-    actions.insert(String::from("neg"), neg_action);
-
-    actions.insert(String::from("eq"), eq_action);
-    actions.insert(String::from("lt"), lt_action);
-    actions.insert(String::from("gt"), gt_action);
-
-    actions.insert(String::from("and"), and_action);
-    actions.insert(String::from("or"), or_action);
-    actions.insert(String::from("not"), not_action);
-
-    actions.insert(String::from("label"), label_action);
-    actions.insert(String::from("goto"), goto_action);
-    actions.insert(String::from("if-goto"), ifgoto_action);
-
-    actions.insert(String::from("function"), function_action);
-    actions.insert(String::from("return"), return_action);
-    actions.insert(String::from("call"), call_action);
-
-    actions.insert(String::from("initialize"), initialize_action);
-
-    actions
-}
+type Callback = fn(&VMInstruction) -> Vec<Line>;
 
-fn push_action(instruction: &VMInstruction) -> Vec<String> {
+fn push_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     // use value information
@@ -200,13 +343,13 @@ fn push_action(instruction: &VMInstruction) -> Vec<String> {
             builder.m_to_d();
             builder.push_to_stack();
         }
-        _ => panic!(format!("Invalid memory location! {}", instruction.detail)),
+        _ => panic!("Invalid memory location! {}", instruction.detail),
     }
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn pop_action(instruction: &VMInstruction) -> Vec<String> {
+fn pop_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
     let temp_label = instruction.build_label_from("tmp");
 
@@ -259,13 +402,13 @@ fn pop_action(instruction: &VMInstruction) -> Vec<String> {
             builder.at(parsed_value);
             builder.d_to_m();
         }
-        _ => panic!(format!("Invalid memory location! {}", instruction.detail)),
+        _ => panic!("Invalid memory location! {}", instruction.detail),
     }
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn add_action(_: &VMInstruction) -> Vec<String> {
+fn add_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -273,10 +416,10 @@ fn add_action(_: &VMInstruction) -> Vec<String> {
     builder.d_plus_m_to_m();
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn sub_action(_: &VMInstruction) -> Vec<String> {
+fn sub_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -284,10 +427,10 @@ fn sub_action(_: &VMInstruction) -> Vec<String> {
     builder.m_less_d_to_m();
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn eq_action(instruction: &VMInstruction) -> Vec<String> {
+fn eq_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -296,10 +439,10 @@ fn eq_action(instruction: &VMInstruction) -> Vec<String> {
     builder.compare_with_d("JEQ", &instruction.build_random_label_from("IS_TRUE"));
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn lt_action(instruction: &VMInstruction) -> Vec<String> {
+fn lt_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -308,10 +451,10 @@ fn lt_action(instruction: &VMInstruction) -> Vec<String> {
     builder.compare_with_d("JLT", &instruction.build_random_label_from("IS_TRUE"));
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn gt_action(instruction: &VMInstruction) -> Vec<String> {
+fn gt_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -320,10 +463,10 @@ fn gt_action(instruction: &VMInstruction) -> Vec<String> {
     builder.compare_with_d("JGT", &instruction.build_random_label_from("IS_TRUE"));
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn and_action(_: &VMInstruction) -> Vec<String> {
+fn and_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -331,10 +474,10 @@ fn and_action(_: &VMInstruction) -> Vec<String> {
     builder.m_and_d_to_m();
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn or_action(_: &VMInstruction) -> Vec<String> {
+fn or_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
@@ -342,87 +485,135 @@ fn or_action(_: &VMInstruction) -> Vec<String> {
     builder.m_or_d_to_m();
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn not_action(_: &VMInstruction) -> Vec<String> {
+fn not_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack();
     builder.not_m_to_m();
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn neg_action(_: &VMInstruction) -> Vec<String> {
+fn neg_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack();
     builder.neg_m_to_m();
     builder.advance_sp();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn label_action(instruction: &VMInstruction) -> Vec<String> {
+fn label_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.label(&instruction.build_label());
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn goto_action(instruction: &VMInstruction) -> Vec<String> {
+fn goto_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.goto_label(&instruction.build_label());
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn ifgoto_action(instruction: &VMInstruction) -> Vec<String> {
+fn ifgoto_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.pop_from_stack_to_d();
     builder.jump_to_label_if_d_neq(&instruction.build_label());
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn function_action(instruction: &VMInstruction) -> Vec<String> {
+fn parse_locals(value: &str) -> usize {
+    value.parse().expect("Invalid number of locals")
+}
+
+// ROM words and cycles per call taken by the prologue zeroing `locals` locals
+pub fn prologue_cost(locals: usize, unrolled: bool) -> (usize, usize) {
+    match (locals, unrolled) {
+        (0, _) => (0, 0),
+        (_, true) => (2 * locals + 4, 2 * locals + 4),
+        (_, false) => (9, 7 * locals + 2),
+    }
+}
+
+fn function_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    builder.label(&instruction.build_function_name()); // set the label for the function
+
+    builder.lines()
+}
+
+fn unrolled_function_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
-    let while_label = instruction.build_random_label_from("WHILE");
-    let break_label = instruction.build_random_label_from("BREAK");
+
+    builder.label(&instruction.build_function_name()); // set the label for the function
+
+    builder.at("SP");
+    builder.m_to_a();
+
+    for local in 0..parse_locals(&instruction.value) {
+        if local > 0 {
+            builder.a_plus_one_to_a();
+        }
+
+        builder.zero_m(); // zero each local in place
+    }
+
+    builder.a_plus_one_to_d();
+    builder.at("SP");
+    builder.d_to_m(); // move SP past the last local
+
+    builder.lines()
+}
+
+fn looped_function_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+    let loop_label = instruction.build_random_label_from("LOCALS");
 
     builder.label(&instruction.build_function_name()); // set the label for the function
 
     builder.move_value_to_d(&instruction.value);
-    builder.push_to_stack();
 
-    builder.label(&while_label); // while d != 0
+    builder.label(&loop_label); // do while d > 0
 
-    builder.pop_from_stack_to_d();
-    builder.jump_to_label_if_d_eq(&break_label);
-    builder.push_to_stack_zero();
+    builder.advance_sp_to_a();
+    builder.a_less_one_to_a();
+    builder.zero_m(); // push 0
     builder.d_less_one_to_d(); // d--
-    builder.push_to_stack();
 
-    builder.goto_label(&while_label);
+    builder.jump_to_label_if_d_gt(&loop_label);
 
-    builder.label(&break_label); // end while
+    builder.lines()
+}
 
-    builder.parsed_content()
+fn return_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    builder.pop_from_stack_to_d(); // return value to d
+
+    let mut result = builder.lines();
+    result.extend(return_from_d_action(instruction));
+    result
 }
 
-fn return_action(_: &VMInstruction) -> Vec<String> {
+fn return_from_d_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     let temp_return_value = "R13";
     let temp_return_addr = "R14";
     let temp_sp = "R15";
 
-    builder.pop_from_stack_to_d();
     builder.at(temp_return_value);
     builder.d_to_m(); // save return value to temp.return.value
 
@@ -463,10 +654,10 @@ fn return_action(_: &VMInstruction) -> Vec<String> {
 
     builder.goto_value_at(temp_return_addr); // go back to flow
 
-    builder.parsed_content()
+    builder.lines()
 }
 
-fn call_action(instruction: &VMInstruction) -> Vec<String> {
+fn call_action(instruction: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
     let random_jump: String = instruction.build_random_label_from("ret");
 
@@ -501,21 +692,189 @@ fn call_action(instruction: &VMInstruction) -> Vec<String> {
 
     builder.label(&random_jump); // get a return point
 
-    builder.parsed_content()
+    builder.lines()
+}
+
+// Top-of-stack caching: the actions below take the top of the stack from D and
+// leave their result there, so the generator only has to track whether D holds it.
+
+// segment indexes up to this are reached by incrementing A instead of adding in D
+const CACHED_INDEX_STEPS: usize = 8;
+
+fn cached_push_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    match instruction.detail.as_str() {
+        "constant" => builder.move_value_to_d(&instruction.value),
+        "temp" => {
+            builder.at(&temp_address(&instruction.value));
+            builder.m_to_d();
+        }
+        "static" => {
+            builder.at(&instruction.build_static_label());
+            builder.m_to_d();
+        }
+        "pointer" => {
+            builder.at(pointer_label(&instruction.value));
+            builder.m_to_d();
+        }
+        segment => {
+            builder.move_value_to_d(&instruction.value);
+            builder.get_value_from_segment_plus_d(segment_label(segment));
+        }
+    }
+
+    builder.lines()
+}
+
+fn cached_pop_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    match instruction.detail.as_str() {
+        "temp" => builder.at(&temp_address(&instruction.value)),
+        "static" => builder.at(&instruction.build_static_label()),
+        "pointer" => builder.at(pointer_label(&instruction.value)),
+        segment => {
+            let index: usize = instruction.value.parse().expect("Invalid index");
+
+            if index <= CACHED_INDEX_STEPS {
+                builder.at(segment_label(segment));
+                builder.m_to_a();
+
+                for _ in 0..index {
+                    builder.a_plus_one_to_a();
+                }
+            } else {
+                // D is taken by the value, so the address goes through R14
+                builder.d_to_tmp("R13");
+                builder.move_value_to_d(&instruction.value);
+                builder.get_address_from_segment_plus_d(segment_label(segment));
+                builder.d_to_tmp("R14");
+                builder.get_value_at("R13");
+                builder.at("R14");
+                builder.m_to_a();
+            }
+        }
+    }
+
+    builder.d_to_m();
+
+    builder.lines()
+}
+
+// y is in D and x on top of the RAM stack
+fn cached_arithmetic_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    match instruction.command.as_str() {
+        "neg" => builder.neg_d_to_d(),
+        "not" => builder.not_d_to_d(),
+        operation => {
+            builder.retreat_sp_to_a();
+
+            match operation {
+                "add" => builder.d_plus_m_to_d(),
+                "sub" => builder.m_less_d_to_d(),
+                "and" => builder.d_and_m_to_d(),
+                "or" => builder.d_or_m_to_d(),
+                _ => {
+                    let compare = match operation {
+                        "eq" => "JEQ",
+                        "lt" => "JLT",
+                        "gt" => "JGT",
+                        _ => panic!("Invalid action required! {}", operation),
+                    };
+
+                    builder.m_less_d_to_d();
+                    builder.compare_d_to_d(
+                        compare,
+                        &instruction.build_random_label_from("IS_TRUE"),
+                        &instruction.build_random_label_from("END_IF"),
+                    );
+                }
+            }
+        }
+    }
+
+    builder.lines()
 }
 
-fn initialize_action(_: &VMInstruction) -> Vec<String> {
+fn cached_ifgoto_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    builder.jump_to_label_if_d_neq(&instruction.build_label());
+
+    builder.lines()
+}
+
+fn segment_label(segment: &str) -> &'static str {
+    match segment {
+        "local" => "LCL",
+        "argument" => "ARG",
+        "this" => "THIS",
+        "that" => "THAT",
+        _ => panic!("Invalid memory location! {}", segment),
+    }
+}
+
+fn pointer_label(value: &str) -> &'static str {
+    if value == "0" {
+        "THIS"
+    } else {
+        "THAT"
+    }
+}
+
+fn temp_address(value: &str) -> String {
+    let index: usize = value.parse().expect("Invalid index");
+    format!("{}", 5 + index)
+}
+
+// __vm_tail reuses the current frame, so there is no return point
+fn tail_call_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+
+    builder.move_value_to_d(&instruction.value);
+    builder.at("R13");
+    builder.d_to_m(); // number of arguments
+
+    builder.move_value_to_d(&instruction.build_function_name());
+    builder.at("R14");
+    builder.d_to_m(); // function address
+
+    builder.goto_label(&routine_label("tail"));
+
+    builder.lines()
+}
+
+// the routine pops y, replaces x with the result and jumps back through R15
+fn runtime_call_action(instruction: &VMInstruction) -> Vec<Line> {
+    let mut builder = AssemblerCommandBuilder::new();
+    let random_jump: String = instruction.build_random_label_from("ret");
+
+    builder.move_value_to_d(&random_jump);
+    builder.at("R15");
+    builder.d_to_m(); // save return addr
+
+    builder.goto_label(&routine_label(&instruction.command)); // go to routine
+
+    builder.label(&random_jump); // get a return point
+
+    builder.lines()
+}
+
+fn initialize_action(_: &VMInstruction) -> Vec<Line> {
     let mut builder = AssemblerCommandBuilder::new();
 
     builder.move_value_to_d("256");
     builder.at("SP");
     builder.d_to_m();
 
-    builder.parsed_content()
+    builder.lines()
 }
 
 struct AssemblerCommandBuilder {
-    result: Vec<String>,
+    result: Vec<Line>,
 }
 
 impl AssemblerCommandBuilder {
@@ -523,163 +882,367 @@ impl AssemblerCommandBuilder {
         AssemblerCommandBuilder { result: Vec::new() }
     }
 
-    pub fn parsed_content(&self) -> Vec<String> {
-        self.result.clone()
+    pub fn lines(self) -> Vec<Line> {
+        self.result
+    }
+
+    fn compute(&mut self, dest: &str, comp: &str, jump: &str) {
+        self.result
+            .push(Line::Instruction(Instruction::compute(dest, comp, jump)));
     }
 
     pub fn label(&mut self, value: &str) {
-        self.result.push(format!("({})", value));
+        self.result.push(Line::Label(String::from(value)));
     }
 
     pub fn goto_label(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("0;JMP"));
+        self.at(value);
+        self.compute("", "0", "JMP");
     }
 
     pub fn jump_to_label_if_d_neq(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("D;JNE"));
+        self.at(value);
+        self.compute("", "D", "JNE");
     }
 
-    pub fn jump_to_label_if_d_eq(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("D;JEQ"));
+    pub fn jump_to_label_if_d_gt(&mut self, value: &str) {
+        self.at(value);
+        self.compute("", "D", "JGT");
     }
 
     pub fn at(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
+        self.result
+            .push(Line::Instruction(Instruction::address(value)));
+    }
+
+    pub fn neg_d_to_d(&mut self) {
+        self.compute("D", "-D", "");
+    }
+
+    pub fn not_d_to_d(&mut self) {
+        self.compute("D", "!D", "");
+    }
+
+    pub fn d_plus_m_to_d(&mut self) {
+        self.compute("D", "D+M", "");
+    }
+
+    pub fn d_and_m_to_d(&mut self) {
+        self.compute("D", "D&M", "");
+    }
+
+    pub fn d_or_m_to_d(&mut self) {
+        self.compute("D", "D|M", "");
+    }
+
+    // d = -1 when d passes the jump condition, 0 otherwise
+    pub fn compare_d_to_d(&mut self, compare: &str, true_label: &str, end_label: &str) {
+        self.at(true_label);
+        self.compute("", "D", compare);
+        self.compute("D", "0", "");
+        self.at(end_label);
+        self.compute("", "0", "JMP");
+        self.label(true_label);
+        self.compute("D", "-1", "");
+        self.label(end_label);
+    }
+
+    // SP-- leaving the new SP in A
+    pub fn retreat_sp_to_a(&mut self) {
+        self.at("SP");
+        self.compute("AM", "M-1", "");
+    }
+
+    pub fn m_to_a(&mut self) {
+        self.compute("A", "M", "");
+    }
+
+    pub fn zero_m(&mut self) {
+        self.compute("M", "0", "");
+    }
+
+    pub fn a_plus_one_to_a(&mut self) {
+        self.compute("A", "A+1", "");
+    }
+
+    pub fn a_less_one_to_a(&mut self) {
+        self.compute("A", "A-1", "");
+    }
+
+    pub fn a_plus_one_to_d(&mut self) {
+        self.compute("D", "A+1", "");
+    }
+
+    // SP++ leaving the new SP in A
+    pub fn advance_sp_to_a(&mut self) {
+        self.at("SP");
+        self.compute("AM", "M+1", "");
     }
 
     pub fn d_less_one_to_d(&mut self) {
-        self.result.push(String::from("D=D-1"));
+        self.compute("D", "D-1", "");
     }
 
     pub fn d_plus_a_to_d(&mut self) {
-        self.result.push(String::from("D=D+A"));
+        self.compute("D", "D+A", "");
     }
 
     pub fn d_plus_a_address_to_d(&mut self) {
-        self.result.push(String::from("A=D+A"));
-        self.result.push(String::from("D=M"));
+        self.compute("A", "D+A", "");
+        self.compute("D", "M", "");
     }
 
     pub fn d_plus_m_to_m(&mut self) {
-        self.result.push(String::from("M=D+M"));
+        self.compute("M", "D+M", "");
     }
 
     pub fn m_less_d_to_m(&mut self) {
-        self.result.push(String::from("M=M-D"));
+        self.compute("M", "M-D", "");
     }
 
     pub fn m_less_d_to_d(&mut self) {
-        self.result.push(String::from("D=M-D"));
+        self.compute("D", "M-D", "");
     }
 
     pub fn m_and_d_to_m(&mut self) {
-        self.result.push(String::from("M=M&D"));
+        self.compute("M", "M&D", "");
     }
 
     pub fn m_or_d_to_m(&mut self) {
-        self.result.push(String::from("M=M|D"));
+        self.compute("M", "M|D", "");
     }
 
     pub fn not_m_to_m(&mut self) {
-        self.result.push(String::from("M=!M"));
+        self.compute("M", "!M", "");
     }
 
     pub fn neg_m_to_m(&mut self) {
-        self.result.push(String::from("M=-M"));
+        self.compute("M", "-M", "");
     }
 
     pub fn compare_with_d(&mut self, compare: &str, label_name: &str) {
-        self.result.push(String::from("M=-1")); // m = true
-        self.result.push(format!("@{}", label_name)); // if compare is false, set m to false
-        self.result.push(format!("D;{}", compare));
-        self.result.push(String::from("@SP"));
-        self.result.push(String::from("A=M"));
-        self.result.push(String::from("M=0"));
-        self.result.push(format!("({})", label_name)); // end if
+        self.compute("M", "-1", ""); // m = true
+        self.at(label_name); // if compare is false, set m to false
+        self.compute("", "D", compare);
+        self.at("SP");
+        self.compute("A", "M", "");
+        self.compute("M", "0", "");
+        self.label(label_name); // end if
     }
 
     pub fn m_to_d(&mut self) {
-        self.result.push(String::from("D=M"));
+        self.compute("D", "M", "");
     }
 
     pub fn d_to_m(&mut self) {
-        self.result.push(String::from("M=D"));
+        self.compute("M", "D", "");
     }
 
     pub fn d_to_tmp(&mut self, label: &str) {
-        self.result.push(format!("@{}", label));
-        self.result.push(String::from("M=D"));
+        self.at(label);
+        self.compute("M", "D", "");
     }
 
     pub fn move_value_to_d(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("D=A"));
+        self.at(value);
+        self.compute("D", "A", "");
     }
 
     pub fn get_value_from_segment_plus_d(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("A=M+D"));
-        self.result.push(String::from("D=M"));
+        self.at(value);
+        self.compute("A", "M+D", "");
+        self.compute("D", "M", "");
     }
 
     pub fn get_address_from_segment_plus_d(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("D=M+D"));
+        self.at(value);
+        self.compute("D", "M+D", "");
     }
 
     pub fn get_value_at(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("D=M"));
+        self.at(value);
+        self.compute("D", "M", "");
     }
 
     pub fn goto_value_at(&mut self, value: &str) {
-        self.result.push(format!("@{}", value));
-        self.result.push(String::from("A=M"));
-        self.result.push(String::from("0;JMP"));
+        self.at(value);
+        self.compute("A", "M", "");
+        self.compute("", "0", "JMP");
     }
 
     pub fn advance_sp(&mut self) {
-        self.result.push(String::from("@SP"));
-        self.result.push(String::from("M=M+1"));
+        self.at("SP");
+        self.compute("M", "M+1", "");
     }
 
     pub fn push_to_stack(&mut self) {
-        self.result.push(String::from("@SP"));
-        self.result.push(String::from("A=M"));
-        self.result.push(String::from("M=D"));
-
-        // @SP++
-        self.advance_sp();
-    }
-
-    pub fn push_to_stack_zero(&mut self) {
-        self.result.push(String::from("@SP"));
-        self.result.push(String::from("A=M"));
-        self.result.push(String::from("M=0"));
+        self.at("SP");
+        self.compute("A", "M", "");
+        self.compute("M", "D", "");
 
         // @SP++
         self.advance_sp();
     }
 
     pub fn pop_from_stack(&mut self) {
-        self.result.push(String::from("@SP"));
-        self.result.push(String::from("M=M-1"));
-        self.result.push(String::from("A=M"));
+        self.at("SP");
+        self.compute("M", "M-1", "");
+        self.compute("A", "M", "");
     }
 
     pub fn pop_from_stack_to_d(&mut self) {
         self.pop_from_stack();
-        self.result.push(String::from("D=M"));
+        self.compute("D", "M", "");
     }
 
     pub fn pop_from_stack_to(&mut self, label: &str) {
         self.pop_from_stack_to_d();
 
-        self.result.push(format!("@{}", label));
-        self.result.push(String::from("A=M"));
-        self.result.push(String::from("M=D"));
+        self.at(label);
+        self.compute("A", "M", "");
+        self.compute("M", "D", "");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hack_emulator::Emulator;
+    use crate::test_programs::{run_on_hack, vm_file, PROGRAMS};
+
+    // runs the prologue of a function with `locals` locals from SP = 256, giving its
+    // ROM words and cycles
+    fn measure_prologue(locals: usize, unrolled: bool) -> (usize, usize) {
+        let mut instruction = VMInstruction::new("Main");
+        instruction.command = String::from("function");
+        instruction.detail = String::from("Main.f");
+        instruction.value = locals.to_string();
+        instruction.function_name = String::from("Main.f");
+
+        let prologue = if unrolled {
+            unrolled_function_action(&instruction)
+        } else {
+            looped_function_action(&instruction)
+        };
+
+        let words = prologue
+            .iter()
+            .filter(|line| matches!(line, Line::Instruction(_)))
+            .count();
+
+        let mut asm: Vec<String> = ["@256", "D=A", "@SP", "M=D"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        asm.extend(prologue.iter().map(|line| line.to_string()));
+        asm.extend(
+            ["(END)", "@END", "0;JMP"]
+                .iter()
+                .map(|line| line.to_string()),
+        );
+
+        let mut emulator = Emulator::load(&asm);
+        emulator.run(1000);
+
+        assert_eq!(emulator.ram(0), 256 + locals as i16);
+
+        // less the four setting SP and the two of the final loop
+        (words, emulator.steps - 6)
+    }
+
+    #[test]
+    fn test_programs_leave_the_expected_ram() {
+        for program in PROGRAMS {
+            let emulator = run_on_hack(&program.vm_files(), false);
+
+            for (address, expected) in program.expected {
+                assert_eq!(
+                    emulator.ram(*address),
+                    *expected,
+                    "RAM[{}] after {}",
+                    address,
+                    program.name
+                );
+            }
+        }
+    }
+
+    // the pointers and temp agree as well; R13-R15 are scratch for both
+    #[test]
+    fn cache_top_leaves_the_same_ram() {
+        for program in PROGRAMS {
+            let files = program.vm_files();
+            let plain = run_on_hack(&files, false);
+            let cached = run_on_hack(&files, true);
+
+            let addresses = (0..13).chain(program.expected.iter().map(|(address, _)| *address));
+
+            for address in addresses {
+                assert_eq!(
+                    cached.ram(address),
+                    plain.ram(address),
+                    "RAM[{}] after {} with --cache-top",
+                    address,
+                    program.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cache_top_pops_to_far_segment_indexes() {
+        let source = "
+            function Sys.init 0
+            push constant 3
+            push constant 4
+            call Main.far 2
+            pop temp 0
+            label END
+            goto END
+            function Main.far 12
+            push argument 1
+            push argument 0
+            sub
+            pop local 11
+            push constant 3100
+            pop pointer 0
+            push local 11
+            push constant 10
+            add
+            pop this 9
+            push this 9
+            push constant 5
+            gt
+            if-goto BIG
+            push constant 0
+            return
+            label BIG
+            push this 9
+            return
+        ";
+        let files = vec![vm_file("Sys", source)];
+
+        for cache_top in [false, true] {
+            let emulator = run_on_hack(&files, cache_top);
+
+            assert_eq!(emulator.ram(3109), 11);
+            assert_eq!(emulator.ram(5), 11);
+            assert_eq!(emulator.ram(0), 261);
+        }
+    }
+
+    #[test]
+    fn prologue_cost_matches_the_emitted_prologue() {
+        for locals in [1, 2, 3, 5, 8, 20] {
+            for unrolled in [true, false] {
+                assert_eq!(
+                    measure_prologue(locals, unrolled),
+                    prologue_cost(locals, unrolled),
+                    "{} locals, unrolled: {}",
+                    locals,
+                    unrolled
+                );
+            }
+        }
     }
 }
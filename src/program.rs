@@ -0,0 +1,102 @@
+use std::fmt;
+
+#[derive(Clone)]
+pub struct VmCommand {
+    pub command: String,
+    pub detail: String,
+    pub value: String,
+    pub line: usize,
+}
+
+impl VmCommand {
+    pub fn parse(text: &str, line: usize) -> VmCommand {
+        let parts: Vec<&str> = text.split_whitespace().collect();
+
+        let part = |index: usize| match parts.get(index) {
+            Some(value) => String::from(*value),
+            None => String::new(),
+        };
+
+        VmCommand {
+            command: part(0),
+            detail: part(1),
+            value: part(2),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for VmCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.command)?;
+
+        if !self.detail.is_empty() {
+            write!(f, " {}", self.detail)?;
+        }
+
+        if !self.value.is_empty() {
+            write!(f, " {}", self.value)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct VmFile {
+    pub name: String,
+    pub path: String,
+    pub commands: Vec<VmCommand>,
+}
+
+pub struct VmFunction {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl VmFile {
+    // commands before the first `function` belong to no function and are not listed
+    pub fn functions(&self) -> Vec<VmFunction> {
+        let mut functions: Vec<VmFunction> = Vec::new();
+
+        for (index, command) in self.commands.iter().enumerate() {
+            if command.command != "function" {
+                continue;
+            }
+
+            if let Some(last) = functions.last_mut() {
+                last.end = index;
+            }
+
+            functions.push(VmFunction {
+                name: command.detail.clone(),
+                start: index,
+                end: self.commands.len(),
+            });
+        }
+
+        functions
+    }
+
+    pub fn top_level_end(&self) -> usize {
+        self.commands
+            .iter()
+            .position(|command| command.command == "function")
+            .unwrap_or(self.commands.len())
+    }
+}
+
+// keeps identifiers of other languages valid for any VM name: every other character becomes _XX
+pub fn mangle(name: &str) -> String {
+    let mut mangled = String::new();
+
+    for character in name.chars() {
+        if character.is_ascii_alphanumeric() {
+            mangled.push(character);
+        } else {
+            mangled.push_str(&format!("_{:02X}", character as u32));
+        }
+    }
+
+    mangled
+}
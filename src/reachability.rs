@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::{build_generator, translate_file};
+use crate::diagnostics::Diagnostic;
+use crate::options::Options;
+use crate::program::{VmCommand, VmFile};
+
+pub struct StrippedFunction {
+    pub name: String,
+    pub filename: String,
+    pub words: usize,
+}
+
+// the sizes of the stripped functions are those of the code the active options generate
+pub fn strip_unreachable(
+    files: &mut [VmFile],
+    entry: &str,
+    options: &Options,
+) -> Result<Vec<StrippedFunction>, Diagnostic> {
+    let call_graph = build_call_graph(files);
+
+    if !call_graph.contains_key(entry) {
+        return Err(Diagnostic::error(
+            "undefined-function",
+            format!("entry function {} is not defined", entry),
+        ));
+    }
+
+    if let Some(name) = options
+        .keep
+        .iter()
+        .find(|name| !call_graph.contains_key(*name))
+    {
+        return Err(Diagnostic::error(
+            "undefined-function",
+            format!("function {} given to --keep is not defined", name),
+        ));
+    }
+
+    // code outside any function always runs, so whatever it calls is a root too
+    let mut pending: Vec<String> = vec![String::from(entry)];
+    pending.extend(options.keep.iter().cloned());
+    for file in files.iter() {
+        pending.extend(called_functions(&file.commands[..file.top_level_end()]));
+    }
+
+    let mut reachable: HashSet<String> = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if !reachable.insert(name.clone()) {
+            continue;
+        }
+
+        if let Some(callees) = call_graph.get(&name) {
+            pending.extend(callees.iter().cloned());
+        }
+    }
+
+    let mut stripped: Vec<StrippedFunction> = Vec::new();
+
+    for file in files.iter_mut() {
+        let mut commands: Vec<VmCommand> = file.commands[..file.top_level_end()].to_vec();
+
+        for function in file.functions() {
+            let body = &file.commands[function.start..function.end];
+
+            if reachable.contains(&function.name) {
+                commands.extend(body.iter().cloned());
+            } else {
+                stripped.push(StrippedFunction {
+                    name: function.name,
+                    filename: file.name.clone(),
+                    words: code_size(options, &file.name, body),
+                });
+            }
+        }
+
+        file.commands = commands;
+    }
+
+    Ok(stripped)
+}
+
+// what `body` adds to a file that is otherwise empty, runtime routines only it uses included
+fn code_size(options: &Options, filename: &str, body: &[VmCommand]) -> usize {
+    let size = |commands: &[VmCommand]| {
+        let mut generator = build_generator(options);
+        generator.prologue();
+        translate_file(generator.as_mut(), filename, commands);
+
+        let output = generator.epilogue();
+        generator.code_size(&output)
+    };
+
+    size(body) - size(&[])
+}
+
+pub fn build_call_graph(files: &[VmFile]) -> HashMap<String, Vec<String>> {
+    let mut call_graph: HashMap<String, Vec<String>> = HashMap::new();
+
+    for file in files {
+        for function in file.functions() {
+            let callees = called_functions(&file.commands[function.start..function.end]);
+            call_graph.insert(function.name, callees);
+        }
+    }
+
+    call_graph
+}
+
+pub fn called_functions(commands: &[VmCommand]) -> Vec<String> {
+    commands
+        .iter()
+        .filter(|command| command.command == "call")
+        .map(|command| command.detail.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::vm_file;
+
+    const SOURCE: &str = "function Main.main 0\ncall Main.used 0\nreturn\nfunction Main.used 0\npush constant 0\nreturn\nfunction Main.dead 0\ncall Main.helper 0\nreturn\nfunction Main.helper 0\npush constant 0\nreturn\nfunction Main.debug 0\ncall Main.format 0\nreturn\nfunction Main.format 0\npush constant 0\nreturn\n";
+
+    fn strip(args: &[&str]) -> (Vec<String>, Vec<String>) {
+        let args: Vec<String> = ["vm-translator", "Prog"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = Options::from_args(&args);
+        let mut files = vec![vm_file("Main", SOURCE)];
+
+        let stripped = strip_unreachable(&mut files, "Main.main", &options)
+            .unwrap_or_else(|diagnostic| panic!("{}", diagnostic.message));
+
+        let kept = files[0]
+            .functions()
+            .into_iter()
+            .map(|function| function.name)
+            .collect();
+        let stripped = stripped.into_iter().map(|function| function.name).collect();
+
+        (kept, stripped)
+    }
+
+    #[test]
+    fn functions_only_called_by_stripped_functions_are_stripped_too() {
+        let (kept, mut stripped) = strip(&[]);
+        stripped.sort();
+
+        assert_eq!(kept, ["Main.main", "Main.used"]);
+        assert_eq!(
+            stripped,
+            ["Main.dead", "Main.debug", "Main.format", "Main.helper"]
+        );
+    }
+
+    #[test]
+    fn kept_functions_stay_with_what_they_call() {
+        let (kept, _) = strip(&["--keep", "Main.debug"]);
+
+        assert_eq!(
+            kept,
+            ["Main.main", "Main.used", "Main.debug", "Main.format"]
+        );
+    }
+
+    #[test]
+    fn an_undefined_kept_function_is_an_error() {
+        let args: Vec<String> = ["vm-translator", "Prog", "--keep", "Main.debgu"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let mut files = vec![vm_file("Main", SOURCE)];
+
+        let diagnostic = strip_unreachable(&mut files, "Main.main", &Options::from_args(&args))
+            .err()
+            .expect("Main.debgu is not defined");
+
+        assert_eq!(diagnostic.code, "undefined-function");
+    }
+}
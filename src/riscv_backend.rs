@@ -0,0 +1,722 @@
+use crate::codegen::{CodeGenerator, Statics};
+use crate::program::mangle;
+
+// RV32I has no multiply, so t1 = t1 * t2 is done by shift and add
+const MULTIPLY: &[&str] = &[
+    "vm_mul:",
+    "    li t3, 0",
+    ".Lvm_mul_loop:",
+    "    beqz t2, .Lvm_mul_done",
+    "    andi t4, t2, 1",
+    "    beqz t4, .Lvm_mul_skip",
+    "    add t3, t3, t1",
+    ".Lvm_mul_skip:",
+    "    slli t1, t1, 1",
+    "    srli t2, t2, 1",
+    "    j .Lvm_mul_loop",
+    ".Lvm_mul_done:",
+    "    mv t1, t3",
+    "    ret",
+];
+
+// t1, t2 = t1 / t2, t1 % t2 with the quotient truncated toward zero and the remainder
+// taking the sign of x; dividing by zero gives 0 and leaves x as the remainder
+const DIVIDE: &[&str] = &[
+    "vm_divmod:",
+    "    bnez t2, .Lvm_divmod_start",
+    "    mv t2, t1",
+    "    li t1, 0",
+    "    ret",
+    ".Lvm_divmod_start:",
+    "    mv t5, t1",
+    "    xor a2, t1, t2",
+    "    bgez t1, .Lvm_divmod_x",
+    "    neg t1, t1",
+    ".Lvm_divmod_x:",
+    "    bgez t2, .Lvm_divmod_y",
+    "    neg t2, t2",
+    ".Lvm_divmod_y:",
+    "    li t3, 0",
+    "    li t4, 0",
+    "    li a0, 16",
+    ".Lvm_divmod_loop:",
+    "    slli t4, t4, 1",
+    "    srli a1, t1, 15",
+    "    andi a1, a1, 1",
+    "    or t4, t4, a1",
+    "    slli t1, t1, 1",
+    "    slli t3, t3, 1",
+    "    blt t4, t2, .Lvm_divmod_next",
+    "    sub t4, t4, t2",
+    "    ori t3, t3, 1",
+    ".Lvm_divmod_next:",
+    "    addi a0, a0, -1",
+    "    bnez a0, .Lvm_divmod_loop",
+    "    bgez a2, .Lvm_divmod_quotient",
+    "    neg t3, t3",
+    ".Lvm_divmod_quotient:",
+    "    bgez t5, .Lvm_divmod_remainder",
+    "    neg t4, t4",
+    ".Lvm_divmod_remainder:",
+    "    mv t1, t3",
+    "    mv t2, t4",
+    "    ret",
+];
+
+// s0 holds the address of RAM[0], s1-s5 the SP, LCL, ARG, THIS and THAT pointers,
+// s6 the 0x7FFF address mask and s7 the remaining step budget
+pub struct RiscVGenerator {
+    top_level: Vec<String>,
+    functions: Vec<String>,
+    in_function: bool,
+    filename: String,
+    function_name: String,
+    statics: Statics,
+    return_points: usize,
+    skips: usize,
+    uses_multiply: bool,
+    uses_divide: bool,
+}
+
+impl RiscVGenerator {
+    pub fn new() -> RiscVGenerator {
+        RiscVGenerator {
+            top_level: Vec::new(),
+            functions: Vec::new(),
+            in_function: false,
+            filename: String::new(),
+            function_name: String::new(),
+            statics: Statics::default(),
+            return_points: 0,
+            skips: 0,
+            uses_multiply: false,
+            uses_divide: false,
+        }
+    }
+
+    // code outside functions runs straight from _start, so it is kept apart
+    fn output(&mut self) -> &mut Vec<String> {
+        if self.in_function {
+            &mut self.functions
+        } else {
+            &mut self.top_level
+        }
+    }
+
+    fn line(&mut self, value: &str) {
+        let line = format!("    {}", value);
+        self.output().push(line);
+    }
+
+    fn lines(&mut self, values: &[&str]) {
+        for value in values {
+            self.line(value);
+        }
+    }
+
+    // registers hold 16-bit values sign-extended to 32 bits, so every result is re-wrapped
+    fn wrap(&mut self, register: &str) {
+        self.line(&format!("slli {}, {}, 16", register, register));
+        self.line(&format!("srai {}, {}, 16", register, register));
+    }
+
+    fn add_immediate(&mut self, target: &str, source: &str, value: usize) {
+        if value < 2048 {
+            self.line(&format!("addi {}, {}, {}", target, source, value));
+        } else {
+            self.line(&format!("li t6, {}", value));
+            self.line(&format!("add {}, {}, t6", target, source));
+        }
+    }
+
+    // turns the RAM index in `register` into a byte address in t0
+    fn ram_address(&mut self, register: &str) {
+        self.line(&format!("and t0, {}, s6", register));
+        self.line("slli t0, t0, 1");
+        self.line("add t0, s0, t0");
+    }
+
+    fn push_register(&mut self, register: &str) {
+        self.ram_address("s1");
+        self.line(&format!("sh {}, 0(t0)", register));
+        self.line("addi s1, s1, 1");
+        self.wrap("s1");
+    }
+
+    fn pop_register(&mut self, register: &str) {
+        self.line("addi s1, s1, -1");
+        self.wrap("s1");
+        self.ram_address("s1");
+        self.line(&format!("lh {}, 0(t0)", register));
+    }
+
+    // leaves the byte address of the segment entry in t3
+    fn segment_address(&mut self, segment: &str, index: &str) {
+        let index = parse_index(index);
+
+        let pointer = match segment {
+            "local" => "s2",
+            "argument" => "s3",
+            "this" => "s4",
+            "that" => "s5",
+            "temp" => return self.add_immediate("t3", "s0", (5 + index) * 2),
+            "static" => {
+                let address = self.statics.address(&self.filename, &index.to_string());
+                return self.add_immediate("t3", "s0", address * 2);
+            }
+            _ => panic!("Invalid memory location! {}", segment),
+        };
+
+        self.add_immediate("t3", pointer, index);
+        self.ram_address("t3");
+        self.line("mv t3, t0");
+    }
+
+    fn pointer_register(index: &str) -> &'static str {
+        if index == "0" {
+            "s4"
+        } else {
+            "s5"
+        }
+    }
+
+    fn build_label(&self, label: &str) -> String {
+        format!(".Lvm_{}_{}", mangle(&self.function_name), mangle(label))
+    }
+
+    // conditional branches only reach 4KB, so far targets are jumped to with j
+    fn jump_if(&mut self, condition: &str, register: &str, target: &str) {
+        self.skips += 1;
+        let skip = format!(".Lskip_{}", self.skips);
+        let inverse = if condition == "beqz" { "bnez" } else { "beqz" };
+
+        self.line(&format!("{} {}, {}", inverse, register, skip));
+        self.line(&format!("j {}", target));
+        self.output().push(format!("{}:", skip));
+    }
+
+    fn tick(&mut self) {
+        self.line("addi s7, s7, -1");
+        self.jump_if("beqz", "s7", "vm_halt");
+    }
+}
+
+impl CodeGenerator for RiscVGenerator {
+    fn extension(&self) -> &'static str {
+        "rv.s"
+    }
+
+    fn prologue(&mut self) {
+        let header = [
+            "# Generated by hack_vm_translator: Hack VM program as RV32I assembly.",
+            "# Runs as a Linux user-mode program; on exit RAM (32K 16-bit words) is written",
+            "# to stdout in little-endian binary. vm_fuel limits the steps (0 = unlimited).",
+            "    .data",
+            "    .globl vm_fuel",
+            "vm_fuel:",
+            "    .word 0",
+            "",
+            "    .bss",
+            "    .align 2",
+            "ram:",
+            "    .zero 65536",
+            "",
+            "    .text",
+            "    .globl _start",
+            "_start:",
+            "    la s0, ram",
+            "    li s6, 0x7FFF",
+            "    la t0, vm_fuel",
+            "    lw s7, 0(t0)",
+            "    lh s1, 0(s0)",
+            "    lh s2, 2(s0)",
+            "    lh s3, 4(s0)",
+            "    lh s4, 6(s0)",
+            "    lh s5, 8(s0)",
+        ];
+
+        self.top_level
+            .extend(header.iter().map(|line| line.to_string()));
+    }
+
+    fn bootstrap(&mut self) {
+        self.comment("bootstrap");
+        self.line("li s1, 256");
+        self.call("Sys.init", "0");
+    }
+
+    fn file(&mut self, filename: &str) {
+        self.filename = String::from(filename);
+        self.function_name = String::new();
+        self.in_function = false;
+        self.output().push(format!("# New file: {}", filename));
+    }
+
+    fn comment(&mut self, value: &str) {
+        self.line(&format!("# {}", value));
+    }
+
+    fn epilogue(&mut self) -> Vec<String> {
+        let footer = [
+            "vm_halt:",
+            "    sh s1, 0(s0)",
+            "    sh s2, 2(s0)",
+            "    sh s3, 4(s0)",
+            "    sh s4, 6(s0)",
+            "    sh s5, 8(s0)",
+            "    li a0, 1",
+            "    mv a1, s0",
+            "    li a2, 65536",
+            "    li a7, 64",
+            "    ecall",
+            "    li a0, 0",
+            "    li a7, 93",
+            "    ecall",
+            "",
+        ];
+
+        self.top_level
+            .extend(footer.iter().map(|line| line.to_string()));
+        self.top_level.extend(std::mem::take(&mut self.functions));
+
+        if self.uses_multiply {
+            self.top_level
+                .extend(MULTIPLY.iter().map(|line| line.to_string()));
+        }
+
+        if self.uses_divide {
+            self.top_level
+                .extend(DIVIDE.iter().map(|line| line.to_string()));
+        }
+
+        std::mem::take(&mut self.top_level)
+    }
+
+    fn push(&mut self, segment: &str, index: &str) {
+        match segment {
+            "constant" => self.line(&format!("li t1, {}", index)),
+            "pointer" => self.line(&format!(
+                "mv t1, {}",
+                RiscVGenerator::pointer_register(index)
+            )),
+            _ => {
+                self.segment_address(segment, index);
+                self.line("lh t1, 0(t3)");
+            }
+        }
+
+        self.push_register("t1");
+    }
+
+    fn pop(&mut self, segment: &str, index: &str) {
+        if segment == "pointer" {
+            self.pop_register(RiscVGenerator::pointer_register(index));
+            return;
+        }
+
+        self.segment_address(segment, index);
+        self.pop_register("t1");
+        self.line("sh t1, 0(t3)");
+    }
+
+    // x is in t1 and y in t2; comparisons test the wrapped difference like D=M-D on Hack
+    fn arithmetic(&mut self, operation: &str) {
+        if operation != "neg" && operation != "not" {
+            self.pop_register("t2");
+        }
+
+        self.pop_register("t1");
+
+        match operation {
+            "neg" => self.line("neg t1, t1"),
+            "not" => self.line("not t1, t1"),
+            "add" => self.line("add t1, t1, t2"),
+            "sub" | "eq" | "gt" | "lt" => self.line("sub t1, t1, t2"),
+            "and" => self.line("and t1, t1, t2"),
+            "or" => self.line("or t1, t1, t2"),
+            "xor" => self.line("xor t1, t1, t2"),
+            "shl" => self.lines(&["andi t2, t2, 15", "sll t1, t1, t2"]),
+            "shr" => self.lines(&["andi t2, t2, 15", "sra t1, t1, t2"]),
+            "mul" => {
+                self.uses_multiply = true;
+                self.line("call vm_mul");
+            }
+            "div" => {
+                self.uses_divide = true;
+                self.line("call vm_divmod");
+            }
+            "mod" => {
+                self.uses_divide = true;
+                self.lines(&["call vm_divmod", "mv t1, t2"]);
+            }
+            _ => panic!("Invalid action required! {}", operation),
+        }
+
+        self.wrap("t1");
+
+        match operation {
+            "eq" => self.lines(&["seqz t1, t1", "neg t1, t1"]),
+            "gt" => self.lines(&["sgtz t1, t1", "neg t1, t1"]),
+            "lt" => self.lines(&["sltz t1, t1", "neg t1, t1"]),
+            _ => (),
+        }
+
+        self.push_register("t1");
+    }
+
+    fn label(&mut self, label: &str) {
+        let label = format!("{}:", self.build_label(label));
+        self.output().push(label);
+        self.tick();
+    }
+
+    fn goto(&mut self, label: &str) {
+        let target = self.build_label(label);
+        self.line(&format!("j {}", target));
+    }
+
+    fn if_goto(&mut self, label: &str) {
+        let target = self.build_label(label);
+        self.pop_register("t1");
+        self.jump_if("bnez", "t1", &target);
+    }
+
+    fn function(&mut self, name: &str, locals: &str) {
+        self.function_name = String::from(name);
+        self.in_function = true;
+        self.output().push(format!("vm_{}:", mangle(name)));
+        self.lines(&["addi sp, sp, -16", "sw ra, 12(sp)"]);
+        self.tick();
+
+        for _ in 0..parse_index(locals) {
+            self.push_register("zero");
+        }
+    }
+
+    // ra, saved on the native stack, does the actual returning
+    fn call(&mut self, name: &str, arguments: &str) {
+        self.return_points += 1;
+
+        self.line(&format!("li t1, {}", self.return_points));
+        self.push_register("t1");
+
+        for pointer in &["s2", "s3", "s4", "s5"] {
+            self.push_register(pointer);
+        }
+
+        self.line(&format!("addi s3, s1, -{}", parse_index(arguments) + 5));
+        self.wrap("s3");
+        self.line("mv s2, s1");
+        self.line(&format!("call vm_{}", mangle(name)));
+    }
+
+    fn return_(&mut self) {
+        self.pop_register("t4"); // return value
+        self.line("mv t5, s3");
+        self.line("mv s1, s2");
+
+        for pointer in &["s5", "s4", "s3", "s2", "t1"] {
+            self.pop_register(pointer);
+        }
+
+        self.line("mv s1, t5");
+        self.push_register("t4");
+        self.lines(&["lw ra, 12(sp)", "addi sp, sp, 16", "ret"]);
+    }
+}
+
+fn parse_index(value: &str) -> usize {
+    value.parse().expect("Invalid index")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::codegen::generate;
+    use crate::program::VmFile;
+    use crate::test_programs::PROGRAMS;
+
+    const DATA_BASE: usize = 0x1000;
+    const MEMORY_SIZE: usize = 0x40000;
+
+    enum Operand {
+        Register(usize),
+        Immediate(i32),
+        // offset(register)
+        Memory(i32, usize),
+        Symbol(String),
+    }
+
+    struct Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    }
+
+    // A minimal RV32I machine for the instructions and pseudo-instructions the backend
+    // writes. Text labels are instruction indexes; ra holds one as well.
+    struct Machine {
+        program: Vec<Instruction>,
+        labels: HashMap<String, usize>,
+        registers: [i32; 32],
+        memory: Vec<u8>,
+        output: Vec<u8>,
+    }
+
+    fn register(name: &str) -> Option<usize> {
+        let number = |prefix: &str| name.strip_prefix(prefix)?.parse::<usize>().ok();
+
+        match name {
+            "zero" => Some(0),
+            "ra" => Some(1),
+            "sp" => Some(2),
+            "gp" => Some(3),
+            "tp" => Some(4),
+            _ => match name.chars().next()? {
+                't' => number("t").map(|n| if n < 3 { 5 + n } else { 25 + n }),
+                's' => number("s").map(|n| if n < 2 { 8 + n } else { 16 + n }),
+                'a' => number("a").map(|n| 10 + n),
+                _ => None,
+            },
+        }
+    }
+
+    fn operand(text: &str) -> Operand {
+        if let Some(register) = register(text) {
+            return Operand::Register(register);
+        }
+
+        if let Some(hex) = text.strip_prefix("0x") {
+            return Operand::Immediate(i32::from_str_radix(hex, 16).unwrap());
+        }
+
+        if let Ok(value) = text.parse() {
+            return Operand::Immediate(value);
+        }
+
+        match (text.find('('), text.strip_suffix(')')) {
+            (Some(open), Some(inner)) => Operand::Memory(
+                text[..open].parse().unwrap(),
+                register(&inner[open + 1..]).unwrap(),
+            ),
+            _ => Operand::Symbol(String::from(text)),
+        }
+    }
+
+    impl Machine {
+        fn load(source: &[String]) -> Machine {
+            let mut machine = Machine {
+                program: Vec::new(),
+                labels: HashMap::new(),
+                registers: [0; 32],
+                memory: vec![0; MEMORY_SIZE],
+                output: Vec::new(),
+            };
+            let mut data = DATA_BASE;
+            let mut text = true;
+
+            for line in source {
+                let line = line.split('#').next().unwrap().trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                if let Some(label) = line.strip_suffix(':') {
+                    let address = if text { machine.program.len() } else { data };
+                    machine.labels.insert(String::from(label), address);
+                    continue;
+                }
+
+                let (mnemonic, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+                let operands: Vec<&str> = rest
+                    .split(',')
+                    .map(|operand| operand.trim())
+                    .filter(|operand| !operand.is_empty())
+                    .collect();
+
+                match mnemonic {
+                    ".text" => text = true,
+                    ".data" | ".bss" => text = false,
+                    ".globl" => (),
+                    ".align" => data = (data + 3) & !3,
+                    ".word" => data += 4,
+                    ".zero" => data += operands[0].parse::<usize>().unwrap(),
+                    _ => machine.program.push(Instruction {
+                        mnemonic: String::from(mnemonic),
+                        operands: operands.iter().map(|text| operand(text)).collect(),
+                    }),
+                }
+            }
+
+            machine.registers[2] = MEMORY_SIZE as i32;
+            machine
+        }
+
+        // memory operands give their address
+        fn get(&self, operand: &Operand) -> i32 {
+            match operand {
+                Operand::Register(register) => self.registers[*register],
+                Operand::Immediate(value) => *value,
+                Operand::Memory(offset, register) => {
+                    self.registers[*register].wrapping_add(*offset)
+                }
+                Operand::Symbol(symbol) => self.labels[symbol] as i32,
+            }
+        }
+
+        fn read(&self, address: i32, bytes: usize) -> i32 {
+            let address = address as usize;
+            let mut value: u32 = 0;
+
+            for byte in (0..bytes).rev() {
+                value = value << 8 | self.memory[address + byte] as u32;
+            }
+
+            match bytes {
+                2 => value as u16 as i16 as i32,
+                _ => value as i32,
+            }
+        }
+
+        fn write(&mut self, address: i32, bytes: usize, value: i32) {
+            for byte in 0..bytes {
+                self.memory[address as usize + byte] = (value >> (8 * byte)) as u8;
+            }
+        }
+
+        // runs until the exit system call; panics when `steps` are not enough
+        fn run(&mut self, fuel: i32, steps: usize) {
+            self.write(self.labels["vm_fuel"] as i32, 4, fuel);
+
+            let program = std::mem::take(&mut self.program);
+            let mut pc = self.labels["_start"];
+
+            for _ in 0..steps {
+                let instruction = &program[pc];
+                let values: Vec<i32> = instruction
+                    .operands
+                    .iter()
+                    .map(|operand| self.get(operand))
+                    .collect();
+                let mut next = pc + 1;
+
+                let result: Option<i32> = match instruction.mnemonic.as_str() {
+                    "li" | "la" | "mv" => Some(values[1]),
+                    "neg" => Some(values[1].wrapping_neg()),
+                    "not" => Some(!values[1]),
+                    "seqz" => Some((values[1] == 0) as i32),
+                    "sgtz" => Some((values[1] > 0) as i32),
+                    "sltz" => Some((values[1] < 0) as i32),
+                    "add" | "addi" => Some(values[1].wrapping_add(values[2])),
+                    "sub" => Some(values[1].wrapping_sub(values[2])),
+                    "and" | "andi" => Some(values[1] & values[2]),
+                    "or" | "ori" => Some(values[1] | values[2]),
+                    "xor" => Some(values[1] ^ values[2]),
+                    "sll" | "slli" => Some(values[1] << (values[2] & 31)),
+                    "sra" | "srai" => Some(values[1] >> (values[2] & 31)),
+                    "srli" => Some((values[1] as u32 >> (values[2] & 31)) as i32),
+                    "lh" => Some(self.read(values[1], 2)),
+                    "lw" => Some(self.read(values[1], 4)),
+                    "sh" => {
+                        self.write(values[1], 2, values[0]);
+                        None
+                    }
+                    "sw" => {
+                        self.write(values[1], 4, values[0]);
+                        None
+                    }
+                    "beqz" | "bnez" | "bgez" | "blt" => {
+                        let taken = match instruction.mnemonic.as_str() {
+                            "beqz" => values[0] == 0,
+                            "bnez" => values[0] != 0,
+                            "bgez" => values[0] >= 0,
+                            _ => values[0] < values[1],
+                        };
+
+                        if taken {
+                            next = *values.last().unwrap() as usize;
+                        }
+                        None
+                    }
+                    "j" => {
+                        next = values[0] as usize;
+                        None
+                    }
+                    "call" => {
+                        self.registers[1] = next as i32;
+                        next = values[0] as usize;
+                        None
+                    }
+                    "ret" => {
+                        next = self.registers[1] as usize;
+                        None
+                    }
+                    "ecall" => match self.registers[17] {
+                        64 => {
+                            let start = self.registers[11] as usize;
+                            let end = start + self.registers[12] as usize;
+                            self.output = self.memory[start..end].to_vec();
+                            None
+                        }
+                        93 => return,
+                        call => panic!("unknown system call {}", call),
+                    },
+                    mnemonic => panic!("unknown instruction {}", mnemonic),
+                };
+
+                match (result, &instruction.operands[..]) {
+                    (None, _) | (Some(_), [Operand::Register(0), ..]) => (),
+                    (Some(result), [Operand::Register(register), ..]) => {
+                        self.registers[*register] = result
+                    }
+                    _ => panic!("only registers can be written"),
+                }
+
+                pc = next;
+            }
+
+            panic!("the program did not exit within {} steps", steps);
+        }
+
+        // RAM as written to stdout on exit
+        fn ram(&self, address: usize) -> i16 {
+            i16::from_le_bytes([self.output[2 * address], self.output[2 * address + 1]])
+        }
+    }
+
+    fn run(files: &[VmFile], fuel: i32) -> Machine {
+        let output = generate(&mut RiscVGenerator::new(), files, true);
+        let mut machine = Machine::load(&output);
+
+        machine.run(fuel, 10_000_000);
+        machine
+    }
+
+    #[test]
+    fn test_programs_leave_the_expected_ram() {
+        for program in PROGRAMS {
+            let machine = run(&program.vm_files(), 10_000);
+
+            for (address, expected) in program.expected {
+                assert_eq!(
+                    machine.ram(*address),
+                    *expected,
+                    "RAM[{}] after {}",
+                    address,
+                    program.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fuel_stops_an_endless_loop() {
+        let machine = run(&PROGRAMS[0].vm_files(), 1);
+
+        // the budget runs out on entering Sys.init, before its first command
+        assert_eq!(machine.ram(0), 261);
+        assert_eq!(machine.ram(3), 0);
+    }
+}
@@ -0,0 +1,560 @@
+// Hack routines behind the extended arithmetic commands. Each one takes x and y from
+// the top of the stack, leaves the result in place of x and jumps back to the address
+// the call site stored in R15.
+
+use crate::hack::Line;
+
+pub const EXTENDED_OPERATIONS: [&str; 6] = ["mul", "div", "mod", "shl", "shr", "xor"];
+
+// every routine, in the order they are emitted
+pub const ROUTINES: [&str; 7] = ["mul", "div", "mod", "shl", "shr", "xor", "tail"];
+
+pub fn routine_label(operation: &str) -> String {
+    format!("__vm_{}", operation)
+}
+
+pub fn hack_runtime(operations: &[&str]) -> Vec<Line> {
+    let mut result: Vec<Line> = Vec::new();
+
+    if operations.is_empty() {
+        return result;
+    }
+
+    // code that runs off the end of the program must not fall into the routines
+    result.push(Line::Comment(String::from("Runtime")));
+    result.extend(lines(&["(__vm_runtime)", "@__vm_runtime", "0;JMP"]));
+
+    for operation in operations {
+        result.push(Line::Comment(format!("Runtime: {}", operation)));
+
+        match *operation {
+            "mul" => result.extend(multiply()),
+            "div" => result.extend(divide(false)),
+            "mod" => result.extend(divide(true)),
+            "shl" => result.extend(shift_left()),
+            "shr" => result.extend(shift_right()),
+            "xor" => result.extend(exclusive_or()),
+            "tail" => result.extend(tail_call()),
+            _ => panic!("Invalid action required! {}", operation),
+        }
+    }
+
+    if operations.contains(&"div") || operations.contains(&"mod") {
+        result.push(Line::Comment(String::from("Runtime: div/mod")));
+        result.extend(unsigned_division());
+    }
+
+    result
+}
+
+// the routines are written as assembly text and checked as they are read
+fn lines(values: &[&str]) -> Vec<Line> {
+    values
+        .iter()
+        .map(|value| Line::parse(value).expect("Invalid runtime instruction"))
+        .collect()
+}
+
+// pops y into __vm.y and copies x into __vm.x
+fn operands(operation: &str) -> Vec<Line> {
+    let mut result = vec![Line::Label(routine_label(operation))];
+
+    result.extend(lines(&[
+        "@SP", "AM=M-1", "D=M", "@__vm.y", "M=D", "@SP", "A=M-1", "D=M", "@__vm.x", "M=D",
+    ]));
+
+    result
+}
+
+// replaces x with D and returns to the call site
+fn result_from_d() -> Vec<Line> {
+    lines(&["@SP", "A=M-1", "M=D", "@R15", "A=M", "0;JMP"])
+}
+
+// shift-and-add over the 16 bits of y
+fn multiply() -> Vec<Line> {
+    let mut result = operands("mul");
+
+    result.extend(lines(&[
+        "@__vm.r",
+        "M=0",
+        "@__vm.m",
+        "M=1",
+        "(__vm_mul.loop)",
+        "@__vm.y",
+        "D=M",
+        "@__vm.m",
+        "D=D&M",
+        "@__vm_mul.skip",
+        "D;JEQ",
+        "@__vm.x",
+        "D=M",
+        "@__vm.r",
+        "M=D+M",
+        "(__vm_mul.skip)",
+        "@__vm.x",
+        "D=M",
+        "M=D+M",
+        "@__vm.m",
+        "D=M",
+        "MD=D+M",
+        "@__vm_mul.loop",
+        "D;JNE",
+        "@__vm.r",
+        "D=M",
+    ]));
+
+    result.extend(result_from_d());
+    result
+}
+
+// div and mod share __vm_divmod; __vm.f tells which result to return
+fn divide(remainder: bool) -> Vec<Line> {
+    let operation = if remainder { "mod" } else { "div" };
+    let mut result = operands(operation);
+
+    result.extend(lines(&[
+        "@__vm.f",
+        if remainder { "M=1" } else { "M=0" },
+        "@__vm_divmod",
+        "0;JMP",
+    ]));
+
+    result
+}
+
+// divides |x| by |y| as unsigned 16-bit numbers, one bit of x at a time, then fixes
+// the signs: the quotient truncates toward zero and the remainder has the sign of x.
+// Dividing by zero gives 0 and leaves x as the remainder.
+fn unsigned_division() -> Vec<Line> {
+    let mut result = lines(&[
+        "(__vm_divmod)",
+        "@__vm.x",
+        "D=M",
+        "@__vm.r",
+        "M=D",
+        "@__vm.q",
+        "M=0",
+        "@__vm.y",
+        "D=M",
+        "@__vm_divmod.done",
+        "D;JEQ",
+        // __vm.a = |x|, __vm.b = |y|
+        "@__vm.x",
+        "D=M",
+        "@__vm.a",
+        "M=D",
+        "@__vm_divmod.x_positive",
+        "D;JGE",
+        "@__vm.a",
+        "M=-D",
+        "(__vm_divmod.x_positive)",
+        "@__vm.y",
+        "D=M",
+        "@__vm.b",
+        "M=D",
+        "@__vm_divmod.y_positive",
+        "D;JGE",
+        "@__vm.b",
+        "M=-D",
+        "(__vm_divmod.y_positive)",
+        "@__vm.r",
+        "M=0",
+        "@16",
+        "D=A",
+        "@__vm.n",
+        "M=D",
+        "(__vm_divmod.loop)",
+        // r = 2r + top bit of a, a = 2a, q = 2q
+        "@__vm.r",
+        "D=M",
+        "M=D+M",
+        "@__vm.a",
+        "D=M",
+        "M=D+M",
+        "@__vm_divmod.no_carry",
+        "D;JGE",
+        "@__vm.r",
+        "M=M+1",
+        "(__vm_divmod.no_carry)",
+        "@__vm.q",
+        "D=M",
+        "M=D+M",
+        // unsigned r >= b
+        "@__vm.r",
+        "D=M",
+        "@__vm_divmod.r_negative",
+        "D;JLT",
+        "@__vm.b",
+        "D=M",
+        "@__vm_divmod.next",
+        "D;JLT",
+        "@__vm_divmod.compare",
+        "0;JMP",
+        "(__vm_divmod.r_negative)",
+        "@__vm.b",
+        "D=M",
+        "@__vm_divmod.subtract",
+        "D;JGE",
+        "(__vm_divmod.compare)",
+        "@__vm.b",
+        "D=M",
+        "@__vm.r",
+        "D=M-D",
+        "@__vm_divmod.next",
+        "D;JLT",
+        "(__vm_divmod.subtract)",
+        "@__vm.b",
+        "D=M",
+        "@__vm.r",
+        "M=M-D",
+        "@__vm.q",
+        "M=M+1",
+        "(__vm_divmod.next)",
+        "@__vm.n",
+        "MD=M-1",
+        "@__vm_divmod.loop",
+        "D;JNE",
+        // the quotient is negative when the signs differ, the remainder follows x
+        "@__vm.x",
+        "D=M",
+        "@__vm_divmod.remainder_sign",
+        "D;JGE",
+        "@__vm.r",
+        "M=-M",
+        "(__vm_divmod.remainder_sign)",
+        "@__vm.x",
+        "D=M",
+        "@__vm_divmod.x_sign",
+        "D;JLT",
+        "@__vm.y",
+        "D=M",
+        "@__vm_divmod.done",
+        "D;JGE",
+        "@__vm_divmod.negate",
+        "0;JMP",
+        "(__vm_divmod.x_sign)",
+        "@__vm.y",
+        "D=M",
+        "@__vm_divmod.done",
+        "D;JLT",
+        "(__vm_divmod.negate)",
+        "@__vm.q",
+        "M=-M",
+        "(__vm_divmod.done)",
+        "@__vm.f",
+        "D=M",
+        "@__vm_divmod.remainder",
+        "D;JNE",
+        "@__vm.q",
+        "D=M",
+        "@__vm_divmod.return",
+        "0;JMP",
+        "(__vm_divmod.remainder)",
+        "@__vm.r",
+        "D=M",
+        "(__vm_divmod.return)",
+    ]);
+
+    result.extend(result_from_d());
+    result
+}
+
+// doubles x (y & 15) times
+fn shift_left() -> Vec<Line> {
+    let mut result = operands("shl");
+
+    result.extend(lines(&[
+        "@__vm.y",
+        "D=M",
+        "@15",
+        "D=D&A",
+        "@__vm.y",
+        "M=D",
+        "(__vm_shl.loop)",
+        "@__vm.y",
+        "D=M",
+        "@__vm_shl.done",
+        "D;JEQ",
+        "@__vm.y",
+        "M=M-1",
+        "@__vm.x",
+        "D=M",
+        "M=D+M",
+        "@__vm_shl.loop",
+        "0;JMP",
+        "(__vm_shl.done)",
+        "@__vm.x",
+        "D=M",
+    ]));
+
+    result.extend(result_from_d());
+    result
+}
+
+// arithmetic shift by (y & 15): copies bit n+i of x to bit i, then fills the top n
+// bits with the sign of x
+fn shift_right() -> Vec<Line> {
+    let mut result = operands("shr");
+
+    result.extend(lines(&[
+        "@__vm.y",
+        "D=M",
+        "@15",
+        "D=D&A",
+        "@__vm.y",
+        "M=D",
+        "@__vm.m",
+        "M=1",
+        "(__vm_shr.mask)",
+        "@__vm.y",
+        "D=M",
+        "@__vm_shr.bits",
+        "D;JEQ",
+        "@__vm.y",
+        "M=M-1",
+        "@__vm.m",
+        "D=M",
+        "M=D+M",
+        "@__vm_shr.mask",
+        "0;JMP",
+        "(__vm_shr.bits)",
+        "@__vm.r",
+        "M=0",
+        "@__vm.d",
+        "M=1",
+        "(__vm_shr.loop)",
+        "@__vm.x",
+        "D=M",
+        "@__vm.m",
+        "D=D&M",
+        "@__vm_shr.skip",
+        "D;JEQ",
+        "@__vm.d",
+        "D=M",
+        "@__vm.r",
+        "M=D|M",
+        "(__vm_shr.skip)",
+        "@__vm.d",
+        "D=M",
+        "M=D+M",
+        "@__vm.m",
+        "D=M",
+        "MD=D+M",
+        "@__vm_shr.loop",
+        "D;JNE",
+        "@__vm.x",
+        "D=M",
+        "@__vm_shr.done",
+        "D;JGE",
+        "@__vm.d",
+        "D=-M",
+        "@__vm.r",
+        "M=D|M",
+        "(__vm_shr.done)",
+        "@__vm.r",
+        "D=M",
+    ]));
+
+    result.extend(result_from_d());
+    result
+}
+
+// x ^ y = (x | y) & !(x & y)
+fn exclusive_or() -> Vec<Line> {
+    let mut result = operands("xor");
+
+    result.extend(lines(&[
+        "@__vm.x", "D=M", "@__vm.y", "D=D&M", "D=!D", "@__vm.r", "M=D", "@__vm.x", "D=M",
+        "@__vm.y", "D=D|M", "@__vm.r", "D=D&M",
+    ]));
+
+    result.extend(result_from_d());
+    result
+}
+
+// Tail call of the function at R14 with the R13 arguments on top of the stack. The
+// saved frame of the current function is pushed above the arguments and the whole
+// block moved down to ARG, so the callee returns straight to our caller. ARG stays
+// the same; moving down never overwrites words that are still to be read.
+fn tail_call() -> Vec<Line> {
+    lines(&[
+        "(__vm_tail)",
+        "@R13",
+        "D=M",
+        "@5",
+        "D=D+A",
+        "@__vm.n",
+        "M=D",
+        "@R14",
+        "D=M",
+        "@__vm.target",
+        "M=D",
+        // push the return address and the saved LCL, ARG, THIS and THAT
+        "@5",
+        "D=A",
+        "@LCL",
+        "D=M-D",
+        "@__vm.src",
+        "M=D",
+        "@5",
+        "D=A",
+        "@__vm.i",
+        "M=D",
+        "(__vm_tail.frame)",
+        "@__vm.src",
+        "A=M",
+        "D=M",
+        "@SP",
+        "A=M",
+        "M=D",
+        "@SP",
+        "M=M+1",
+        "@__vm.src",
+        "M=M+1",
+        "@__vm.i",
+        "MD=M-1",
+        "@__vm_tail.frame",
+        "D;JGT",
+        // move arguments and frame down to ARG
+        "@__vm.n",
+        "D=M",
+        "@SP",
+        "D=M-D",
+        "@__vm.src",
+        "M=D",
+        "@ARG",
+        "D=M",
+        "@__vm.dst",
+        "M=D",
+        "(__vm_tail.move)",
+        "@__vm.src",
+        "A=M",
+        "D=M",
+        "@__vm.dst",
+        "A=M",
+        "M=D",
+        "@__vm.src",
+        "M=M+1",
+        "@__vm.dst",
+        "M=M+1",
+        "@__vm.n",
+        "MD=M-1",
+        "@__vm_tail.move",
+        "D;JGT",
+        // the callee starts with an empty frame right after the moved block
+        "@__vm.dst",
+        "D=M",
+        "@SP",
+        "M=D",
+        "@LCL",
+        "M=D",
+        "@__vm.target",
+        "A=M",
+        "0;JMP",
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_programs::{run_on_hack, vm_file};
+
+    const VALUES: [i16; 16] = [
+        -32768, -32767, -12345, -256, -17, -5, -1, 0, 1, 2, 3, 15, 16, 17, 4321, 32767,
+    ];
+
+    const RESULTS: usize = 3000;
+
+    fn reference(operation: &str, x: i16, y: i16) -> i16 {
+        match operation {
+            "mul" => x.wrapping_mul(y),
+            "div" if y == 0 => 0,
+            "div" => x.wrapping_div(y),
+            "mod" if y == 0 => x,
+            "mod" => x.wrapping_rem(y),
+            "shl" => ((x as u16) << (y & 15)) as i16,
+            "shr" => x >> (y & 15),
+            "xor" => x ^ y,
+            _ => unreachable!(),
+        }
+    }
+
+    fn push(value: i16) -> String {
+        match value {
+            -32768 => String::from("push constant 32767\nneg\npush constant 1\nsub\n"),
+            value if value < 0 => format!("push constant {}\nneg\n", -value),
+            value => format!("push constant {}\n", value),
+        }
+    }
+
+    // applies `operation` to every pair of VALUES, writing the results from RAM[3000] on
+    fn run(operation: &str, cache_top: bool) -> Vec<(i16, i16, i16)> {
+        let mut source = format!(
+            "function Sys.init 0\npush constant {}\npop pointer 1\n",
+            RESULTS
+        );
+        let mut pairs: Vec<(i16, i16)> = Vec::new();
+
+        for x in VALUES.iter() {
+            for y in VALUES.iter() {
+                source.push_str(&push(*x));
+                source.push_str(&push(*y));
+                source.push_str(&format!("{}\npop that 0\n", operation));
+                source.push_str("push pointer 1\npush constant 1\nadd\npop pointer 1\n");
+                pairs.push((*x, *y));
+            }
+        }
+
+        source.push_str("label END\ngoto END\n");
+
+        let emulator = run_on_hack(&[vm_file("Sys", &source)], cache_top);
+
+        pairs
+            .iter()
+            .enumerate()
+            .map(|(index, (x, y))| (*x, *y, emulator.ram(RESULTS + index)))
+            .collect()
+    }
+
+    fn check(operation: &str) {
+        for cache_top in [false, true] {
+            for (x, y, result) in run(operation, cache_top) {
+                assert_eq!(
+                    result,
+                    reference(operation, x, y),
+                    "{} {} {} (cache-top {})",
+                    x,
+                    operation,
+                    y,
+                    cache_top
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mul_wraps_like_16_bit_multiplication() {
+        check("mul");
+    }
+
+    #[test]
+    fn div_truncates_toward_zero_and_gives_0_for_a_zero_divisor() {
+        check("div");
+    }
+
+    #[test]
+    fn mod_follows_the_sign_of_x_and_gives_x_for_a_zero_divisor() {
+        check("mod");
+    }
+
+    #[test]
+    fn shifts_use_the_low_four_bits_of_y() {
+        check("shl");
+        check("shr");
+    }
+
+    #[test]
+    fn xor() {
+        check("xor");
+    }
+}
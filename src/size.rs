@@ -0,0 +1,184 @@
+use std::fmt;
+
+use crate::diagnostics::Diagnostic;
+use crate::hack::Instruction;
+use crate::parser::prologue_cost;
+
+pub const ROM_SIZE: usize = 32768;
+
+const TOP_LEVEL: &str = "(top level)";
+const RUNTIME: &str = "(runtime)";
+
+// labels and comments do not take ROM space
+pub fn count_instructions(asm: &[String]) -> usize {
+    asm.iter().filter(|line| is_instruction(line)).count()
+}
+
+fn is_instruction(line: &str) -> bool {
+    !line.starts_with("//") && !line.starts_with('(')
+}
+
+pub struct SizeReport {
+    pub total: usize,
+    pub files: Vec<(String, usize)>,
+    pub functions: Vec<(String, usize)>,
+    // (function, locals) for every function that has locals to zero
+    pub prologues: Vec<(String, usize)>,
+    pub prologue_threshold: usize,
+}
+
+impl SizeReport {
+    // relies on the `// New file:`, `// function` and `// Runtime` comments of the Hack output
+    pub fn from_asm(asm: &[String], prologue_threshold: usize) -> SizeReport {
+        let mut report = SizeReport {
+            total: 0,
+            files: Vec::new(),
+            functions: Vec::new(),
+            prologues: Vec::new(),
+            prologue_threshold,
+        };
+
+        for line in asm {
+            if let Some(filename) = line.strip_prefix("// New file: ") {
+                report.files.push((String::from(filename), 0));
+                report
+                    .functions
+                    .push((format!("{} {}", filename, TOP_LEVEL), 0));
+            } else if line == "// Runtime" {
+                report.files.push((String::from(RUNTIME), 0));
+                report.functions.push((String::from(RUNTIME), 0));
+            } else if let Some(function) = line.strip_prefix("// function ") {
+                let mut parts = function.split(' ');
+                let name = parts.next().unwrap_or(function);
+                report.functions.push((String::from(name), 0));
+
+                match parts.next().and_then(|locals| locals.parse().ok()) {
+                    Some(0) | None => (),
+                    Some(locals) => report.prologues.push((String::from(name), locals)),
+                }
+            } else if is_instruction(line) {
+                report.total += 1;
+
+                if let Some(file) = report.files.last_mut() {
+                    file.1 += 1;
+                }
+
+                if let Some(function) = report.functions.last_mut() {
+                    function.1 += 1;
+                }
+            }
+        }
+
+        report.functions.retain(|(_, words)| *words > 0);
+
+        report
+    }
+
+    // the unrolled prologue is always faster but grows with the number of locals,
+    // so show what the other shape would cost next to the one that was picked
+    fn write_prologues(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Function prologues (unrolled up to {} locals):",
+            self.prologue_threshold
+        )?;
+        writeln!(f, "   words  cycles  function")?;
+
+        let mut prologues: Vec<&(String, usize)> = self.prologues.iter().collect();
+        prologues.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        for (name, locals) in prologues {
+            let unrolled = *locals <= self.prologue_threshold;
+            let (words, cycles) = prologue_cost(*locals, unrolled);
+            let (other_words, other_cycles) = prologue_cost(*locals, !unrolled);
+
+            writeln!(
+                f,
+                "  {:>6}  {:>6}  {} ({} local{}, {}; {} would take {} words, {} cycles)",
+                words,
+                cycles,
+                name,
+                locals,
+                if *locals == 1 { "" } else { "s" },
+                if unrolled { "unrolled" } else { "loop" },
+                if unrolled { "a loop" } else { "unrolled" },
+                other_words,
+                other_cycles
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "ROM usage: {} / {} words ({:.1}%)",
+            self.total,
+            ROM_SIZE,
+            self.total as f64 * 100.0 / ROM_SIZE as f64
+        )?;
+
+        writeln!(f, "By file:")?;
+        write_sorted(f, &self.files)?;
+
+        writeln!(f, "By function:")?;
+        write_sorted(f, &self.functions)?;
+
+        if !self.prologues.is_empty() {
+            self.write_prologues(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_sorted(f: &mut fmt::Formatter, entries: &[(String, usize)]) -> fmt::Result {
+    let mut entries: Vec<&(String, usize)> = entries.iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    for (name, words) in entries {
+        writeln!(f, "  {:>6}  {}", words, name)?;
+    }
+
+    Ok(())
+}
+
+pub fn check_rom_budget(asm: &[String]) -> Result<(), Diagnostic> {
+    let mut function = String::from(TOP_LEVEL);
+
+    for line in asm {
+        if let Some(filename) = line.strip_prefix("// New file: ") {
+            function = format!("{} {}", filename, TOP_LEVEL);
+        } else if let Some(name) = line.strip_prefix("// function ") {
+            function = String::from(name.split(' ').next().unwrap_or(name));
+        }
+
+        // every instruction must encode, which also keeps constants within 15 bits
+        if is_instruction(line) {
+            if let Err(message) = Instruction::parse(line) {
+                return Err(Diagnostic::error(
+                    "invalid-instruction",
+                    format!("{} in {}", message, function),
+                ));
+            }
+        }
+    }
+
+    let total = count_instructions(asm);
+
+    if total > ROM_SIZE {
+        return Err(Diagnostic::error(
+            "rom-overflow",
+            format!(
+                "program needs {} ROM words but the Hack ROM only holds {}",
+                total, ROM_SIZE
+            ),
+        )
+        .note(String::from("run with --size-report for a breakdown")));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::program::{VmCommand, VmFile};
+
+pub struct StackUsage {
+    pub function: String,
+    pub max_depth: i32,
+}
+
+// (values consumed, values produced) by each command
+pub fn stack_effect(command: &VmCommand) -> (i32, i32) {
+    match command.command.as_str() {
+        "push" => (0, 1),
+        "pop" | "if-goto" => (1, 0),
+        "add" | "sub" | "eq" | "lt" | "gt" | "and" | "or" => (2, 1),
+        "mul" | "div" | "mod" | "shl" | "shr" | "xor" => (2, 1),
+        "neg" | "not" | "return" => (1, 1),
+        "call" => (command.value.parse().unwrap_or(0), 1),
+        _ => (0, 0),
+    }
+}
+
+pub fn check_stack_depth(files: &[VmFile]) -> (Vec<Diagnostic>, Vec<StackUsage>) {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut usage: Vec<StackUsage> = Vec::new();
+
+    for file in files {
+        for function in file.functions() {
+            // the function command itself opens the frame with an empty working stack
+            let body = &file.commands[function.start + 1..function.end];
+            let (function_diagnostics, max_depth) = analyze(&file.path, body);
+
+            diagnostics.extend(function_diagnostics);
+            usage.push(StackUsage {
+                function: function.name,
+                max_depth,
+            });
+        }
+    }
+
+    (diagnostics, usage)
+}
+
+fn analyze(path: &str, body: &[VmCommand]) -> (Vec<Diagnostic>, i32) {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+
+    for (index, command) in body.iter().enumerate() {
+        if command.command == "label" {
+            labels.entry(&command.detail).or_insert(index);
+        }
+    }
+
+    let mut depths: Vec<Option<i32>> = vec![None; body.len()];
+    let mut conflicts: Vec<bool> = vec![false; body.len()];
+    let mut pending: Vec<(usize, i32)> = vec![(0, 0)];
+    let mut max_depth = 0;
+
+    while let Some((index, depth)) = pending.pop() {
+        if index >= body.len() {
+            continue;
+        }
+
+        let command = &body[index];
+
+        match depths[index] {
+            Some(known) if known == depth => continue,
+            Some(_) if conflicts[index] => continue,
+            Some(known) => {
+                conflicts[index] = true;
+                diagnostics.push(
+                    Diagnostic::error(
+                        "inconsistent-stack-depth",
+                        format!(
+                            "stack depth reaching `{}` is {} on one path and {} on another",
+                            command, known, depth
+                        ),
+                    )
+                    .at(path, command.line),
+                );
+                continue;
+            }
+            None => depths[index] = Some(depth),
+        }
+
+        let (consumed, produced) = stack_effect(command);
+
+        if depth < consumed {
+            diagnostics.push(
+                Diagnostic::error(
+                    "stack-underflow",
+                    format!(
+                        "`{}` needs {} values but the stack only holds {}",
+                        command, consumed, depth
+                    ),
+                )
+                .at(path, command.line),
+            );
+            continue;
+        }
+
+        let next_depth = depth - consumed + produced;
+        max_depth = max_depth.max(next_depth);
+
+        match command.command.as_str() {
+            "return" => {
+                if depth != 1 {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            "return-stack-depth",
+                            format!("return with {} values on the stack instead of 1", depth),
+                        )
+                        .at(path, command.line)
+                        .note(String::from(
+                            "the caller would find its stack in an undefined state",
+                        )),
+                    );
+                }
+            }
+            "goto" => {
+                if let Some(target) = labels.get(command.detail.as_str()) {
+                    pending.push((*target, next_depth));
+                }
+            }
+            "if-goto" => {
+                if let Some(target) = labels.get(command.detail.as_str()) {
+                    pending.push((*target, next_depth));
+                }
+                pending.push((index + 1, next_depth));
+            }
+            _ => pending.push((index + 1, next_depth)),
+        }
+    }
+
+    (diagnostics, max_depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::vm_file;
+
+    // the code and line of every diagnostic
+    type Findings = Vec<(&'static str, usize)>;
+
+    fn check(source: &str) -> (Findings, Vec<(String, i32)>) {
+        let (diagnostics, usage) = check_stack_depth(&[vm_file("Main", source)]);
+
+        let found = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.location.as_ref().unwrap().line))
+            .collect();
+        let depths = usage
+            .into_iter()
+            .map(|usage| (usage.function, usage.max_depth))
+            .collect();
+
+        (found, depths)
+    }
+
+    #[test]
+    fn the_deepest_branch_and_call_arguments_set_the_maximum() {
+        let source = "function Main.f 0\npush constant 0\nif-goto DEEP\npush constant 1\ngoto DONE\nlabel DEEP\npush constant 1\npush constant 2\npush constant 3\ncall Main.g 3\nlabel DONE\nreturn\nfunction Main.g 0\npush constant 0\nreturn\n";
+
+        let (diagnostics, depths) = check(source);
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            depths,
+            [(String::from("Main.f"), 3), (String::from("Main.g"), 1)]
+        );
+    }
+
+    #[test]
+    fn paths_meeting_with_different_depths_are_an_error() {
+        let source = "function Main.f 0\npush constant 0\nif-goto MERGE\npush constant 1\nlabel MERGE\npush constant 2\nreturn\n";
+
+        let (diagnostics, _) = check(source);
+
+        // the return after the merge is also reached with the depth of one of the paths
+        assert!(diagnostics.contains(&("inconsistent-stack-depth", 5)));
+    }
+
+    #[test]
+    fn popping_an_empty_working_stack_is_an_underflow() {
+        let source = "function Main.f 0\npush constant 1\nadd\nreturn\n";
+
+        assert_eq!(check(source).0, [("stack-underflow", 3)]);
+    }
+
+    #[test]
+    fn return_needs_exactly_one_value() {
+        let source = "function Main.f 0\npush constant 1\npush constant 2\nreturn\n";
+
+        assert_eq!(check(source).0, [("return-stack-depth", 4)]);
+    }
+}
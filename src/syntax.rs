@@ -0,0 +1,143 @@
+use crate::diagnostics::{closest, Diagnostic};
+use crate::hack::{is_symbol, MAX_A_VALUE};
+use crate::program::{VmCommand, VmFile};
+use crate::runtime::EXTENDED_OPERATIONS;
+
+pub const ARITHMETIC: [&str; 9] = ["add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not"];
+
+pub const COMMANDS: [&str; 8] = [
+    "push", "pop", "label", "goto", "if-goto", "function", "call", "return",
+];
+
+pub const SEGMENTS: [&str; 8] = [
+    "argument", "local", "static", "constant", "this", "that", "pointer", "temp",
+];
+
+// every command is checked on its own, before anything tries to translate it
+pub fn check_syntax(files: &[VmFile]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for file in files {
+        for command in &file.commands {
+            if let Err(diagnostic) = check_command(command) {
+                diagnostics.push(diagnostic.at(&file.path, command.line));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn check_command(command: &VmCommand) -> Result<(), Diagnostic> {
+    let name = command.command.as_str();
+
+    let operands = match name {
+        "push" | "pop" | "function" | "call" => 2,
+        "label" | "goto" | "if-goto" => 1,
+        "return" => 0,
+        _ if ARITHMETIC.contains(&name) || EXTENDED_OPERATIONS.contains(&name) => 0,
+        _ => {
+            let mut diagnostic =
+                Diagnostic::error("unknown-command", format!("`{}` is not a VM command", name));
+
+            let known = ARITHMETIC
+                .iter()
+                .chain(&COMMANDS)
+                .chain(&EXTENDED_OPERATIONS);
+
+            if let Some(suggestion) = closest(name, known.copied()) {
+                diagnostic = diagnostic.suggest(
+                    format!("did you mean `{}`?", suggestion),
+                    command_text(suggestion, &command.detail, &command.value),
+                );
+            }
+
+            return Err(diagnostic);
+        }
+    };
+
+    let given = [&command.detail, &command.value]
+        .iter()
+        .filter(|operand| !operand.is_empty())
+        .count();
+
+    if given != operands {
+        return Err(Diagnostic::error(
+            "invalid-operands",
+            format!("{} takes {} operands, not {}", name, operands, given),
+        ));
+    }
+
+    match name {
+        "push" | "pop" => check_segment(name, &command.detail, &command.value),
+        "label" | "goto" | "if-goto" if !is_symbol(&command.detail) => Err(Diagnostic::error(
+            "invalid-name",
+            format!("`{}` is not a valid label name", command.detail),
+        )),
+        "function" | "call" if !is_symbol(&command.detail) => Err(Diagnostic::error(
+            "invalid-name",
+            format!("`{}` is not a valid function name", command.detail),
+        )),
+        "function" if command.value.parse::<u16>().is_err() => Err(Diagnostic::error(
+            "invalid-count",
+            format!("`{}` is not a number of locals", command.value),
+        )),
+        "call" if command.value.parse::<u16>().is_err() => Err(Diagnostic::error(
+            "invalid-count",
+            format!("`{}` is not a number of arguments", command.value),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_segment(command: &str, segment: &str, index: &str) -> Result<(), Diagnostic> {
+    if !SEGMENTS.contains(&segment) {
+        let mut diagnostic =
+            Diagnostic::error("invalid-segment", format!("`{}` is not a segment", segment))
+                .note(format!("segments are {}", SEGMENTS.join(", ")));
+
+        if let Some(suggestion) = closest(segment, SEGMENTS.iter().copied()) {
+            diagnostic = diagnostic.suggest(
+                format!("did you mean `{}`?", suggestion),
+                command_text(command, suggestion, index),
+            );
+        }
+
+        return Err(diagnostic);
+    }
+
+    if command == "pop" && segment == "constant" {
+        return Err(Diagnostic::error(
+            "invalid-segment",
+            String::from("cannot pop to constant"),
+        ));
+    }
+
+    let size = match segment {
+        "pointer" => 2,
+        "temp" => 8,
+        _ => MAX_A_VALUE as usize + 1,
+    };
+
+    match index.parse::<usize>() {
+        Ok(index) if index < size => Ok(()),
+        Ok(_) => Err(Diagnostic::error(
+            "invalid-index",
+            format!("{} {} is out of range", segment, index),
+        )
+        .note(format!("{} has {} slots", segment, size))),
+        Err(_) => Err(Diagnostic::error(
+            "invalid-index",
+            format!("`{}` is not an index", index),
+        )),
+    }
+}
+
+fn command_text(name: &str, detail: &str, value: &str) -> String {
+    [name, detail, value]
+        .iter()
+        .filter(|word| !word.is_empty())
+        .cloned()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
@@ -0,0 +1,138 @@
+use crate::program::{VmCommand, VmFile};
+
+// turns `call F n` + `return` inside a function into a single `tail-call F n`, which
+// generators translate into a jump reusing the frame of the current function
+pub fn mark_tail_calls(files: &mut [VmFile]) {
+    for file in files.iter_mut() {
+        let top_level_end = file.top_level_end();
+        let mut commands: Vec<VmCommand> = Vec::new();
+
+        for (index, command) in file.commands.drain(..).enumerate() {
+            if command.command == "return" && index > top_level_end {
+                if let Some(call) = commands.last_mut().filter(|last| last.command == "call") {
+                    call.command = String::from("tail-call");
+                    continue;
+                }
+            }
+
+            commands.push(command);
+        }
+
+        file.commands = commands;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hack_emulator::Emulator;
+    use crate::test_programs::{run_on_hack, vm_file};
+
+    // Main.sum adds n + (n-1) + ... + 1 to acc; Main.spread passes the count on to
+    // Main.gather with more arguments than it got, which tail-calls back with fewer
+    const PROGRAM: &str = "
+        function Main.sum 0
+        push argument 0
+        if-goto MORE
+        push argument 1
+        return
+        label MORE
+        push argument 0
+        push constant 1
+        sub
+        push argument 1
+        push argument 0
+        add
+        call Main.sum 2
+        return
+        function Main.spread 1
+        push argument 0
+        push constant 7
+        push argument 0
+        call Main.gather 3
+        return
+        function Main.gather 0
+        push argument 0
+        if-goto MORE
+        push argument 1
+        push argument 2
+        add
+        return
+        label MORE
+        push argument 0
+        push constant 1
+        sub
+        call Main.spread 1
+        return
+    ";
+
+    fn run(entry: &str, n: i16, tail_calls: bool, cache_top: bool) -> Emulator {
+        let init = format!(
+            "function Sys.init 0\npush constant {}\npush constant 0\ncall {} 2\npop temp 0\nlabel END\ngoto END\n",
+            n, entry
+        );
+        let mut files = vec![vm_file("Sys", &init), vm_file("Main", PROGRAM)];
+
+        if tail_calls {
+            mark_tail_calls(&mut files);
+        }
+
+        run_on_hack(&files, cache_top)
+    }
+
+    #[test]
+    fn call_then_return_becomes_a_tail_call() {
+        let mut files = vec![vm_file("Main", PROGRAM)];
+
+        mark_tail_calls(&mut files);
+
+        let tail_calls: Vec<String> = files[0]
+            .commands
+            .iter()
+            .filter(|command| command.command == "tail-call")
+            .map(|command| command.to_string())
+            .collect();
+
+        assert_eq!(
+            tail_calls,
+            [
+                "tail-call Main.sum 2",
+                "tail-call Main.gather 3",
+                "tail-call Main.spread 1"
+            ]
+        );
+        assert_eq!(
+            files[0]
+                .commands
+                .iter()
+                .filter(|command| command.command == "return")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn tail_calls_keep_the_results() {
+        for cache_top in [false, true] {
+            assert_eq!(run("Main.sum", 200, true, cache_top).ram(5), 20100);
+            assert_eq!(run("Main.spread", 50, true, cache_top).ram(5), 7);
+            assert_eq!(run("Main.sum", 200, false, cache_top).ram(5), 20100);
+        }
+    }
+
+    #[test]
+    fn tail_recursion_runs_in_constant_stack_depth() {
+        for entry in ["Main.sum", "Main.spread"] {
+            for cache_top in [false, true] {
+                let short = run(entry, 10, true, cache_top);
+                let long = run(entry, 200, true, cache_top);
+
+                assert_eq!(short.max_sp, long.max_sp, "{}", entry);
+                assert_eq!(long.ram(0), 261);
+            }
+
+            // without the pass every call keeps its frame
+            assert!(run(entry, 200, false, false).max_sp > run(entry, 10, false, false).max_sp);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+// VM programs run on every target that has an emulator in the tests. Each one starts at
+// Sys.init, stores its results in temp or on the heap, where every target puts them at
+// the same address, and ends in an endless loop.
+
+use std::collections::HashMap;
+use std::process::{self, Command};
+use std::{env, fs};
+
+use crate::builder::build_content;
+use crate::hack_emulator::{run_program, Emulator};
+use crate::parser::{HackGenerator, DEFAULT_PROLOGUE_THRESHOLD};
+use crate::program::VmFile;
+
+pub struct TestProgram {
+    pub name: &'static str,
+    pub files: &'static [(&'static str, &'static str)],
+    // RAM words the program must leave behind
+    pub expected: &'static [(usize, i16)],
+}
+
+impl TestProgram {
+    pub fn vm_files(&self) -> Vec<VmFile> {
+        self.files
+            .iter()
+            .map(|(name, source)| vm_file(name, source))
+            .collect()
+    }
+}
+
+pub fn vm_file(name: &str, source: &str) -> VmFile {
+    VmFile {
+        name: name.to_string(),
+        path: format!("{}.vm", name),
+        commands: build_content(source.to_string()),
+    }
+}
+
+// translates `files` for Hack with the bootstrap code and runs them to the end
+pub fn run_on_hack(files: &[VmFile], cache_top: bool) -> Emulator {
+    run_program(
+        files,
+        &mut HackGenerator::new(DEFAULT_PROLOGUE_THRESHOLD, cache_top),
+    )
+}
+
+// Builds a C or assembly translation with the system compiler and runs it until its
+// step budget is spent, giving back the non-zero RAM words it prints. None when there
+// is no such compiler.
+pub fn run_native(
+    compiler: &str,
+    name: &str,
+    extension: &str,
+    output: &[String],
+) -> Option<HashMap<usize, i16>> {
+    let directory = env::temp_dir().join(format!("native_{}_{}", name, process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let source = directory.join(format!("{}.{}", name, extension));
+    let binary = directory.join(name);
+    fs::write(&source, output.join("\n")).unwrap();
+
+    let status = match Command::new(compiler)
+        .arg("-o")
+        .arg(&binary)
+        .arg(&source)
+        .status()
+    {
+        Ok(status) => status,
+        Err(_) => {
+            fs::remove_dir_all(&directory).unwrap();
+            return None;
+        }
+    };
+    assert!(status.success(), "{} failed on {}", compiler, name);
+
+    let run = Command::new(&binary).arg("100000").output().unwrap();
+    assert!(run.status.success(), "{} did not exit cleanly", name);
+    fs::remove_dir_all(&directory).unwrap();
+
+    // `address value` lines
+    let ram = String::from_utf8(run.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut parts = line.split(' ').map(|part| part.parse::<i32>().unwrap());
+            (parts.next().unwrap() as usize, parts.next().unwrap() as i16)
+        })
+        .collect();
+
+    Some(ram)
+}
+
+pub const PROGRAMS: &[TestProgram] = &[
+    TestProgram {
+        name: "arithmetic",
+        files: &[(
+            "Sys",
+            "
+            function Sys.init 0
+            push constant 3000
+            pop pointer 1
+            push constant 32767
+            push constant 1
+            add
+            pop temp 0
+            push constant 5
+            push constant 9
+            sub
+            pop temp 1
+            push constant 7
+            neg
+            pop temp 2
+            push constant 12
+            push constant 10
+            and
+            pop temp 3
+            push constant 12
+            push constant 10
+            or
+            pop temp 4
+            push constant 0
+            not
+            pop temp 5
+            push constant 3
+            push constant 3
+            eq
+            pop temp 6
+            push constant 2
+            push constant 3
+            gt
+            pop temp 7
+            push constant 2
+            push constant 3
+            lt
+            pop that 0
+            push constant 4
+            push constant 3
+            eq
+            pop that 1
+            label END
+            goto END
+            ",
+        )],
+        expected: &[
+            (5, -32768),
+            (6, -4),
+            (7, -7),
+            (8, 8),
+            (9, 14),
+            (10, -1),
+            (11, -1),
+            (12, 0),
+            (3000, -1),
+            (3001, 0),
+        ],
+    },
+    TestProgram {
+        name: "loop",
+        files: &[(
+            "Sys",
+            "
+            function Sys.init 2
+            push constant 1
+            pop local 1
+            label LOOP
+            push local 1
+            push constant 100
+            gt
+            if-goto DONE
+            push local 0
+            push local 1
+            add
+            pop local 0
+            push local 1
+            push constant 1
+            add
+            pop local 1
+            goto LOOP
+            label DONE
+            push local 0
+            pop temp 0
+            label END
+            goto END
+            ",
+        )],
+        expected: &[(5, 5050)],
+    },
+    TestProgram {
+        name: "recursion",
+        files: &[
+            (
+                "Sys",
+                "
+                function Sys.init 0
+                push constant 10
+                call Main.fibonacci 1
+                pop temp 0
+                label END
+                goto END
+                ",
+            ),
+            (
+                "Main",
+                "
+                function Main.fibonacci 0
+                push argument 0
+                push constant 2
+                lt
+                if-goto BASE
+                push argument 0
+                push constant 1
+                sub
+                call Main.fibonacci 1
+                push argument 0
+                push constant 2
+                sub
+                call Main.fibonacci 1
+                add
+                return
+                label BASE
+                push argument 0
+                return
+                ",
+            ),
+        ],
+        expected: &[(5, 55), (0, 261)],
+    },
+    TestProgram {
+        name: "segments",
+        files: &[
+            (
+                "Sys",
+                "
+                function Sys.init 0
+                push constant 3000
+                pop pointer 0
+                push constant 3010
+                pop pointer 1
+                push constant 11
+                pop this 2
+                push constant 22
+                pop that 5
+                push this 2
+                push that 5
+                add
+                pop temp 0
+                push constant 6
+                push constant 8
+                call Class1.set 2
+                pop temp 7
+                push constant 23
+                push constant 15
+                call Class2.set 2
+                pop temp 7
+                call Class1.get 0
+                pop temp 1
+                call Class2.get 0
+                pop temp 2
+                push pointer 0
+                push pointer 1
+                sub
+                pop temp 3
+                label END
+                goto END
+                ",
+            ),
+            (
+                "Class1",
+                "
+                function Class1.set 0
+                push argument 0
+                pop static 0
+                push argument 1
+                pop static 1
+                push constant 0
+                return
+                function Class1.get 0
+                push static 0
+                push static 1
+                sub
+                return
+                ",
+            ),
+            (
+                "Class2",
+                "
+                function Class2.set 0
+                push argument 0
+                pop static 0
+                push argument 1
+                pop static 1
+                push constant 0
+                return
+                function Class2.get 0
+                push static 0
+                push static 1
+                sub
+                return
+                ",
+            ),
+        ],
+        expected: &[
+            (3002, 11),
+            (3015, 22),
+            (5, 33),
+            (6, -2),
+            (7, 8),
+            (8, -10),
+            (3, 3000),
+            (4, 3010),
+        ],
+    },
+    TestProgram {
+        name: "extended arithmetic",
+        files: &[(
+            "Sys",
+            "
+            function Sys.init 0
+            push constant 4000
+            pop pointer 1
+            push constant 7
+            push constant 6
+            mul
+            pop that 0
+            push constant 7
+            neg
+            push constant 6
+            mul
+            pop that 1
+            push constant 7
+            neg
+            push constant 6
+            neg
+            mul
+            pop that 2
+            push constant 300
+            push constant 300
+            mul
+            pop that 3
+            push constant 32767
+            neg
+            push constant 1
+            sub
+            push constant 1
+            neg
+            mul
+            pop that 4
+            push constant 42
+            push constant 5
+            div
+            pop that 5
+            push constant 42
+            neg
+            push constant 5
+            div
+            pop that 6
+            push constant 42
+            push constant 5
+            neg
+            div
+            pop that 7
+            push constant 42
+            neg
+            push constant 5
+            neg
+            div
+            pop that 8
+            push constant 7
+            push constant 0
+            div
+            pop that 9
+            push constant 32767
+            neg
+            push constant 1
+            sub
+            push constant 1
+            neg
+            div
+            pop that 10
+            push constant 42
+            push constant 5
+            mod
+            pop that 11
+            push constant 42
+            neg
+            push constant 5
+            mod
+            pop that 12
+            push constant 42
+            push constant 5
+            neg
+            mod
+            pop that 13
+            push constant 42
+            neg
+            push constant 5
+            neg
+            mod
+            pop that 14
+            push constant 7
+            push constant 0
+            mod
+            pop that 15
+            push constant 1
+            push constant 15
+            shl
+            pop that 16
+            push constant 3
+            push constant 2
+            shl
+            pop that 17
+            push constant 5
+            push constant 16
+            shl
+            pop that 18
+            push constant 16
+            neg
+            push constant 2
+            shr
+            pop that 19
+            push constant 16
+            push constant 2
+            shr
+            pop that 20
+            push constant 32767
+            neg
+            push constant 1
+            sub
+            push constant 15
+            shr
+            pop that 21
+            push constant 12345
+            push constant 0
+            shr
+            pop that 22
+            push constant 12
+            push constant 10
+            xor
+            pop that 23
+            push constant 1
+            neg
+            push constant 5
+            xor
+            pop that 24
+            label END
+            goto END
+            ",
+        )],
+        expected: &[
+            (4000, 42),
+            (4001, -42),
+            (4002, 42),
+            (4003, 24464),
+            (4004, -32768),
+            (4005, 8),
+            (4006, -8),
+            (4007, -8),
+            (4008, 8),
+            (4009, 0),
+            (4010, -32768),
+            (4011, 2),
+            (4012, -2),
+            (4013, 2),
+            (4014, -2),
+            (4015, 7),
+            (4016, -32768),
+            (4017, 12),
+            (4018, 5),
+            (4019, -4),
+            (4020, 4),
+            (4021, -1),
+            (4022, 12345),
+            (4023, 6),
+            (4024, -6),
+        ],
+    },
+];
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use crate::program::{VmCommand, VmFile};
+
+// Binary form of parsed VM files:
+//
+//   magic "HVMB", version byte
+//   string table: count, then length + UTF-8 bytes for each string
+//   file count, then per file: name, path, command count and the commands
+//   command: opcode byte, operands, source line
+//
+// Numbers are unsigned LEB128 varints and names are indexes into the string table.
+// push/pop take a segment byte and an index, label/goto/if-goto a name, and
+// function/call a name and a count.
+
+const MAGIC: &[u8] = b"HVMB";
+const VERSION: u8 = 1;
+
+const OPCODES: [&str; 23] = [
+    "add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not", "mul", "div", "mod", "shl", "shr",
+    "xor", "push", "pop", "label", "goto", "if-goto", "function", "call", "return",
+];
+
+const SEGMENTS: [&str; 8] = [
+    "argument", "local", "static", "constant", "this", "that", "pointer", "temp",
+];
+
+pub fn write_vmb(files: &[VmFile]) -> Result<Vec<u8>, String> {
+    let mut strings = StringTable::default();
+    let mut body: Vec<u8> = Vec::new();
+
+    write_varint(&mut body, files.len());
+
+    for file in files {
+        write_varint(&mut body, strings.intern(&file.name));
+        write_varint(&mut body, strings.intern(&file.path));
+        write_varint(&mut body, file.commands.len());
+
+        for command in &file.commands {
+            write_command(&mut body, &mut strings, command)
+                .map_err(|message| format!("{}:{}: {}", file.path, command.line, message))?;
+        }
+    }
+
+    let mut result: Vec<u8> = MAGIC.to_vec();
+    result.push(VERSION);
+    write_varint(&mut result, strings.values.len());
+
+    for value in &strings.values {
+        write_varint(&mut result, value.len());
+        result.extend(value.as_bytes());
+    }
+
+    result.extend(body);
+    Ok(result)
+}
+
+pub fn read_vmb(bytes: &[u8]) -> Result<Vec<VmFile>, String> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(String::from("not a VM bytecode file"));
+    }
+
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(format!("unsupported VM bytecode version {}", version));
+    }
+
+    let mut strings: Vec<String> = Vec::new();
+    for _ in 0..reader.varint()? {
+        let length = reader.varint()?;
+        let value = std::str::from_utf8(reader.take(length)?)
+            .map_err(|_| String::from("string table entry is not UTF-8"))?;
+        strings.push(String::from(value));
+    }
+
+    let mut files: Vec<VmFile> = Vec::new();
+    for _ in 0..reader.varint()? {
+        let name = reader.string(&strings)?;
+        let path = reader.string(&strings)?;
+        let mut commands: Vec<VmCommand> = Vec::new();
+
+        for _ in 0..reader.varint()? {
+            commands.push(reader.command(&strings)?);
+        }
+
+        files.push(VmFile {
+            name,
+            path,
+            commands,
+        });
+    }
+
+    if reader.position != bytes.len() {
+        return Err(String::from("unexpected bytes after the last file"));
+    }
+
+    Ok(files)
+}
+
+fn write_command(
+    output: &mut Vec<u8>,
+    strings: &mut StringTable,
+    command: &VmCommand,
+) -> Result<(), String> {
+    let number = |value: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number '{}' in {}", value, command))
+    };
+
+    let opcode = OPCODES
+        .iter()
+        .position(|opcode| *opcode == command.command)
+        .ok_or_else(|| format!("unknown command {}", command))?;
+    output.push(opcode as u8);
+
+    match command.command.as_str() {
+        "push" | "pop" => {
+            let segment = SEGMENTS
+                .iter()
+                .position(|segment| *segment == command.detail)
+                .ok_or_else(|| format!("unknown segment in {}", command))?;
+
+            output.push(segment as u8);
+            write_varint(output, number(&command.value)?);
+        }
+        "label" | "goto" | "if-goto" => write_varint(output, strings.intern(&command.detail)),
+        "function" | "call" => {
+            write_varint(output, strings.intern(&command.detail));
+            write_varint(output, number(&command.value)?);
+        }
+        _ => (),
+    }
+
+    write_varint(output, command.line);
+    Ok(())
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    output.push(value as u8);
+}
+
+#[derive(Default)]
+struct StringTable {
+    values: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn intern(&mut self, value: &str) -> usize {
+        if let Some(index) = self.indexes.get(value) {
+            return *index;
+        }
+
+        self.values.push(String::from(value));
+        self.indexes
+            .insert(String::from(value), self.values.len() - 1);
+        self.values.len() - 1
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < length {
+            return Err(String::from("unexpected end of file"));
+        }
+
+        self.position += length;
+        Ok(&self.bytes[self.position - length..self.position])
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<usize, String> {
+        let mut value: usize = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+
+            if shift > 56 {
+                return Err(String::from("varint is too long"));
+            }
+
+            value |= ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self, strings: &[String]) -> Result<String, String> {
+        let index = self.varint()?;
+
+        match strings.get(index) {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!("string index {} is out of range", index)),
+        }
+    }
+
+    fn command(&mut self, strings: &[String]) -> Result<VmCommand, String> {
+        let opcode = self.byte()?;
+
+        let command = OPCODES
+            .get(opcode as usize)
+            .ok_or_else(|| format!("unknown opcode {}", opcode))?;
+
+        let (detail, value) = match *command {
+            "push" | "pop" => {
+                let segment = self.byte()?;
+                let segment = SEGMENTS
+                    .get(segment as usize)
+                    .ok_or_else(|| format!("unknown segment {}", segment))?;
+
+                (segment.to_string(), self.varint()?.to_string())
+            }
+            "label" | "goto" | "if-goto" => (self.string(strings)?, String::new()),
+            "function" | "call" => (self.string(strings)?, self.varint()?.to_string()),
+            _ => (String::new(), String::new()),
+        };
+
+        Ok(VmCommand {
+            command: command.to_string(),
+            detail,
+            value,
+            line: self.varint()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::build_content;
+
+    const SOURCE: &str = "// every opcode and segment
+function Main.main 2
+push constant 32767
+push argument 1
+push local 0
+push static 3
+push this 2
+push that 1
+push pointer 1
+push temp 7
+pop local 1
+add
+sub
+neg
+eq
+gt
+lt
+and
+or
+not
+mul
+div
+mod
+shl
+shr
+xor
+label LOOP
+if-goto LOOP
+goto END
+label END
+call Other.function 200
+return
+";
+
+    fn files() -> Vec<VmFile> {
+        vec![
+            VmFile {
+                name: String::from("Main"),
+                path: String::from("dir/Main.vm"),
+                commands: build_content(String::from(SOURCE)),
+            },
+            VmFile {
+                name: String::from("Empty"),
+                path: String::from("dir/Empty.vm"),
+                commands: Vec::new(),
+            },
+        ]
+    }
+
+    // name, path and the commands with their lines
+    type Text = (String, String, Vec<(String, usize)>);
+
+    fn text(files: &[VmFile]) -> Vec<Text> {
+        files
+            .iter()
+            .map(|file| {
+                (
+                    file.name.clone(),
+                    file.path.clone(),
+                    file.commands
+                        .iter()
+                        .map(|command| (command.to_string(), command.line))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn files_read_back_as_written() {
+        let files = files();
+        let bytes = write_vmb(&files).unwrap();
+
+        assert_eq!(&bytes[..5], b"HVMB\x01");
+        assert_eq!(text(&read_vmb(&bytes).unwrap()), text(&files));
+    }
+
+    #[test]
+    fn large_numbers_and_lines_round_trip() {
+        let mut files = files();
+        files[0].commands = vec![VmCommand::parse("function Main.big 1000000", 70000)];
+
+        let bytes = write_vmb(&files).unwrap();
+
+        assert_eq!(text(&read_vmb(&bytes).unwrap()), text(&files));
+    }
+
+    #[test]
+    fn commands_that_cannot_be_encoded_are_errors() {
+        let mut files = files();
+        files[0].commands = vec![VmCommand::parse("push nowhere 1", 4)];
+
+        assert_eq!(
+            write_vmb(&files).err().unwrap(),
+            "dir/Main.vm:4: unknown segment in push nowhere 1"
+        );
+    }
+
+    #[test]
+    fn damaged_files_are_errors() {
+        let bytes = write_vmb(&files()).unwrap();
+
+        assert_eq!(
+            read_vmb(b"HVMX\x01").err().unwrap(),
+            "not a VM bytecode file"
+        );
+        assert_eq!(
+            read_vmb(b"HVMB\x02").err().unwrap(),
+            "unsupported VM bytecode version 2"
+        );
+        assert_eq!(
+            read_vmb(&bytes[..bytes.len() - 1]).err().unwrap(),
+            "unexpected end of file"
+        );
+    }
+}
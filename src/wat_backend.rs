@@ -0,0 +1,524 @@
+use crate::codegen::{CodeGenerator, Statics};
+
+// a wasm function whose body is split in blocks at every VM label
+struct WatFunction {
+    name: String,
+    labels: Vec<String>,
+    blocks: Vec<Vec<String>>,
+}
+
+impl WatFunction {
+    fn new(name: &str) -> WatFunction {
+        WatFunction {
+            name: String::from(name),
+            labels: Vec::new(),
+            blocks: vec![Vec::new()],
+        }
+    }
+
+    // the block index doubles as the value of $pc that resumes at that label
+    fn block_of(&self, label: &str) -> usize {
+        self.labels
+            .iter()
+            .position(|name| name == label)
+            .map(|index| index + 1)
+            .unwrap_or_else(|| panic!("Undefined label {} in {}", label, self.name))
+    }
+
+    // jumps are written as {label} until every label of the function is known
+    fn resolve(&self, line: &str) -> String {
+        match (line.find('{'), line.find('}')) {
+            (Some(start), Some(end)) => format!(
+                "{}{}{}",
+                &line[..start],
+                self.block_of(&line[start + 1..end]),
+                &line[end + 1..]
+            ),
+            _ => String::from(line),
+        }
+    }
+
+    fn render(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        let count = self.blocks.len();
+
+        result.push(format!("  (func ${}", self.name));
+        result.push(String::from(
+            "    (local $pc i32) (local $x i32) (local $y i32) (local $address i32)",
+        ));
+
+        if count == 1 {
+            result.extend(
+                self.blocks[0]
+                    .iter()
+                    .map(|line| format!("    {}", self.resolve(line))),
+            );
+            result.push(String::from("  )"));
+            return result;
+        }
+
+        // goto sets $pc and branches back to $dispatch, which br_tables into the block
+        result.push(String::from("    (loop $dispatch"));
+
+        for index in (0..count).rev() {
+            result.push(format!("{}(block $B{}", indent(count - index), index));
+        }
+
+        let targets: Vec<String> = (0..count).map(|index| format!("$B{}", index)).collect();
+        result.push(format!(
+            "{}(br_table {} (local.get $pc))",
+            indent(count + 1),
+            targets.join(" ")
+        ));
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let depth = count - index;
+            result.push(format!("{})", indent(depth)));
+            result.extend(
+                block
+                    .iter()
+                    .map(|line| format!("{}{}", indent(depth), self.resolve(line))),
+            );
+        }
+
+        result.push(String::from("    )"));
+        result.push(String::from("  )"));
+
+        result
+    }
+}
+
+fn indent(depth: usize) -> String {
+    " ".repeat(4 + depth * 2)
+}
+
+pub struct WatGenerator {
+    result: Vec<String>,
+    filename: String,
+    statics: Statics,
+    return_points: usize,
+    top_level: Vec<String>,
+    current: WatFunction,
+}
+
+impl WatGenerator {
+    pub fn new() -> WatGenerator {
+        WatGenerator {
+            result: Vec::new(),
+            filename: String::new(),
+            statics: Statics::default(),
+            return_points: 0,
+            top_level: vec![String::from("run.bootstrap")],
+            current: WatFunction::new("run.bootstrap"),
+        }
+    }
+
+    fn line(&mut self, value: &str) {
+        self.current
+            .blocks
+            .last_mut()
+            .unwrap()
+            .push(String::from(value));
+    }
+
+    fn finish_function(&mut self) {
+        self.result.extend(self.current.render());
+    }
+
+    fn segment_address(&mut self, segment: &str, index: &str) -> String {
+        let base = match segment {
+            "local" => 1,
+            "argument" => 2,
+            "this" => 3,
+            "that" => 4,
+            "temp" => return format!("(i32.const {})", 5 + parse_index(index)),
+            "pointer" => return format!("(i32.const {})", 3 + parse_index(index)),
+            "static" => {
+                let address = self.statics.address(&self.filename, index);
+                return format!("(i32.const {})", address);
+            }
+            _ => panic!("Invalid memory location! {}", segment),
+        };
+
+        format!(
+            "(i32.add (call $load (i32.const {})) (i32.const {}))",
+            base, index
+        )
+    }
+
+    fn jump_to(&self, label: &str) -> String {
+        format!("(local.set $pc (i32.const {{{}}})) (br $dispatch)", label)
+    }
+}
+
+impl CodeGenerator for WatGenerator {
+    fn extension(&self) -> &'static str {
+        "wat"
+    }
+
+    fn prologue(&mut self) {
+        let header = [
+            ";; Generated by hack_vm_translator: Hack VM program as a WebAssembly text module.",
+            ";; RAM word n lives at byte 2n of `memory` (screen at word 16384, keyboard at 24576).",
+            ";; `run` takes a step budget (0 = unlimited) and traps with unreachable once it is spent.",
+            "(module",
+            "  (memory (export \"memory\") 1)",
+            "  (global $fuel (mut i32) (i32.const 0))",
+            "",
+            "  (func $load (param $address i32) (result i32)",
+            "    (i32.load16_s (i32.shl (i32.and (local.get $address) (i32.const 32767)) (i32.const 1))))",
+            "  (func $store (param $address i32) (param $value i32)",
+            "    (i32.store16 (i32.shl (i32.and (local.get $address) (i32.const 32767)) (i32.const 1)) (local.get $value)))",
+            "  ;; 16-bit two's complement wraparound, like the Hack ALU",
+            "  (func $wrap (param $value i32) (result i32)",
+            "    (i32.shr_s (i32.shl (local.get $value) (i32.const 16)) (i32.const 16)))",
+            "  (func $push (param $value i32)",
+            "    (call $store (call $load (i32.const 0)) (local.get $value))",
+            "    (call $store (i32.const 0) (i32.add (call $load (i32.const 0)) (i32.const 1))))",
+            "  (func $pop (result i32)",
+            "    (call $store (i32.const 0) (i32.sub (call $load (i32.const 0)) (i32.const 1)))",
+            "    (call $load (call $load (i32.const 0))))",
+            "  (func $tick",
+            "    (if (global.get $fuel) (then",
+            "      (global.set $fuel (i32.sub (global.get $fuel) (i32.const 1)))",
+            "      (if (i32.eqz (global.get $fuel)) (then (unreachable))))))",
+            "",
+        ];
+
+        self.result
+            .extend(header.iter().map(|line| line.to_string()));
+    }
+
+    fn bootstrap(&mut self) {
+        self.line("(call $store (i32.const 0) (i32.const 256))");
+        self.call("Sys.init", "0");
+    }
+
+    // code outside functions runs from `run`, one wasm function per file
+    fn file(&mut self, filename: &str) {
+        self.finish_function();
+        self.filename = String::from(filename);
+        self.current = WatFunction::new(&format!("top.{}", filename));
+        self.top_level.push(self.current.name.clone());
+    }
+
+    fn comment(&mut self, value: &str) {
+        self.line(&format!(";; {}", value));
+    }
+
+    fn epilogue(&mut self) -> Vec<String> {
+        self.finish_function();
+
+        self.result.push(String::new());
+        self.result
+            .push(String::from("  (func (export \"run\") (param $steps i32)"));
+        self.result
+            .push(String::from("    (global.set $fuel (local.get $steps))"));
+
+        for name in &self.top_level {
+            self.result.push(format!("    (call ${})", name));
+        }
+
+        self.result.push(String::from("  )"));
+        self.result.push(String::from(")"));
+
+        std::mem::take(&mut self.result)
+    }
+
+    fn push(&mut self, segment: &str, index: &str) {
+        if segment == "constant" {
+            self.line(&format!("(call $push (i32.const {}))", index));
+            return;
+        }
+
+        let address = self.segment_address(segment, index);
+        self.line(&format!("(call $push (call $load {}))", address));
+    }
+
+    fn pop(&mut self, segment: &str, index: &str) {
+        let address = self.segment_address(segment, index);
+        self.line(&format!("(local.set $address {})", address));
+        self.line("(call $store (local.get $address) (call $pop))");
+    }
+
+    // comparisons use the wrapped difference, exactly like D=M-D on Hack; division
+    // is guarded since i32.div_s and i32.rem_s trap on zero
+    fn arithmetic(&mut self, operation: &str) {
+        let expression = match operation {
+            "neg" => "(i32.sub (i32.const 0) (local.get $x))",
+            "not" => "(i32.xor (local.get $x) (i32.const -1))",
+            "add" => "(i32.add (local.get $x) (local.get $y))",
+            "sub" => "(i32.sub (local.get $x) (local.get $y))",
+            "and" => "(i32.and (local.get $x) (local.get $y))",
+            "or" => "(i32.or (local.get $x) (local.get $y))",
+            "eq" => "(i32.sub (i32.const 0) (i32.eqz (call $wrap (i32.sub (local.get $x) (local.get $y)))))",
+            "gt" => "(i32.sub (i32.const 0) (i32.gt_s (call $wrap (i32.sub (local.get $x) (local.get $y))) (i32.const 0)))",
+            "lt" => "(i32.sub (i32.const 0) (i32.lt_s (call $wrap (i32.sub (local.get $x) (local.get $y))) (i32.const 0)))",
+            "mul" => "(i32.mul (local.get $x) (local.get $y))",
+            "div" => "(if (result i32) (local.get $y) (then (i32.div_s (local.get $x) (local.get $y))) (else (i32.const 0)))",
+            "mod" => "(if (result i32) (local.get $y) (then (i32.rem_s (local.get $x) (local.get $y))) (else (local.get $x)))",
+            "shl" => "(i32.shl (local.get $x) (i32.and (local.get $y) (i32.const 15)))",
+            "shr" => "(i32.shr_s (local.get $x) (i32.and (local.get $y) (i32.const 15)))",
+            "xor" => "(i32.xor (local.get $x) (local.get $y))",
+            _ => panic!("Invalid action required! {}", operation),
+        };
+
+        if operation != "neg" && operation != "not" {
+            self.line("(local.set $y (call $pop))");
+        }
+
+        self.line("(local.set $x (call $pop))");
+        self.line(&format!("(call $push {})", expression));
+    }
+
+    fn label(&mut self, label: &str) {
+        self.current.labels.push(String::from(label));
+        self.current.blocks.push(Vec::new());
+        self.line("(call $tick)");
+    }
+
+    fn goto(&mut self, label: &str) {
+        let jump = self.jump_to(label);
+        self.line(&jump);
+    }
+
+    fn if_goto(&mut self, label: &str) {
+        let jump = self.jump_to(label);
+        self.line(&format!("(if (call $pop) (then {}))", jump));
+    }
+
+    fn function(&mut self, name: &str, locals: &str) {
+        // the `;; function` comment goes between the two funcs, not at the end of the last one
+        let comment = format!(";; function {} {}", name, locals);
+        let block = self.current.blocks.last_mut().unwrap();
+        let header = if block.last() == Some(&comment) {
+            block.pop()
+        } else {
+            None
+        };
+
+        self.finish_function();
+        self.result
+            .extend(header.map(|comment| format!("  {}", comment)));
+        self.current = WatFunction::new(name);
+        self.line("(call $tick)");
+
+        for _ in 0..parse_index(locals) {
+            self.line("(call $push (i32.const 0))");
+        }
+    }
+
+    // the wasm call stack does the actual returning
+    fn call(&mut self, name: &str, arguments: &str) {
+        self.return_points += 1;
+        let point = self.return_points;
+
+        self.line(&format!("(call $push (i32.const {}))", point));
+
+        for pointer in 1..=4 {
+            self.line(&format!(
+                "(call $push (call $load (i32.const {})))",
+                pointer
+            ));
+        }
+
+        self.line(&format!(
+            "(call $store (i32.const 2) (i32.sub (call $load (i32.const 0)) (i32.const {})))",
+            parse_index(arguments) + 5
+        ));
+        self.line("(call $store (i32.const 1) (call $load (i32.const 0)))");
+        self.line(&format!("(call ${})", name));
+    }
+
+    fn return_(&mut self) {
+        let lines = [
+            "(call $store (i32.const 13) (call $pop))",
+            "(call $store (i32.const 15) (call $load (i32.const 2)))",
+            "(call $store (i32.const 0) (call $load (i32.const 1)))",
+            "(call $store (i32.const 4) (call $pop))",
+            "(call $store (i32.const 3) (call $pop))",
+            "(call $store (i32.const 2) (call $pop))",
+            "(call $store (i32.const 1) (call $pop))",
+            "(call $store (i32.const 14) (call $pop))",
+            "(call $store (i32.const 0) (call $load (i32.const 15)))",
+            "(call $push (call $load (i32.const 13)))",
+            "(return)",
+        ];
+
+        for line in lines.iter() {
+            self.line(line);
+        }
+    }
+}
+
+fn parse_index(value: &str) -> usize {
+    value.parse().expect("Invalid index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::generate;
+    use crate::test_programs::vm_file;
+
+    fn translate(source: &str) -> Vec<String> {
+        generate(&mut WatGenerator::new(), &[vm_file("Main", source)], true)
+    }
+
+    // the nesting depth after every line, comments left out
+    fn depths(output: &[String]) -> Vec<i32> {
+        let mut depth = 0;
+
+        output
+            .iter()
+            .map(|line| {
+                for character in line.split(";;").next().unwrap().chars() {
+                    match character {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => (),
+                    }
+                }
+                depth
+            })
+            .collect()
+    }
+
+    fn function<'a>(output: &'a [String], name: &str) -> &'a [String] {
+        let header = format!("  (func ${}", name);
+        let start = output
+            .iter()
+            .position(|line| *line == header)
+            .unwrap_or_else(|| panic!("no function {}", name));
+        let end = start
+            + output[start..]
+                .iter()
+                .position(|line| line == "  )")
+                .unwrap();
+
+        &output[start..=end]
+    }
+
+    fn exported_run(output: &[String]) -> &[String] {
+        let start = output
+            .iter()
+            .position(|line| line == "  (func (export \"run\") (param $steps i32)")
+            .unwrap();
+
+        &output[start..]
+    }
+
+    const PROGRAM: &str = "
+        function Sys.init 0
+        push constant 2
+        call Main.double 1
+        pop static 0
+        label END
+        goto END
+        function Main.double 0
+        push argument 0
+        push argument 0
+        add
+        return
+    ";
+
+    #[test]
+    fn output_is_one_balanced_module() {
+        let output = translate(PROGRAM);
+        let depths = depths(&output);
+
+        assert!(depths.iter().all(|depth| *depth >= 0));
+        assert_eq!(depths.last(), Some(&0));
+
+        let module = output.iter().position(|line| line == "(module").unwrap();
+        assert!(depths[module..depths.len() - 1]
+            .iter()
+            .all(|depth| *depth >= 1));
+        assert_eq!(output.last().unwrap(), ")");
+    }
+
+    #[test]
+    fn exports_memory_and_run() {
+        let output = translate(PROGRAM);
+
+        assert!(output.contains(&String::from("  (memory (export \"memory\") 1)")));
+
+        let run = exported_run(&output);
+        assert_eq!(run[1], "    (global.set $fuel (local.get $steps))");
+        assert_eq!(run[2], "    (call $run.bootstrap)");
+        assert_eq!(run[3], "    (call $top.Main)");
+    }
+
+    #[test]
+    fn every_vm_function_is_a_wasm_function_called_directly() {
+        let output = translate(PROGRAM);
+
+        let bootstrap = function(&output, "run.bootstrap");
+        assert!(bootstrap.contains(&String::from("    (call $Sys.init)")));
+
+        let init = function(&output, "Sys.init");
+        assert!(init.iter().any(|line| line.trim() == "(call $Main.double)"));
+
+        let double = function(&output, "Main.double");
+        assert_eq!(
+            double
+                .iter()
+                .filter(|line| line.contains("(return)"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn labels_split_the_body_in_blocks_dispatched_by_br_table() {
+        let output = translate(PROGRAM);
+        let init = function(&output, "Sys.init");
+
+        assert_eq!(init[2], "    (loop $dispatch");
+        assert!(init
+            .iter()
+            .any(|line| line.trim() == "(br_table $B0 $B1 (local.get $pc))"));
+        // goto END resumes at the block after the label
+        assert!(init
+            .iter()
+            .any(|line| line.trim() == "(local.set $pc (i32.const 1)) (br $dispatch)"));
+    }
+
+    #[test]
+    fn function_comments_come_after_the_previous_function_closes() {
+        let output = translate(PROGRAM);
+        let header = output
+            .iter()
+            .position(|line| line == "  (func $Main.double")
+            .unwrap();
+
+        assert_eq!(output[header - 2], "  )");
+        assert_eq!(output[header - 1], "  ;; function Main.double 0");
+    }
+
+    #[test]
+    fn functions_without_labels_have_no_dispatch_loop() {
+        let output = translate(PROGRAM);
+        let double = function(&output, "Main.double");
+
+        assert!(!double.iter().any(|line| line.contains("$dispatch")));
+    }
+
+    #[test]
+    fn if_goto_branches_on_the_popped_value() {
+        let output = translate(
+            "
+            function Main.main 0
+            label LOOP
+            push constant 0
+            if-goto LOOP
+            push constant 0
+            return
+            ",
+        );
+        let main = function(&output, "Main.main");
+
+        assert!(main.iter().any(|line| line.trim()
+            == "(if (call $pop) (then (local.set $pc (i32.const 1)) (br $dispatch)))"));
+    }
+}
@@ -0,0 +1,494 @@
+use crate::codegen::{CodeGenerator, Statics};
+use crate::program::mangle;
+
+// %rbx holds the address of RAM[0] and %r12 the remaining step budget
+pub struct X86Generator {
+    result: Vec<String>,
+    filename: String,
+    function_name: String,
+    statics: Statics,
+    return_points: usize,
+}
+
+impl X86Generator {
+    pub fn new() -> X86Generator {
+        X86Generator {
+            result: Vec::new(),
+            filename: String::new(),
+            function_name: String::new(),
+            statics: Statics::default(),
+            return_points: 0,
+        }
+    }
+
+    fn line(&mut self, value: &str) {
+        self.result.push(format!("    {}", value));
+    }
+
+    // leaves the RAM address in %ecx
+    fn segment_address(&mut self, segment: &str, index: &str) {
+        let base = match segment {
+            "local" => 1,
+            "argument" => 2,
+            "this" => 3,
+            "that" => 4,
+            "temp" => {
+                let address = 5 + parse_index(index);
+                return self.line(&format!("movl ${}, %ecx", address));
+            }
+            "pointer" => {
+                let address = 3 + parse_index(index);
+                return self.line(&format!("movl ${}, %ecx", address));
+            }
+            "static" => {
+                let address = self.statics.address(&self.filename, index);
+                return self.line(&format!("movl ${}, %ecx", address));
+            }
+            _ => panic!("Invalid memory location! {}", segment),
+        };
+
+        self.line(&format!("movzwl {}(%rbx), %ecx", base * 2));
+        self.line(&format!("addl ${}, %ecx", index));
+        self.line("andl $0x7FFF, %ecx");
+    }
+
+    fn build_label(&self, label: &str) -> String {
+        format!(".Lvm_{}_{}", mangle(&self.function_name), mangle(label))
+    }
+}
+
+impl CodeGenerator for X86Generator {
+    fn extension(&self) -> &'static str {
+        "s"
+    }
+
+    fn prologue(&mut self) {
+        let header = [
+            "# Generated by hack_vm_translator: Hack VM program as GNU as x86-64 assembly.",
+            "# build: gcc -o program program.s",
+            "# usage: ./program [max-steps] [address=value ...]",
+            "# prints every non-zero RAM word as `address value` once the program stops.",
+            "    .local ram",
+            "    .comm ram, 65536, 16",
+            "",
+            "    .section .rodata",
+            "dump_format:",
+            "    .string \"%d %d\\n\"",
+            "",
+            "# RAM[SP++] = %ax",
+            "    .macro vmpush",
+            "    movzwl (%rbx), %ecx",
+            "    andl $0x7FFF, %ecx",
+            "    movw %ax, (%rbx,%rcx,2)",
+            "    incw (%rbx)",
+            "    .endm",
+            "",
+            "# %ax = RAM[--SP]",
+            "    .macro vmpop",
+            "    decw (%rbx)",
+            "    movzwl (%rbx), %ecx",
+            "    andl $0x7FFF, %ecx",
+            "    movw (%rbx,%rcx,2), %ax",
+            "    .endm",
+            "",
+            "    .macro tick",
+            "    decq %r12",
+            "    jz vm_halt",
+            "    .endm",
+            "",
+            "    .text 2",
+            "    .globl main",
+            "main:",
+            "    pushq %rbx",
+            "    pushq %r12",
+            "    pushq %r13",
+            "    leaq ram(%rip), %rbx",
+            "    movq $-1, %r12",
+            "    leaq 8(%rsi), %r13",
+            "1:",
+            "    movq (%r13), %rdi",
+            "    testq %rdi, %rdi",
+            "    jz 5f",
+            "    movl $61, %esi",
+            "    call strchr@PLT",
+            "    testq %rax, %rax",
+            "    jz 4f",
+            "    leaq 1(%rax), %rdi",
+            "    call atoi@PLT",
+            "    pushq %rax",
+            "    pushq %rax",
+            "    movq (%r13), %rdi",
+            "    call atoi@PLT",
+            "    andl $0x7FFF, %eax",
+            "    popq %rdx",
+            "    popq %rdx",
+            "    movw %dx, (%rbx,%rax,2)",
+            "    jmp 6f",
+            "4:",
+            "    movq (%r13), %rdi",
+            "    call atol@PLT",
+            "    testq %rax, %rax",
+            "    jz 6f",
+            "    movq %rax, %r12",
+            "6:",
+            "    addq $8, %r13",
+            "    jmp 1b",
+            "5:",
+            "    call vm_run",
+            "vm_halt:",
+            "    andq $-16, %rsp",
+            "    xorl %r13d, %r13d",
+            "2:",
+            "    movswl (%rbx,%r13,2), %edx",
+            "    testl %edx, %edx",
+            "    jz 3f",
+            "    leaq dump_format(%rip), %rdi",
+            "    movl %r13d, %esi",
+            "    xorl %eax, %eax",
+            "    call printf@PLT",
+            "3:",
+            "    incq %r13",
+            "    cmpq $32768, %r13",
+            "    jl 2b",
+            "    xorl %edi, %edi",
+            "    call exit@PLT",
+            "",
+            "# code outside functions goes to subsection 0, functions to subsection 1",
+            "    .text 0",
+            "vm_run:",
+        ];
+
+        self.result
+            .extend(header.iter().map(|line| line.to_string()));
+    }
+
+    fn bootstrap(&mut self) {
+        self.comment("bootstrap");
+        self.line("movw $256, (%rbx)");
+        self.call("Sys.init", "0");
+    }
+
+    fn file(&mut self, filename: &str) {
+        self.filename = String::from(filename);
+        self.function_name = String::new();
+        self.result.push(format!("# New file: {}", filename));
+        self.line(".text 0");
+    }
+
+    fn comment(&mut self, value: &str) {
+        self.line(&format!("# {}", value));
+    }
+
+    fn epilogue(&mut self) -> Vec<String> {
+        self.line(".text 0");
+        self.line("ret");
+        self.line(".section .note.GNU-stack,\"\",@progbits");
+        self.result.push(String::new());
+
+        std::mem::take(&mut self.result)
+    }
+
+    fn push(&mut self, segment: &str, index: &str) {
+        if segment == "constant" {
+            self.line(&format!("movw ${}, %ax", index));
+        } else {
+            self.segment_address(segment, index);
+            self.line("movw (%rbx,%rcx,2), %ax");
+        }
+
+        self.line("vmpush");
+    }
+
+    fn pop(&mut self, segment: &str, index: &str) {
+        self.segment_address(segment, index);
+        self.line("movl %ecx, %edx");
+        self.line("vmpop");
+        self.line("movw %ax, (%rbx,%rdx,2)");
+    }
+
+    // x is in %ax and y in %dx; comparisons test the wrapped difference like D=M-D on Hack.
+    // Division is done on 32 bits so -32768 / -1 wraps instead of faulting
+    fn arithmetic(&mut self, operation: &str) {
+        let instructions: &[&str] = match operation {
+            "neg" => &["negw %ax"],
+            "not" => &["notw %ax"],
+            "add" => &["addw %dx, %ax"],
+            "sub" => &["subw %dx, %ax"],
+            "and" => &["andw %dx, %ax"],
+            "or" => &["orw %dx, %ax"],
+            "eq" => &["subw %dx, %ax", "sete %al", "movzbw %al, %ax", "negw %ax"],
+            "gt" => &[
+                "subw %dx, %ax",
+                "testw %ax, %ax",
+                "setg %al",
+                "movzbw %al, %ax",
+                "negw %ax",
+            ],
+            "lt" => &["subw %dx, %ax", "sets %al", "movzbw %al, %ax", "negw %ax"],
+            "xor" => &["xorw %dx, %ax"],
+            "mul" => &["imulw %dx, %ax"],
+            "div" => &[
+                "movswl %ax, %eax",
+                "movswl %dx, %ecx",
+                "testl %ecx, %ecx",
+                "jz 1f",
+                "cltd",
+                "idivl %ecx",
+                "jmp 2f",
+                "1:",
+                "xorl %eax, %eax",
+                "2:",
+            ],
+            "mod" => &[
+                "movswl %ax, %eax",
+                "movswl %dx, %ecx",
+                "testl %ecx, %ecx",
+                "jz 1f",
+                "cltd",
+                "idivl %ecx",
+                "movl %edx, %eax",
+                "1:",
+            ],
+            "shl" => &["movl %edx, %ecx", "andb $15, %cl", "shlw %cl, %ax"],
+            "shr" => &["movl %edx, %ecx", "andb $15, %cl", "sarw %cl, %ax"],
+            _ => panic!("Invalid action required! {}", operation),
+        };
+
+        if operation != "neg" && operation != "not" {
+            self.line("vmpop");
+            self.line("movw %ax, %dx");
+        }
+
+        self.line("vmpop");
+
+        for instruction in instructions {
+            self.line(instruction);
+        }
+
+        self.line("vmpush");
+    }
+
+    fn label(&mut self, label: &str) {
+        self.result.push(format!("{}:", self.build_label(label)));
+        self.line("tick");
+    }
+
+    fn goto(&mut self, label: &str) {
+        let target = self.build_label(label);
+        self.line(&format!("jmp {}", target));
+    }
+
+    fn if_goto(&mut self, label: &str) {
+        let target = self.build_label(label);
+        self.line("vmpop");
+        self.line("testw %ax, %ax");
+        self.line(&format!("jnz {}", target));
+    }
+
+    fn function(&mut self, name: &str, locals: &str) {
+        self.function_name = String::from(name);
+        self.line(".text 1");
+        self.result.push(format!("vm_{}:", mangle(name)));
+        self.line("tick");
+
+        for _ in 0..parse_index(locals) {
+            self.line("xorl %eax, %eax");
+            self.line("vmpush");
+        }
+    }
+
+    // the native call stack does the actual returning
+    fn call(&mut self, name: &str, arguments: &str) {
+        self.return_points += 1;
+
+        self.line(&format!("movw ${}, %ax", self.return_points));
+        self.line("vmpush");
+
+        for pointer in 1..=4 {
+            self.line(&format!("movw {}(%rbx), %ax", pointer * 2));
+            self.line("vmpush");
+        }
+
+        self.line("movw (%rbx), %ax");
+        self.line(&format!("subw ${}, %ax", parse_index(arguments) + 5));
+        self.line("movw %ax, 4(%rbx)");
+        self.line("movw (%rbx), %ax");
+        self.line("movw %ax, 2(%rbx)");
+        self.line(&format!("call vm_{}", mangle(name)));
+    }
+
+    fn return_(&mut self) {
+        let lines = [
+            "vmpop",
+            "movw %ax, 26(%rbx)",
+            "movw 4(%rbx), %ax",
+            "movw %ax, 30(%rbx)",
+            "movw 2(%rbx), %ax",
+            "movw %ax, (%rbx)",
+            "vmpop",
+            "movw %ax, 8(%rbx)",
+            "vmpop",
+            "movw %ax, 6(%rbx)",
+            "vmpop",
+            "movw %ax, 4(%rbx)",
+            "vmpop",
+            "movw %ax, 2(%rbx)",
+            "vmpop",
+            "movw %ax, 28(%rbx)",
+            "movw 30(%rbx), %ax",
+            "movw %ax, (%rbx)",
+            "movw 26(%rbx), %ax",
+            "vmpush",
+            "ret",
+        ];
+
+        for line in lines.iter() {
+            self.line(line);
+        }
+    }
+}
+
+fn parse_index(value: &str) -> usize {
+    value.parse().expect("Invalid index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::generate;
+    use crate::test_programs::{run_native, vm_file, PROGRAMS};
+
+    const SOURCE: &str = "function Main.main 0\npush constant 1\npush constant 2\ncall Main.add 2\nreturn\nfunction Main.add 0\npush argument 0\npush argument 1\nadd\nreturn\n";
+
+    fn translate(source: &str) -> Vec<String> {
+        generate(&mut X86Generator::new(), &[vm_file("Main", source)], true)
+    }
+
+    #[test]
+    fn every_function_has_exactly_one_label() {
+        let output = translate(SOURCE);
+
+        for name in ["Sys.init", "Main.main", "Main.add"].iter() {
+            let label = format!("vm_{}:", mangle(name));
+            let count = output.iter().filter(|line| **line == label).count();
+
+            // Sys.init is called by the bootstrap but not defined by the program
+            let expected = if *name == "Sys.init" { 0 } else { 1 };
+            assert_eq!(count, expected, "labels of {}", name);
+        }
+
+        for line in &output {
+            if let Some(target) = line.trim().strip_prefix("call vm_") {
+                if target != "Sys_2Einit" {
+                    assert!(output.contains(&format!("vm_{}:", target)), "{}", line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_call_pushes_the_frame_of_call_action() {
+        let output = translate(SOURCE);
+        let call = output
+            .iter()
+            .position(|line| line.trim() == "call vm_Main_2Eadd")
+            .unwrap();
+
+        let frame: Vec<&str> = output[call - 15..=call]
+            .iter()
+            .map(|line| line.trim())
+            .collect();
+
+        assert_eq!(
+            frame,
+            [
+                // the return point, then LCL, ARG, THIS and THAT
+                "movw $2, %ax",
+                "vmpush",
+                "movw 2(%rbx), %ax",
+                "vmpush",
+                "movw 4(%rbx), %ax",
+                "vmpush",
+                "movw 6(%rbx), %ax",
+                "vmpush",
+                "movw 8(%rbx), %ax",
+                "vmpush",
+                // ARG = SP - 2 - 5 and LCL = SP
+                "movw (%rbx), %ax",
+                "subw $7, %ax",
+                "movw %ax, 4(%rbx)",
+                "movw (%rbx), %ax",
+                "movw %ax, 2(%rbx)",
+                "call vm_Main_2Eadd",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_return_restores_the_frame_and_leaves_the_result_at_arg() {
+        let output = translate(SOURCE);
+        let start = output
+            .iter()
+            .position(|line| line == "vm_Main_2Eadd:")
+            .unwrap();
+        let body: Vec<&str> = output[start..].iter().map(|line| line.trim()).collect();
+        let end = body.iter().position(|line| *line == "ret").unwrap();
+
+        assert_eq!(
+            body[end - 20..=end],
+            [
+                // RAM[13] = result and RAM[15] = ARG, then SP = LCL
+                "vmpop",
+                "movw %ax, 26(%rbx)",
+                "movw 4(%rbx), %ax",
+                "movw %ax, 30(%rbx)",
+                "movw 2(%rbx), %ax",
+                "movw %ax, (%rbx)",
+                // THAT, THIS, ARG, LCL and the return point into RAM[14]
+                "vmpop",
+                "movw %ax, 8(%rbx)",
+                "vmpop",
+                "movw %ax, 6(%rbx)",
+                "vmpop",
+                "movw %ax, 4(%rbx)",
+                "vmpop",
+                "movw %ax, 2(%rbx)",
+                "vmpop",
+                "movw %ax, 28(%rbx)",
+                // SP = old ARG, then the result is pushed there
+                "movw 30(%rbx), %ax",
+                "movw %ax, (%rbx)",
+                "movw 26(%rbx), %ax",
+                "vmpush",
+                "ret",
+            ]
+        );
+    }
+
+    // skipped when there is no gcc to assemble and link with
+    #[test]
+    fn assembled_test_programs_leave_the_expected_ram() {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return;
+        }
+
+        for program in PROGRAMS {
+            let output = generate(&mut X86Generator::new(), &program.vm_files(), true);
+
+            let ram = match run_native("gcc", program.name, "s", &output) {
+                Some(ram) => ram,
+                None => return eprintln!("no gcc, skipping the assembled test programs"),
+            };
+
+            for (address, expected) in program.expected {
+                assert_eq!(
+                    ram.get(address).copied().unwrap_or(0),
+                    *expected,
+                    "RAM[{}] after {}",
+                    address,
+                    program.name
+                );
+            }
+        }
+    }
+}